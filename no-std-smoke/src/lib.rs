@@ -0,0 +1,66 @@
+// Copyright 2020 Google LLC
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Exercises robust-binary-search from a `#![no_std]` crate, so that building this crate checks that
+//! the core search types don't depend on std.
+
+#![no_std]
+
+use robust_binary_search::search_linear;
+use robust_binary_search::AutoSearcher;
+use robust_binary_search::Searcher;
+
+/// Searches `len` indices for the first bad one, where every index at or after `transition` is bad.
+pub fn find_transition(len: usize, transition: usize) -> usize {
+    search_linear(len, 0.99, Some(1000), |index| index >= transition)
+}
+
+/// Reports every index in order, then returns the best index and the estimated flakiness.
+pub fn report_all(len: usize, transition: usize) -> (usize, f64) {
+    let mut searcher = AutoSearcher::new(len);
+    for index in 0..len {
+        searcher.report(index, index >= transition);
+    }
+    (searcher.best_index(), searcher.flakiness())
+}
+
+/// Reports a single vote with the given flakiness and returns the best index.
+pub fn report_once(len: usize, index: usize, heads: bool, flakiness: f64) -> usize {
+    let mut searcher = Searcher::new(len);
+    searcher.report(index, heads, flakiness);
+    searcher.best_index()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn find_transition_works() {
+        assert_eq!(find_transition(100, 37), 37);
+    }
+
+    #[test]
+    fn report_all_works() {
+        let (best, flakiness) = report_all(20, 5);
+        assert_eq!(best, 5);
+        assert!(flakiness < 0.5, "flakiness = {}", flakiness);
+    }
+
+    #[test]
+    fn report_once_works() {
+        assert!(report_once(10, 4, true, 0.01) <= 4);
+        assert!(report_once(10, 4, false, 0.01) > 4);
+    }
+}
@@ -15,8 +15,9 @@
 use lazy_static::lazy_static;
 use log::info;
 use rand;
-use rand::rngs::ThreadRng;
+use rand::rngs::StdRng;
 use rand::Rng;
+use rand::SeedableRng;
 use regex::Regex;
 use simplelog::Config;
 use simplelog::LevelFilter;
@@ -105,7 +106,7 @@ fn git_is_ancestor<P: AsRef<Path>>(dir: P, r1: &str, r2: &str) -> bool {
 
 struct CommitTester {
     target_commit: String,
-    rng: ThreadRng,
+    rng: StdRng,
     flakiness: f64,
 }
 
@@ -217,10 +218,25 @@ fn run_robust_bisect<P: AsRef<Path>>(
     Ok((iterations, best_commit == Some(target_commit.to_string())))
 }
 
+/// Removes `--seed <n>` from the arguments, if present, and returns a random number generator
+/// seeded with it, so that runs can be reproduced. Without `--seed`, the generator is seeded from
+/// the operating system.
+fn rng_from_args(args: &mut Vec<String>) -> Result<StdRng, Box<dyn Error>> {
+    match args.iter().position(|arg| arg == "--seed") {
+        None => Ok(StdRng::from_entropy()),
+        Some(i) => {
+            let seed = args.get(i + 1).ok_or("--seed requires a value")?.parse()?;
+            args.drain(i..i + 2);
+            Ok(StdRng::seed_from_u64(seed))
+        }
+    }
+}
+
 fn main() -> Result<(), Box<dyn Error>> {
-    let args: Vec<String> = env::args().collect();
+    let mut args: Vec<String> = env::args().collect();
+    let rng = rng_from_args(&mut args)?;
     if args.len() != 5 {
-        println!("Usage: main <dir> <output_file> <bisect> <test_commit_script>");
+        println!("Usage: main [--seed <seed>] <dir> <output_file> <bisect> <test_commit_script>");
         process::exit(1);
     }
     TermLogger::init(LevelFilter::Info, Config::default(), TerminalMode::Mixed).unwrap();
@@ -235,7 +251,7 @@ fn main() -> Result<(), Box<dyn Error>> {
     let flakiness = 0.1;
     let mut commit_tester = CommitTester {
         target_commit: target_commit.to_string(),
-        rng: rand::thread_rng(),
+        rng,
         flakiness,
     };
     loop {
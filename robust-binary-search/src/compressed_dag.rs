@@ -0,0 +1,961 @@
+// Copyright 2020 Google LLC
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use crate::CompressedDag;
+use crate::CompressedDagNodeRef;
+use crate::CompressedDagSegment;
+use crate::Dag;
+use crate::DagError;
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
+use std::fmt::Write;
+use std::hash::Hash;
+
+/// Extra styling for a segment in `CompressedDag::to_dot_with`.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct DotStyle {
+    /// Additional line appended to the segment's label.
+    pub label: Option<String>,
+    /// Graphviz color of the segment, e.g. `"red"` or `"#ff0000"`.
+    pub color: Option<String>,
+}
+
+/// Escapes a string for use inside a quoted DOT string.
+fn escape_dot(s: &str) -> String {
+    s.replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+/// Error returned by `CompressedDagBuilder::build`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BuildError {
+    /// The segment at the given index has length zero.
+    EmptySegment {
+        /// Index of the segment.
+        segment: usize,
+    },
+    /// The inputs of a segment do not refer to distinct, previously added segments.
+    InvalidInputs(DagError),
+}
+
+impl fmt::Display for BuildError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            BuildError::EmptySegment { segment } => write!(f, "segment {} is empty", segment),
+            BuildError::InvalidInputs(e) => write!(f, "invalid inputs: {}", e),
+        }
+    }
+}
+
+impl Error for BuildError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            BuildError::EmptySegment { .. } => None,
+            BuildError::InvalidInputs(e) => Some(e),
+        }
+    }
+}
+
+/// Builds a CompressedDag from untrusted input, checking each segment as it is added. Unlike
+/// `CompressedDag::add_node`, which panics on bad inputs and accepts empty segments, violations are
+/// reported by `build` as a `BuildError`.
+///
+/// ```
+/// use robust_binary_search::CompressedDagBuilder;
+///
+/// let mut builder = CompressedDagBuilder::new();
+/// builder.add_segment(3, vec![]).add_segment(2, vec![0]);
+/// let graph = builder.build().unwrap();
+/// assert_eq!(graph.nodes().len(), 2);
+/// ```
+#[derive(Clone, Debug, Default)]
+pub struct CompressedDagBuilder {
+    graph: CompressedDag,
+    /// First violation found, if any. Later segments are ignored once this is set.
+    error: Option<BuildError>,
+}
+
+impl CompressedDagBuilder {
+    /// Creates a builder for an empty CompressedDag.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds a segment of the given length. Each input must be the index of a previously added
+    /// segment, and no input may be listed more than once. Errors are deferred until `build`.
+    pub fn add_segment(&mut self, len: usize, inputs: Vec<usize>) -> &mut Self {
+        if self.error.is_some() {
+            return self;
+        }
+        let segment = self.graph.nodes().len();
+        self.error = if len == 0 {
+            Some(BuildError::EmptySegment { segment })
+        } else {
+            self.graph
+                .try_add_node(CompressedDagSegment::new(len), inputs)
+                .err()
+                .map(BuildError::InvalidInputs)
+        };
+        self
+    }
+
+    /// Returns the CompressedDag, or the first violation found by `add_segment`.
+    pub fn build(self) -> Result<CompressedDag, BuildError> {
+        match self.error {
+            Some(e) => Err(e),
+            None => Ok(self.graph),
+        }
+    }
+}
+
+/// Translates node references across a call to `CompressedDag::split_segment`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SplitMapping {
+    segment: usize,
+    at: usize,
+}
+
+impl SplitMapping {
+    /// Returns the reference in the split graph to the node referred to by `node` in the original
+    /// graph. Nodes before the split point are unchanged, nodes at or after it move to the new
+    /// segment, and all later segments shift up by one.
+    pub fn map(&self, node: CompressedDagNodeRef) -> CompressedDagNodeRef {
+        if node.segment < self.segment || (node.segment == self.segment && node.index < self.at) {
+            node
+        } else if node.segment == self.segment {
+            CompressedDagNodeRef {
+                segment: node.segment + 1,
+                index: node.index - self.at,
+            }
+        } else {
+            CompressedDagNodeRef {
+                segment: node.segment + 1,
+                index: node.index,
+            }
+        }
+    }
+
+    /// Same as `map`, but for segment indices. The original segment maps to its first half.
+    pub fn map_segment(&self, segment: usize) -> usize {
+        if segment <= self.segment {
+            segment
+        } else {
+            segment + 1
+        }
+    }
+}
+
+impl CompressedDag {
+    /// Builds a CompressedDag from a list of `(node, parents)` edges, collapsing linear chains of
+    /// nodes into segments. Returns the graph and a map from each node to its location in the
+    /// graph.
+    ///
+    /// A node continues its parent's segment if it has exactly one parent and is that parent's only
+    /// child. Parents which are not themselves listed as nodes are treated as lying outside the
+    /// graph and are otherwise ignored, so for example a commit log from `git log --format="%H %P"`
+    /// can be passed in directly even though the oldest commits refer to parents outside the range.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the edges contain a cycle.
+    pub fn from_edges<N: Clone + Eq + Hash>(
+        edges: &[(N, Vec<N>)],
+    ) -> (CompressedDag, HashMap<N, CompressedDagNodeRef>) {
+        let index_by_node = edges
+            .iter()
+            .enumerate()
+            .map(|(i, (node, _))| (node.clone(), i))
+            .collect::<HashMap<N, usize>>();
+        let known_parents = |i: usize| {
+            edges[i]
+                .1
+                .iter()
+                .filter_map(|parent| index_by_node.get(parent).copied())
+        };
+        let mut children = vec![Vec::new(); edges.len()];
+        for i in 0..edges.len() {
+            for parent in known_parents(i) {
+                children[parent].push(i);
+            }
+        }
+        // Returns the node whose segment the given node continues, if any.
+        let chain_parent = |i: usize| {
+            if edges[i].1.len() != 1 {
+                return None;
+            }
+            known_parents(i)
+                .next()
+                .filter(|parent| children[*parent].len() == 1)
+        };
+
+        // Collapse linear chains into segments, identified by their first node.
+        let mut segment_by_node = vec![usize::MAX; edges.len()];
+        let mut segments = Vec::<Vec<usize>>::new();
+        for head in 0..edges.len() {
+            if chain_parent(head).is_some() {
+                continue;
+            }
+            let mut nodes = vec![head];
+            let mut node = head;
+            while children[node].len() == 1 && chain_parent(children[node][0]) == Some(node) {
+                node = children[node][0];
+                nodes.push(node);
+            }
+            for node in &nodes {
+                segment_by_node[*node] = segments.len();
+            }
+            segments.push(nodes);
+        }
+        assert!(
+            segment_by_node.iter().all(|s| *s != usize::MAX),
+            "edges contain a cycle"
+        );
+
+        // Sort the segments topologically.
+        let mut segment_parents = segments
+            .iter()
+            .map(|nodes| {
+                known_parents(nodes[0])
+                    .map(|parent| segment_by_node[parent])
+                    .collect::<Vec<usize>>()
+            })
+            .collect::<Vec<_>>();
+        let mut segment_children = vec![Vec::new(); segments.len()];
+        let mut pending_parents = vec![0; segments.len()];
+        for (segment, parents) in segment_parents.iter().enumerate() {
+            pending_parents[segment] = parents.len();
+            for parent in parents {
+                segment_children[*parent].push(segment);
+            }
+        }
+        let mut ready = (0..segments.len())
+            .rev()
+            .filter(|s| pending_parents[*s] == 0)
+            .collect::<Vec<usize>>();
+        let mut sorted_index = vec![usize::MAX; segments.len()];
+        let mut sorted = Vec::with_capacity(segments.len());
+        while let Some(segment) = ready.pop() {
+            sorted_index[segment] = sorted.len();
+            sorted.push(segment);
+            for child in segment_children[segment].iter().rev() {
+                pending_parents[*child] -= 1;
+                if pending_parents[*child] == 0 {
+                    ready.push(*child);
+                }
+            }
+        }
+        assert_eq!(sorted.len(), segments.len(), "edges contain a cycle");
+
+        let mut graph = CompressedDag::new();
+        for segment in &sorted {
+            let inputs = segment_parents[*segment]
+                .drain(..)
+                .map(|parent| sorted_index[parent])
+                .collect();
+            graph.add_node(CompressedDagSegment::new(segments[*segment].len()), inputs);
+        }
+        let mut refs = HashMap::with_capacity(edges.len());
+        for (segment, nodes) in segments.iter().enumerate() {
+            for (index, node) in nodes.iter().enumerate() {
+                refs.insert(
+                    edges[*node].0.clone(),
+                    CompressedDagNodeRef {
+                        segment: sorted_index[segment],
+                        index,
+                    },
+                );
+            }
+        }
+        (graph, refs)
+    }
+
+    /// Builds a CompressedDag from an expanded Dag with one node per element (e.g. per commit),
+    /// collapsing linear chains of nodes into segments in the same way as `from_edges`. Returns the
+    /// graph and the location of each of the original nodes in it, indexed like `dag.nodes()`.
+    pub fn compress<T>(dag: &Dag<T>) -> (CompressedDag, Vec<CompressedDagNodeRef>) {
+        let edges = dag
+            .nodes()
+            .iter()
+            .enumerate()
+            .map(|(index, node)| (index, node.inputs().to_vec()))
+            .collect::<Vec<_>>();
+        let (graph, refs) = CompressedDag::from_edges(&edges);
+        let refs = (0..dag.nodes().len()).map(|index| refs[&index]).collect();
+        (graph, refs)
+    }
+
+    /// Splits a segment into two sequential segments. The first `at` nodes stay in `segment`, and
+    /// the rest move to a new segment at index `segment + 1` whose only input is `segment`. The
+    /// outputs of the original segment become outputs of the new segment, and later segments are
+    /// shifted up by one. Returns the mapping from node references in the original graph to node
+    /// references in the split graph.
+    ///
+    /// This invalidates any CompressedDagSearcher or AutoCompressedDagSearcher built from a clone of
+    /// the original graph, since their weights and votes are stored per segment. Takes time linear
+    /// in the size of the graph.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the segment is out of range or if `at` is not in the range `1..len`.
+    pub fn split_segment(&mut self, segment: usize, at: usize) -> SplitMapping {
+        assert!(
+            segment < self.nodes().len(),
+            "segment {} out of range for CompressedDag with {} segments",
+            segment,
+            self.nodes().len()
+        );
+        let len = self.node(segment).value().len();
+        assert!(
+            at > 0 && at < len,
+            "split point {} out of range for segment {} of length {}",
+            at,
+            segment,
+            len
+        );
+        let mapping = SplitMapping { segment, at };
+        let mut graph = CompressedDag::new();
+        for (i, node) in self.nodes().iter().enumerate() {
+            if i == segment {
+                graph.add_node(CompressedDagSegment::new(at), node.inputs().to_vec());
+                graph.add_node(CompressedDagSegment::new(len - at), vec![segment]);
+            } else {
+                let inputs = node
+                    .inputs()
+                    .iter()
+                    .map(|input| {
+                        if *input == segment {
+                            segment + 1
+                        } else {
+                            mapping.map_segment(*input)
+                        }
+                    })
+                    .collect();
+                graph.add_node(node.value().clone(), inputs);
+            }
+        }
+        *self = graph;
+        mapping
+    }
+
+    /// Returns a Graphviz DOT digraph with one node per segment, labeled with the segment's index
+    /// and length, and an edge from each input to the segment that uses it. Useful for debugging.
+    pub fn to_dot(&self) -> String {
+        self.to_dot_with(|_| DotStyle::default())
+    }
+
+    /// Same as `to_dot`, but `style` is called with each segment's index to add an extra label line
+    /// or a color, e.g. to overlay a searcher's likelihoods.
+    pub fn to_dot_with<F: FnMut(usize) -> DotStyle>(&self, mut style: F) -> String {
+        let mut dot = String::from("digraph {\n");
+        for (i, node) in self.nodes().iter().enumerate() {
+            let style = style(i);
+            let mut label = format!("{}\\nlen {}", i, node.value().len());
+            if let Some(extra) = &style.label {
+                label.push_str("\\n");
+                label.push_str(&escape_dot(extra));
+            }
+            write!(dot, "  {} [label=\"{}\"", i, label).unwrap();
+            if let Some(color) = &style.color {
+                write!(dot, ", color=\"{}\"", escape_dot(color)).unwrap();
+            }
+            dot.push_str("];\n");
+        }
+        for (from, to) in self.edges() {
+            writeln!(dot, "  {} -> {};", from, to).unwrap();
+        }
+        dot.push_str("}\n");
+        dot
+    }
+
+    /// Returns every edge between segments as a `(from, to)` pair, where `from` is one of the inputs
+    /// of `to`. Edges are ordered by `to` and then in the order of its inputs.
+    pub fn edges(&self) -> impl Iterator<Item = (usize, usize)> + '_ {
+        self.nodes()
+            .iter()
+            .enumerate()
+            .flat_map(|(to, node)| node.inputs().iter().map(move |from| (*from, to)))
+    }
+
+    /// Returns the index of the node in the conceptual expanded graph, where the nodes of each
+    /// segment are numbered consecutively in segment order. This is the inverse of
+    /// `linear_to_node_ref`. Takes time linear in the number of segments.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the segment or the index within the segment is out of range.
+    pub fn node_ref_to_linear(&self, node: CompressedDagNodeRef) -> usize {
+        assert!(
+            node.segment < self.nodes().len(),
+            "segment {} out of range for CompressedDag with {} segments",
+            node.segment,
+            self.nodes().len()
+        );
+        let len = self.node(node.segment).value().len();
+        assert!(
+            node.index < len,
+            "index {} out of range for segment {} of length {}",
+            node.index,
+            node.segment,
+            len
+        );
+        self.nodes()[..node.segment]
+            .iter()
+            .map(|n| n.value().len())
+            .sum::<usize>()
+            + node.index
+    }
+
+    /// Returns the node at the given index in the conceptual expanded graph. This is the inverse of
+    /// `node_ref_to_linear`. Takes time linear in the number of segments.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the index is greater than or equal to the total length of all segments.
+    pub fn linear_to_node_ref(&self, index: usize) -> CompressedDagNodeRef {
+        let mut offset = 0;
+        for (segment, node) in self.nodes().iter().enumerate() {
+            let len = node.value().len();
+            if index < offset + len {
+                return CompressedDagNodeRef {
+                    segment,
+                    index: index - offset,
+                };
+            }
+            offset += len;
+        }
+        panic!(
+            "linear index {} out of range for CompressedDag with {} nodes",
+            index, offset
+        );
+    }
+
+    /// Returns the total length of all segments, i.e. the number of nodes in the conceptual
+    /// expanded graph.
+    pub fn total_len(&self) -> usize {
+        self.nodes().iter().map(|n| n.value().len()).sum()
+    }
+
+    /// Returns the number of segments.
+    pub fn segment_count(&self) -> usize {
+        self.nodes().len()
+    }
+
+    /// Returns the length of the longest segment, or 0 if the graph is empty.
+    pub fn max_segment_len(&self) -> usize {
+        self.nodes()
+            .iter()
+            .map(|n| n.value().len())
+            .max()
+            .unwrap_or(0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn node_ref(segment: usize, index: usize) -> CompressedDagNodeRef {
+        CompressedDagNodeRef { segment, index }
+    }
+
+    fn segment_lens(graph: &CompressedDag) -> Vec<usize> {
+        graph.nodes().iter().map(|n| n.value().len()).collect()
+    }
+
+    #[test]
+    fn empty() {
+        let (graph, refs) = CompressedDag::from_edges::<&str>(&[]);
+        assert!(graph.nodes().is_empty());
+        assert!(refs.is_empty());
+    }
+
+    #[test]
+    fn linear_chain() {
+        // Listed newest first, as git log does, and with a parent outside the graph.
+        let edges = vec![
+            ("d", vec!["c"]),
+            ("c", vec!["b"]),
+            ("b", vec!["a"]),
+            ("a", vec!["z"]),
+        ];
+        let (graph, refs) = CompressedDag::from_edges(&edges);
+        assert_eq!(segment_lens(&graph), vec![4]);
+        assert!(graph.nodes()[0].inputs().is_empty());
+        assert_eq!(refs["a"], node_ref(0, 0));
+        assert_eq!(refs["b"], node_ref(0, 1));
+        assert_eq!(refs["c"], node_ref(0, 2));
+        assert_eq!(refs["d"], node_ref(0, 3));
+    }
+
+    #[test]
+    fn diamond() {
+        //   b-c-d
+        //  /     \
+        // a       g
+        //  \     /
+        //   e---f
+        let edges = vec![
+            ("g", vec!["d", "f"]),
+            ("f", vec!["e"]),
+            ("e", vec!["a"]),
+            ("d", vec!["c"]),
+            ("c", vec!["b"]),
+            ("b", vec!["a"]),
+            ("a", vec![]),
+        ];
+        let (graph, refs) = CompressedDag::from_edges(&edges);
+        assert_eq!(graph.nodes().len(), 4);
+        let a = refs["a"].segment;
+        let b = refs["b"].segment;
+        let e = refs["e"].segment;
+        let g = refs["g"].segment;
+        assert_eq!(a, 0);
+        assert_eq!(g, 3);
+        assert_eq!(refs["c"], node_ref(b, 1));
+        assert_eq!(refs["d"], node_ref(b, 2));
+        assert_eq!(refs["f"], node_ref(e, 1));
+        assert_eq!(graph.nodes()[a].value().len(), 1);
+        assert_eq!(graph.nodes()[b].value().len(), 3);
+        assert_eq!(graph.nodes()[e].value().len(), 2);
+        assert_eq!(graph.nodes()[g].value().len(), 1);
+        assert_eq!(graph.nodes()[b].inputs(), &[a]);
+        assert_eq!(graph.nodes()[e].inputs(), &[a]);
+        assert_eq!(graph.nodes()[g].inputs(), &[b, e]);
+    }
+
+    #[test]
+    fn parallel_roots() {
+        let edges = vec![
+            ("c", vec!["a", "b"]),
+            ("b", vec![]),
+            ("a", vec![]),
+            ("d", vec!["c"]),
+        ];
+        let (graph, refs) = CompressedDag::from_edges(&edges);
+        assert_eq!(graph.nodes().len(), 3);
+        let c = refs["c"].segment;
+        assert_eq!(c, 2);
+        assert_eq!(refs["d"], node_ref(c, 1));
+        assert_eq!(graph.nodes()[c].value().len(), 2);
+        let mut inputs = graph.nodes()[c].inputs().to_vec();
+        inputs.sort();
+        assert_eq!(inputs, vec![0, 1]);
+        assert!(graph.nodes()[0].inputs().is_empty());
+        assert!(graph.nodes()[1].inputs().is_empty());
+    }
+
+    #[test]
+    #[should_panic(expected = "cycle")]
+    fn cycle() {
+        let edges = vec![("a", vec!["c"]), ("b", vec!["a"]), ("c", vec!["b", "x"])];
+        CompressedDag::from_edges(&edges);
+    }
+
+    fn assert_round_trip(graph: &CompressedDag) {
+        let total = graph.total_len();
+        let mut expected = 0;
+        for (segment, node) in graph.nodes().iter().enumerate() {
+            for index in 0..node.value().len() {
+                let node_ref = node_ref(segment, index);
+                assert_eq!(graph.node_ref_to_linear(node_ref), expected);
+                assert_eq!(graph.linear_to_node_ref(expected), node_ref);
+                expected += 1;
+            }
+        }
+        assert_eq!(expected, total);
+    }
+
+    #[test]
+    fn linear_round_trip() {
+        let edges = vec![
+            ("g", vec!["d", "f"]),
+            ("f", vec!["e"]),
+            ("e", vec!["a"]),
+            ("d", vec!["c"]),
+            ("c", vec!["b"]),
+            ("b", vec!["a"]),
+            ("a", vec![]),
+        ];
+        let (graph, refs) = CompressedDag::from_edges(&edges);
+        assert_round_trip(&graph);
+        let mut linear = refs
+            .values()
+            .map(|r| graph.node_ref_to_linear(*r))
+            .collect::<Vec<_>>();
+        linear.sort();
+        assert_eq!(linear, (0..7).collect::<Vec<_>>());
+
+        let (graph, _) = CompressedDag::from_edges(&[
+            ("c", vec!["a", "b"]),
+            ("b", vec![]),
+            ("a", vec![]),
+            ("d", vec!["c"]),
+        ]);
+        assert_round_trip(&graph);
+
+        let mut graph = CompressedDag::new();
+        graph.add_node(CompressedDagSegment::new(3), vec![]);
+        graph.add_node(CompressedDagSegment::new(0), vec![0]);
+        graph.add_node(CompressedDagSegment::new(2), vec![1]);
+        assert_round_trip(&graph);
+        assert_eq!(graph.linear_to_node_ref(3), node_ref(2, 0));
+    }
+
+    #[test]
+    #[should_panic(expected = "linear index 5 out of range for CompressedDag with 5 nodes")]
+    fn linear_out_of_range() {
+        let mut graph = CompressedDag::new();
+        graph.add_node(CompressedDagSegment::new(3), vec![]);
+        graph.add_node(CompressedDagSegment::new(2), vec![0]);
+        graph.linear_to_node_ref(5);
+    }
+
+    #[test]
+    #[should_panic(expected = "index 2 out of range for segment 1 of length 2")]
+    fn node_ref_out_of_range() {
+        let mut graph = CompressedDag::new();
+        graph.add_node(CompressedDagSegment::new(3), vec![]);
+        graph.add_node(CompressedDagSegment::new(2), vec![0]);
+        graph.node_ref_to_linear(node_ref(1, 2));
+    }
+
+    fn fork_join() -> CompressedDag {
+        let mut graph = CompressedDag::new();
+        graph.add_node(CompressedDagSegment::new(100), vec![]);
+        graph.add_node(CompressedDagSegment::new(50), vec![0]);
+        graph.add_node(CompressedDagSegment::new(25), vec![0]);
+        graph.add_node(CompressedDagSegment::new(10), vec![1, 2]);
+        graph
+    }
+
+    #[test]
+    fn segment_stats() {
+        let graph = fork_join();
+        assert_eq!(graph.total_len(), 185);
+        assert_eq!(graph.segment_count(), 4);
+        assert_eq!(graph.max_segment_len(), 100);
+
+        let (graph, _) = CompressedDag::from_edges(&[
+            ("g", vec!["d", "f"]),
+            ("f", vec!["e"]),
+            ("e", vec!["a"]),
+            ("d", vec!["c"]),
+            ("c", vec!["b"]),
+            ("b", vec!["a"]),
+            ("a", vec![]),
+        ]);
+        assert_eq!(graph.total_len(), 7);
+        assert_eq!(graph.segment_count(), 4);
+        assert_eq!(graph.max_segment_len(), 3);
+
+        let graph = CompressedDag::new();
+        assert_eq!(graph.total_len(), 0);
+        assert_eq!(graph.segment_count(), 0);
+        assert_eq!(graph.max_segment_len(), 0);
+    }
+
+    /// Checks that every edge of `dag` is either within a segment of `graph` or between segments.
+    fn assert_compressed<T>(dag: &Dag<T>, graph: &CompressedDag, refs: &[CompressedDagNodeRef]) {
+        assert_eq!(refs.len(), dag.nodes().len());
+        assert_eq!(graph.total_len(), dag.nodes().len());
+        for (index, node) in dag.nodes().iter().enumerate() {
+            let location = refs[index];
+            if location.index > 0 {
+                assert_eq!(node.inputs().len(), 1);
+                assert_eq!(
+                    refs[node.inputs()[0]],
+                    node_ref(location.segment, location.index - 1)
+                );
+            } else {
+                let mut inputs = node
+                    .inputs()
+                    .iter()
+                    .map(|input| refs[*input].segment)
+                    .collect::<Vec<_>>();
+                inputs.sort();
+                let mut expected = graph.node(location.segment).inputs().to_vec();
+                expected.sort();
+                assert_eq!(inputs, expected);
+                for input in node.inputs() {
+                    let len = graph.node(refs[*input].segment).value().len();
+                    assert_eq!(refs[*input].index, len - 1);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn compress_linear() {
+        let mut dag = Dag::default();
+        dag.add_node((), vec![]);
+        dag.add_node((), vec![0]);
+        dag.add_node((), vec![1]);
+        dag.add_node((), vec![2]);
+        let (graph, refs) = CompressedDag::compress(&dag);
+        assert_compressed(&dag, &graph, &refs);
+        assert_eq!(segment_lens(&graph), vec![4]);
+        assert_eq!(
+            refs,
+            vec![
+                node_ref(0, 0),
+                node_ref(0, 1),
+                node_ref(0, 2),
+                node_ref(0, 3)
+            ]
+        );
+    }
+
+    #[test]
+    fn compress_fork() {
+        // 0---1---2
+        //  \
+        //   3---4
+        let mut dag = Dag::default();
+        dag.add_node((), vec![]);
+        dag.add_node((), vec![0]);
+        dag.add_node((), vec![1]);
+        dag.add_node((), vec![0]);
+        dag.add_node((), vec![3]);
+        let (graph, refs) = CompressedDag::compress(&dag);
+        assert_compressed(&dag, &graph, &refs);
+        assert_eq!(graph.segment_count(), 3);
+        assert_eq!(refs[0], node_ref(refs[0].segment, 0));
+        assert_eq!(refs[2], node_ref(refs[1].segment, 1));
+        assert_eq!(refs[4], node_ref(refs[3].segment, 1));
+    }
+
+    #[test]
+    fn compress_merge() {
+        // 0---1
+        //      \
+        //   2---3---4
+        let mut dag = Dag::default();
+        dag.add_node((), vec![]);
+        dag.add_node((), vec![0]);
+        dag.add_node((), vec![]);
+        dag.add_node((), vec![1, 2]);
+        dag.add_node((), vec![3]);
+        let (graph, refs) = CompressedDag::compress(&dag);
+        assert_compressed(&dag, &graph, &refs);
+        let mut lens = segment_lens(&graph);
+        lens.sort();
+        assert_eq!(lens, vec![1, 2, 2]);
+        assert_eq!(refs[4], node_ref(refs[3].segment, 1));
+    }
+
+    #[test]
+    fn compress_diamond() {
+        // 0---1---2
+        //  \       \
+        //   3---4---5
+        let mut dag = Dag::default();
+        dag.add_node((), vec![]);
+        dag.add_node((), vec![0]);
+        dag.add_node((), vec![1]);
+        dag.add_node((), vec![0]);
+        dag.add_node((), vec![3]);
+        dag.add_node((), vec![2, 4]);
+        let (graph, refs) = CompressedDag::compress(&dag);
+        assert_compressed(&dag, &graph, &refs);
+        assert_eq!(graph.segment_count(), 4);
+        let mut lens = segment_lens(&graph);
+        lens.sort();
+        assert_eq!(lens, vec![1, 1, 2, 2]);
+    }
+
+    #[test]
+    fn compress_empty() {
+        let (graph, refs) = CompressedDag::compress(&Dag::<()>::default());
+        assert_eq!(graph.segment_count(), 0);
+        assert!(refs.is_empty());
+    }
+
+    fn assert_same_graph(actual: &CompressedDag, expected: &CompressedDag) {
+        assert_eq!(actual.nodes().len(), expected.nodes().len());
+        for (a, e) in actual.nodes().iter().zip(expected.nodes()) {
+            assert_eq!(a.value().len(), e.value().len());
+            assert_eq!(a.inputs(), e.inputs());
+            assert_eq!(a.outputs(), e.outputs());
+            assert_eq!(a.ancestors(), e.ancestors());
+            assert_eq!(a.remainder_ancestors(), e.remainder_ancestors());
+        }
+    }
+
+    #[test]
+    fn split_segment() {
+        let mut graph = fork_join();
+        let mapping = graph.split_segment(1, 20);
+        let mut expected = CompressedDag::new();
+        expected.add_node(CompressedDagSegment::new(100), vec![]);
+        expected.add_node(CompressedDagSegment::new(20), vec![0]);
+        expected.add_node(CompressedDagSegment::new(30), vec![1]);
+        expected.add_node(CompressedDagSegment::new(25), vec![0]);
+        expected.add_node(CompressedDagSegment::new(10), vec![2, 3]);
+        assert_same_graph(&graph, &expected);
+
+        assert_eq!(mapping.map(node_ref(0, 99)), node_ref(0, 99));
+        assert_eq!(mapping.map(node_ref(1, 19)), node_ref(1, 19));
+        assert_eq!(mapping.map(node_ref(1, 20)), node_ref(2, 0));
+        assert_eq!(mapping.map(node_ref(1, 49)), node_ref(2, 29));
+        assert_eq!(mapping.map(node_ref(2, 3)), node_ref(3, 3));
+        assert_eq!(mapping.map(node_ref(3, 9)), node_ref(4, 9));
+        assert_eq!(mapping.map_segment(1), 1);
+        assert_eq!(mapping.map_segment(3), 4);
+    }
+
+    #[test]
+    fn split_segment_matches_from_edges() {
+        let edges = |split: bool| {
+            let mut edges = vec![("a", vec![]), ("b", vec!["a"]), ("c", vec!["b"])];
+            if split {
+                // A second child of b ends the segment there.
+                edges.push(("x", vec!["b"]));
+            }
+            edges.push(("d", vec!["c"]));
+            edges
+        };
+        let (mut graph, refs) = CompressedDag::from_edges(&edges(false));
+        assert_eq!(graph.nodes().len(), 1);
+        let mapping = graph.split_segment(0, 2);
+        let (mut expected, expected_refs) = CompressedDag::from_edges(&edges(true));
+        // Drop x so the graphs should be identical.
+        let x = expected_refs["x"].segment;
+        assert_eq!(x, expected.nodes().len() - 1);
+        let mut trimmed = CompressedDag::new();
+        for node in &expected.nodes()[..x] {
+            trimmed.add_node(node.value().clone(), node.inputs().to_vec());
+        }
+        expected = trimmed;
+        assert_same_graph(&graph, &expected);
+        for (node, node_ref) in &refs {
+            assert_eq!(mapping.map(*node_ref), expected_refs[node]);
+        }
+        assert_round_trip(&graph);
+    }
+
+    #[test]
+    #[should_panic(expected = "split point 0 out of range")]
+    fn split_segment_at_start() {
+        fork_join().split_segment(1, 0);
+    }
+
+    #[test]
+    #[should_panic(expected = "split point 50 out of range")]
+    fn split_segment_at_end() {
+        fork_join().split_segment(1, 50);
+    }
+
+    #[test]
+    fn builder() {
+        let mut builder = CompressedDagBuilder::new();
+        builder
+            .add_segment(100, vec![])
+            .add_segment(50, vec![0])
+            .add_segment(25, vec![0])
+            .add_segment(10, vec![1, 2]);
+        let graph = builder.build().unwrap();
+        assert_eq!(segment_lens(&graph), vec![100, 50, 25, 10]);
+        assert_eq!(graph.node(3).inputs(), &[1, 2]);
+        assert_eq!(graph.node(3).ancestors().len(), 3);
+    }
+
+    #[test]
+    fn builder_input_not_yet_added() {
+        let mut builder = CompressedDagBuilder::new();
+        builder
+            .add_segment(3, vec![])
+            .add_segment(2, vec![2])
+            .add_segment(1, vec![0]);
+        let err = builder.build().unwrap_err();
+        assert_eq!(
+            err,
+            BuildError::InvalidInputs(DagError::InputOutOfRange { node: 1, input: 2 })
+        );
+        assert_eq!(
+            err.to_string(),
+            "invalid inputs: input 2 of node 1 is not less than the node's index"
+        );
+    }
+
+    #[test]
+    fn builder_errors() {
+        let mut builder = CompressedDagBuilder::new();
+        builder.add_segment(3, vec![]).add_segment(0, vec![0]);
+        let err = builder.build().unwrap_err();
+        assert_eq!(err, BuildError::EmptySegment { segment: 1 });
+        assert_eq!(err.to_string(), "segment 1 is empty");
+
+        let mut builder = CompressedDagBuilder::new();
+        builder.add_segment(3, vec![]).add_segment(2, vec![0, 0]);
+        assert_eq!(
+            builder.build().unwrap_err(),
+            BuildError::InvalidInputs(DagError::DuplicateInput { node: 1, input: 0 })
+        );
+    }
+
+    #[test]
+    fn edges() {
+        let graph = fork_join();
+        assert_eq!(
+            graph.edges().collect::<Vec<_>>(),
+            vec![(0, 1), (0, 2), (1, 3), (2, 3)]
+        );
+        assert_eq!(CompressedDag::new().edges().count(), 0);
+    }
+
+    #[test]
+    fn to_dot() {
+        let dot = fork_join().to_dot();
+        assert!(dot.starts_with("digraph {\n"));
+        assert!(dot.ends_with("}\n"));
+        for line in &[
+            r#"  0 [label="0\nlen 100"];"#,
+            r#"  1 [label="1\nlen 50"];"#,
+            r#"  2 [label="2\nlen 25"];"#,
+            r#"  3 [label="3\nlen 10"];"#,
+            "  0 -> 1;",
+            "  0 -> 2;",
+            "  1 -> 3;",
+            "  2 -> 3;",
+        ] {
+            assert!(
+                dot.lines().any(|l| l == *line),
+                "missing {:?} in {}",
+                line,
+                dot
+            );
+        }
+        assert_eq!(dot.lines().count(), 10);
+    }
+
+    #[test]
+    fn to_dot_with_style() {
+        let dot = fork_join().to_dot_with(|segment| {
+            if segment == 2 {
+                DotStyle {
+                    label: Some("p = \"0.9\"".to_string()),
+                    color: Some("red".to_string()),
+                }
+            } else {
+                DotStyle::default()
+            }
+        });
+        assert!(dot
+            .lines()
+            .any(|l| l == r#"  2 [label="2\nlen 25\np = \"0.9\"", color="red"];"#));
+        assert!(dot.lines().any(|l| l == r#"  1 [label="1\nlen 50"];"#));
+    }
+}
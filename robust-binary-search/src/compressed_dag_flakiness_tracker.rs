@@ -12,6 +12,7 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+use crate::flakiness_tracker::split_estimate;
 use crate::CompressedDag;
 use crate::CompressedDagNodeRef;
 use crate::FlakinessTracker;
@@ -22,7 +23,7 @@ use std::rc::Rc;
 
 /// Calculates vote inversions over a graph, which can be used to estimate flakiness.
 #[derive(Clone, Debug)]
-pub(crate) struct CompressedDagFlakinessTracker {
+pub struct CompressedDagFlakinessTracker {
     graph: Rc<CompressedDag>,
     votes: BTreeMap<usize, FlakinessTracker>,
 }
@@ -36,6 +37,16 @@ impl CompressedDagFlakinessTracker {
         }
     }
 
+    /// Discards all votes.
+    pub fn reset(&mut self) {
+        self.votes.clear();
+    }
+
+    /// Returns the total number of votes.
+    pub fn total_votes(&self) -> usize {
+        self.votes.values().map(|v| v.total_votes()).sum()
+    }
+
     /// Adds a vote to the internal statistics. With low flakiness, true votes are expected not to
     /// appear in the ancestors of false votes.
     pub fn report(&mut self, node: CompressedDagNodeRef, heads: bool) {
@@ -50,9 +61,15 @@ impl CompressedDagFlakinessTracker {
     /// cast at the same nodes but were randomly half heads and half tails. It is scaled by four
     /// to avoid loss of precision.
     fn inversions(&self) -> (usize, usize) {
+        self.inversions_within(|_| true)
+    }
+
+    /// Same as `inversions`, but only counts votes in segments for which `include` returns true.
+    /// The included segments must be closed under taking ancestors.
+    fn inversions_within<F: Fn(usize) -> bool>(&self, include: F) -> (usize, usize) {
         let mut votes_at_segment = HashMap::new();
         let graph: &CompressedDag = self.graph.borrow();
-        for segment in self.votes.keys() {
+        for segment in self.votes.keys().filter(|segment| include(**segment)) {
             let inputs = graph.node(*segment).inputs();
             if !inputs.is_empty() {
                 let (input_heads, input_votes) = self
@@ -77,7 +94,7 @@ impl CompressedDagFlakinessTracker {
         }
         let mut inversions = 0;
         let mut random_inversions = 0;
-        for (segment, votes) in &self.votes {
+        for (segment, votes) in self.votes.iter().filter(|(segment, _)| include(**segment)) {
             let (segment_heads, segment_votes) = *votes_at_segment.get(&segment).unwrap_or(&(0, 0));
             let (inv, rand_inv) = votes.inversions();
             inversions += votes.total_tails() * segment_heads + inv;
@@ -89,16 +106,88 @@ impl CompressedDagFlakinessTracker {
     /// Returns the estimated flakiness based on the votes, where 0.0 is deterministic and 1.0 is
     /// complete randomness.
     pub fn flakiness(&self) -> f64 {
-        // See note in FlakinessTracker::flakiness.
-        let (inv, rand_inv) = self.inversions();
-        let tmp = 1.0 - (inv + 1) as f64 / (rand_inv as f64 / 4.0 + 4.0 / 3.0);
-        1.0 - tmp.max(0.0).sqrt()
+        flakiness_from_inversions(self.inversions())
+    }
+
+    /// Returns the estimated flakiness of the given segment's branch, where 0.0 is deterministic and
+    /// 1.0 is complete randomness. Only votes in the segment and its ancestors are considered, so
+    /// noise in parallel branches (and in descendants) doesn't affect the estimate.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the segment is out of range.
+    pub fn branch_flakiness(&self, segment: usize) -> f64 {
+        let graph: &CompressedDag = self.graph.borrow();
+        let ancestors = graph.node(segment).ancestors();
+        flakiness_from_inversions(
+            self.inversions_within(|s| s == segment || ancestors.contains(&s)),
+        )
+    }
+
+    /// Returns the estimated flakiness of the given segment, where 0.0 is deterministic and 1.0 is
+    /// complete randomness. Only votes within the segment are considered, so unlike
+    /// `branch_flakiness`, noise in its ancestors doesn't affect the estimate. A segment without
+    /// votes has the same estimate as an empty `FlakinessTracker`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the segment is out of range.
+    pub fn segment_flakiness(&self, segment: usize) -> f64 {
+        let graph: &CompressedDag = self.graph.borrow();
+        assert!(
+            segment < graph.nodes().len(),
+            "segment {} out of range",
+            segment
+        );
+        self.votes
+            .get(&segment)
+            .map(|votes| votes.flakiness())
+            .unwrap_or_else(|| FlakinessTracker::default().flakiness())
+    }
+
+    /// Returns separate flakiness estimates for the nodes which should vote tails and heads if
+    /// `pivot` is the first bad node. This is the graph's equivalent of
+    /// `FlakinessTracker::split_flakiness`: `.1` is the estimate for `pivot` and its descendants,
+    /// and `.0` is the estimate for every other node. `prior` is the number of pseudo-votes added to
+    /// each side.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the pivot's segment is out of range.
+    pub fn split_flakiness(&self, pivot: CompressedDagNodeRef, prior: f64) -> (f64, f64) {
+        let graph: &CompressedDag = self.graph.borrow();
+        assert!(pivot.segment < graph.nodes().len());
+        let mut before = (0, 0);
+        let mut after = (0, 0);
+        for (segment, votes) in &self.votes {
+            let split = if *segment == pivot.segment {
+                pivot.index
+            } else if graph.node(*segment).ancestors().contains(&pivot.segment) {
+                0
+            } else {
+                usize::MAX
+            };
+            let (segment_before, segment_after) = votes.split_votes(split);
+            before.0 += segment_before.0;
+            before.1 += segment_before.1;
+            after.0 += segment_after.0;
+            after.1 += segment_after.1;
+        }
+        (split_estimate(before, prior), split_estimate(after, prior))
     }
 }
 
+/// Converts the output of `inversions` into a flakiness estimate.
+fn flakiness_from_inversions((inv, rand_inv): (usize, usize)) -> f64 {
+    // See note in FlakinessTracker::flakiness.
+    let tmp = 1.0 - (inv + 1) as f64 / (rand_inv as f64 / 4.0 + 4.0 / 3.0);
+    1.0 - tmp.max(0.0).sqrt()
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::test_rng::TestRng;
     use crate::CompressedDagSegment;
 
     macro_rules! assert_flakiness {
@@ -716,4 +805,105 @@ mod tests {
         assert_eq!(tracker.inversions(), (1, 5));
         assert_flakiness!(tracker, 0.5248);
     }
+
+    #[test]
+    fn branch_flakiness_diamond() {
+        //      /-1-\
+        // *-0-*     *-3-*
+        //      \-2-/
+        let mut graph = CompressedDag::default();
+        graph.add_node(CompressedDagSegment::new(10), vec![]);
+        graph.add_node(CompressedDagSegment::new(10), vec![0]);
+        graph.add_node(CompressedDagSegment::new(10), vec![0]);
+        graph.add_node(CompressedDagSegment::new(10), vec![1, 2]);
+        let mut tracker = CompressedDagFlakinessTracker::new(Rc::new(graph));
+        for i in 0..10 {
+            // Branch 1 is clean: everything before index 5 is good.
+            tracker.report(
+                CompressedDagNodeRef {
+                    segment: 1,
+                    index: i,
+                },
+                i >= 5,
+            );
+            // Branch 2 is noisy: every index gets contradictory votes.
+            for heads in &[false, true] {
+                tracker.report(
+                    CompressedDagNodeRef {
+                        segment: 2,
+                        index: i,
+                    },
+                    *heads,
+                );
+            }
+        }
+        let clean = tracker.branch_flakiness(1);
+        let noisy = tracker.branch_flakiness(2);
+        assert!(clean < 0.1, "clean = {}", clean);
+        assert!(noisy > 0.5, "noisy = {}", noisy);
+        assert_flakiness!(tracker, tracker.branch_flakiness(3));
+    }
+
+    #[test]
+    fn split_flakiness_fork() {
+        // 0-1
+        //  \
+        //   2
+        let mut graph = CompressedDag::default();
+        graph.add_node(CompressedDagSegment::new(10), vec![]);
+        graph.add_node(CompressedDagSegment::new(10), vec![0]);
+        graph.add_node(CompressedDagSegment::new(10), vec![0]);
+        let mut tracker = CompressedDagFlakinessTracker::new(Rc::new(graph));
+        let node = |segment, index| CompressedDagNodeRef { segment, index };
+        tracker.report(node(0, 2), false);
+        tracker.report(node(0, 8), true);
+        tracker.report(node(1, 5), true);
+        // The parallel branch shouldn't vote heads if the first bad node is in segment 0 or 1.
+        tracker.report(node(2, 5), true);
+        tracker.report(node(2, 5), false);
+        assert_eq!(tracker.split_flakiness(node(0, 5), 0.0), (0.0, 2.0 / 4.0));
+        assert_eq!(
+            tracker.split_flakiness(node(1, 0), 0.0),
+            (2.0 * 2.0 / 4.0, 0.0)
+        );
+    }
+
+    #[test]
+    fn segment_flakiness() {
+        // 0-1-3
+        //  \
+        //   2
+        let mut graph = CompressedDag::default();
+        graph.add_node(CompressedDagSegment::new(20), vec![]);
+        graph.add_node(CompressedDagSegment::new(20), vec![0]);
+        graph.add_node(CompressedDagSegment::new(20), vec![0]);
+        graph.add_node(CompressedDagSegment::new(20), vec![1]);
+        let mut tracker = CompressedDagFlakinessTracker::new(Rc::new(graph));
+        let node = |segment, index| CompressedDagNodeRef { segment, index };
+        // The first bad node is (1, 10), so segments 0 and 2 are good and 3 is bad. Only segment 2
+        // is noisy.
+        let mut rng = TestRng::new(1);
+        for i in 0..20 {
+            tracker.report(node(0, i), false);
+            tracker.report(node(1, i), i >= 10);
+            tracker.report(node(3, i), true);
+            for _ in 0..2 {
+                tracker.report(node(2, i), rng.next_bool());
+            }
+        }
+        for &segment in &[0, 1, 3] {
+            let flakiness = tracker.segment_flakiness(segment);
+            assert!(flakiness < 0.1, "segment {}: {}", segment, flakiness);
+        }
+        let noisy = tracker.segment_flakiness(2);
+        assert!(noisy > 0.5, "noisy = {}", noisy);
+    }
+
+    #[test]
+    #[should_panic(expected = "segment 1 out of range")]
+    fn segment_flakiness_out_of_range() {
+        let mut graph = CompressedDag::default();
+        graph.add_node(CompressedDagSegment::new(10), vec![]);
+        CompressedDagFlakinessTracker::new(Rc::new(graph)).segment_flakiness(1);
+    }
 }
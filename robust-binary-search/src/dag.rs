@@ -13,13 +13,78 @@
 // limitations under the License.
 
 use im_rc::OrdSet;
+#[cfg(feature = "serde")]
+use serde::de::Error as _;
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::cell::RefCell;
 use std::collections::HashSet;
+use std::error::Error;
+use std::fmt;
+
+/// Error returned by `Dag::try_add_node` and `Dag::validate`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DagError {
+    /// The input of the node at the given index is not less than the node's index, so the nodes
+    /// would not be sorted topologically.
+    InputOutOfRange {
+        /// Index of the node.
+        node: usize,
+        /// The offending input.
+        input: usize,
+    },
+    /// The node at the given index lists the same input more than once.
+    DuplicateInput {
+        /// Index of the node.
+        node: usize,
+        /// The duplicated input.
+        input: usize,
+    },
+}
+
+impl fmt::Display for DagError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            DagError::InputOutOfRange { node, input } => write!(
+                f,
+                "input {} of node {} is not less than the node's index",
+                input, node
+            ),
+            DagError::DuplicateInput { node, input } => {
+                write!(f, "node {} lists input {} more than once", node, input)
+            }
+        }
+    }
+}
+
+impl Error for DagError {}
+
+/// Checks that the inputs of the node at index `node` are all less than `node` and are distinct.
+fn check_inputs(node: usize, inputs: &[usize]) -> Result<(), DagError> {
+    let mut seen = HashSet::new();
+    for input in inputs {
+        if *input >= node {
+            return Err(DagError::InputOutOfRange {
+                node,
+                input: *input,
+            });
+        }
+        if !seen.insert(*input) {
+            return Err(DagError::DuplicateInput {
+                node,
+                input: *input,
+            });
+        }
+    }
+    Ok(())
+}
 
 /// A node in a Dag.
 #[derive(Clone, Debug)]
 pub struct DagNode<T> {
     value: T,
     inputs: Vec<usize>,
+    outputs: Vec<usize>,
     ancestors: OrdSet<usize>,
     remainder_ancestors: Vec<usize>,
 }
@@ -35,6 +100,11 @@ impl<T> DagNode<T> {
         &self.inputs
     }
 
+    /// Returns indices within the Dag of the nodes which have this node as an input.
+    pub fn outputs(&self) -> &[usize] {
+        &self.outputs
+    }
+
     /// Returns indices within the Dag of the transitive closure of the node's inputs. Includes the
     /// inputs but excludes the node itself.
     pub fn ancestors(&self) -> &OrdSet<usize> {
@@ -60,18 +130,23 @@ impl<T> DagNode<T> {
 #[derive(Clone, Debug)]
 pub struct Dag<T> {
     nodes: Vec<DagNode<T>>,
+    /// Descendants of each node, computed lazily by `descendants` and cleared by `add_node`.
+    descendants: RefCell<Option<Vec<OrdSet<usize>>>>,
 }
 
 impl<T> Default for Dag<T> {
     fn default() -> Self {
-        Self { nodes: vec![] }
+        Self::new()
     }
 }
 
 impl<T> Dag<T> {
     /// Creates an empty Dag.
     pub fn new() -> Self {
-        Dag { nodes: vec![] }
+        Dag {
+            nodes: vec![],
+            descendants: RefCell::new(None),
+        }
     }
 
     /// Returns the nodes in the Dag.
@@ -123,12 +198,100 @@ impl<T> Dag<T> {
             sorted_remainder_ancestors.sort();
             (ancestors, sorted_remainder_ancestors)
         };
+        let index = self.nodes.len();
+        for input in &inputs {
+            self.nodes[*input].outputs.push(index);
+        }
         self.nodes.push(DagNode {
             value,
             ancestors,
             remainder_ancestors,
             inputs,
+            outputs: vec![],
         });
+        *self.descendants.get_mut() = None;
+    }
+
+    /// Same as `add_node`, but returns an error without modifying the Dag if any input is greater
+    /// than or equal to nodes().len() or if an input is listed more than once.
+    pub fn try_add_node(&mut self, value: T, inputs: Vec<usize>) -> Result<(), DagError> {
+        check_inputs(self.nodes.len(), &inputs)?;
+        self.add_node(value, inputs);
+        Ok(())
+    }
+
+    /// Returns indices within the Dag of the transitive closure of the node's outputs. Excludes the
+    /// node itself. This is the counterpart of `DagNode::ancestors`, but because nodes can be added
+    /// after the given node, it is computed for all nodes on first use after a node is added.
+    ///
+    /// `CompressedDagSearcher::report` doesn't use this: a false vote scales every segment which is
+    /// not the node or one of its ancestors, and that includes unrelated branches as well as
+    /// descendants, so every segment has to be visited anyway.
+    ///
+    /// # Panics
+    ///
+    /// Panics if index is greater than or equal to nodes().len().
+    pub fn descendants(&self, index: usize) -> OrdSet<usize> {
+        assert!(index < self.nodes.len());
+        let mut cache = self.descendants.borrow_mut();
+        let descendants = cache.get_or_insert_with(|| {
+            let mut descendants = vec![OrdSet::new(); self.nodes.len()];
+            for (i, node) in self.nodes.iter().enumerate().rev() {
+                let mut set = OrdSet::new();
+                for output in &node.outputs {
+                    if set.is_empty() {
+                        set = descendants[*output].clone();
+                    } else {
+                        set = set.union(descendants[*output].clone());
+                    }
+                    set.insert(*output);
+                }
+                descendants[i] = set;
+            }
+            descendants
+        });
+        descendants[index].clone()
+    }
+
+    /// Verifies that the nodes are sorted topologically, i.e. that every input of a node is less
+    /// than the node's own index, and that no node lists an input more than once.
+    pub fn validate(&self) -> Result<(), DagError> {
+        for (i, node) in self.nodes.iter().enumerate() {
+            check_inputs(i, &node.inputs)?;
+        }
+        Ok(())
+    }
+}
+
+/// Serialized form of a DagNode. The ancestor sets are derived data, so they are rebuilt on
+/// deserialization rather than stored.
+#[cfg(feature = "serde")]
+#[derive(Serialize, Deserialize)]
+struct SerializedDagNode<T> {
+    value: T,
+    inputs: Vec<usize>,
+}
+
+#[cfg(feature = "serde")]
+impl<T: Serialize> Serialize for Dag<T> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_seq(self.nodes.iter().map(|node| SerializedDagNode {
+            value: &node.value,
+            inputs: node.inputs.clone(),
+        }))
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de, T: Deserialize<'de>> Deserialize<'de> for Dag<T> {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let nodes = Vec::<SerializedDagNode<T>>::deserialize(deserializer)?;
+        let mut dag = Dag::new();
+        for node in nodes {
+            dag.try_add_node(node.value, node.inputs)
+                .map_err(D::Error::custom)?;
+        }
+        Ok(dag)
     }
 }
 
@@ -169,4 +332,108 @@ mod tests {
         graph.add_node((), vec![2, 4]);
         assert_eq!(graph.node(5).remainder_ancestors(), &[3, 4]);
     }
+
+    #[test]
+    fn try_add_node() {
+        let mut graph = Dag::default();
+        assert_eq!(graph.try_add_node((), vec![]), Ok(()));
+        assert_eq!(graph.try_add_node((), vec![0]), Ok(()));
+        assert_eq!(
+            graph.try_add_node((), vec![0, 2]),
+            Err(DagError::InputOutOfRange { node: 2, input: 2 })
+        );
+        assert_eq!(
+            graph.try_add_node((), vec![1, 0, 1]),
+            Err(DagError::DuplicateInput { node: 2, input: 1 })
+        );
+        assert_eq!(graph.nodes().len(), 2);
+        assert_eq!(graph.validate(), Ok(()));
+        assert_eq!(
+            DagError::InputOutOfRange { node: 2, input: 2 }.to_string(),
+            "input 2 of node 2 is not less than the node's index"
+        );
+    }
+
+    #[test]
+    fn validate() {
+        let mut graph = Dag::default();
+        graph.add_node((), vec![]);
+        graph.add_node((), vec![0]);
+        graph.add_node((), vec![0, 0]);
+        assert_eq!(
+            graph.validate(),
+            Err(DagError::DuplicateInput { node: 2, input: 0 })
+        );
+
+        let mut graph = Dag::default();
+        graph.add_node((), vec![]);
+        graph.add_node((), vec![0]);
+        graph.nodes[0].inputs.push(1);
+        assert_eq!(
+            graph.validate(),
+            Err(DagError::InputOutOfRange { node: 0, input: 1 })
+        );
+    }
+
+    #[test]
+    fn descendants() {
+        // 0---1---2
+        //  \       \
+        //   3---4---5
+        let mut graph = Dag::default();
+        graph.add_node((), vec![]);
+        graph.add_node((), vec![0]);
+        graph.add_node((), vec![1]);
+        graph.add_node((), vec![0]);
+        graph.add_node((), vec![3]);
+        assert_eq!(graph.descendants(1), hash_set![2]);
+        graph.add_node((), vec![2, 4]);
+        assert_eq!(graph.node(0).outputs(), &[1, 3]);
+        assert_eq!(graph.node(4).outputs(), &[5]);
+        assert_eq!(graph.descendants(0), hash_set![1, 2, 3, 4, 5]);
+        assert_eq!(graph.descendants(1), hash_set![2, 5]);
+        assert_eq!(graph.descendants(3), hash_set![4, 5]);
+        assert_eq!(graph.descendants(5), hash_set![]);
+        for i in 0..6 {
+            for j in graph.descendants(i) {
+                assert!(graph.node(j).ancestors().contains(&i));
+            }
+        }
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn serde_round_trip() {
+        let mut graph = Dag::default();
+        graph.add_node("a".to_string(), vec![]);
+        graph.add_node("b".to_string(), vec![0]);
+        graph.add_node("c".to_string(), vec![0]);
+        graph.add_node("d".to_string(), vec![2, 1]);
+        let json = serde_json::to_string(&graph).unwrap();
+        let restored: Dag<String> = serde_json::from_str(&json).unwrap();
+        assert_eq!(restored.nodes().len(), 4);
+        for i in 0..4 {
+            assert_eq!(restored.node(i).value(), graph.node(i).value());
+            assert_eq!(restored.node(i).inputs(), graph.node(i).inputs());
+            assert_eq!(restored.node(i).ancestors(), graph.node(i).ancestors());
+            assert_eq!(
+                restored.node(i).remainder_ancestors(),
+                graph.node(i).remainder_ancestors()
+            );
+        }
+        assert_eq!(restored.descendants(0), graph.descendants(0));
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn serde_invalid_order() {
+        let json = r#"[{"value":1,"inputs":[]},{"value":2,"inputs":[2]}]"#;
+        let err = serde_json::from_str::<Dag<i32>>(json).unwrap_err();
+        assert!(
+            err.to_string()
+                .contains("input 2 of node 1 is not less than the node's index"),
+            "{}",
+            err
+        );
+    }
 }
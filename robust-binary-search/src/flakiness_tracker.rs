@@ -12,21 +12,59 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-use std::collections::BTreeMap;
+//! Flakiness estimation for votes over a linear range.
+//!
+//! ```
+//! use robust_binary_search::flakiness_tracker::FlakinessTracker;
+//!
+//! let mut tracker = FlakinessTracker::default();
+//! for i in 0..20 {
+//!     tracker.report(i, i >= 10);
+//! }
+//! // The test is sometimes wrong after the change, but never before it.
+//! tracker.report(15, false);
+//! tracker.report(18, false);
+//! let (before, after) = tracker.split_flakiness(10, 1.0);
+//! assert!(before < after);
+//! ```
+
+use alloc::collections::BTreeMap;
 
-/// INTERNAL ONLY.
-///
 /// Calculates vote inversions in a linear range, which can be used to estimate flakiness.
-#[doc(hidden)]
-#[derive(Clone, Debug, Default)]
+/// `AutoSearcher` uses one of these to infer the flakiness of its votes, but it can also be used
+/// directly, e.g. to diagnose whether a test is noisier on one side of a change than the other.
+#[derive(Clone, Debug)]
 pub struct FlakinessTracker {
     /// Maps index to number of number of tails votes and number of heads votes.
     votes: BTreeMap<usize, (usize, usize)>,
     total_heads: usize,
     total_tails: usize,
+    /// Strength of the Bayesian prior used by `flakiness`. See `flakiness_with_prior`.
+    prior_strength: f64,
+}
+
+/// Default strength of the prior used by `FlakinessTracker::flakiness`, and the prior used for
+/// split flakiness estimates.
+pub(crate) const DEFAULT_PRIOR_STRENGTH: f64 = 1.0;
+
+impl Default for FlakinessTracker {
+    fn default() -> Self {
+        FlakinessTracker::with_prior(DEFAULT_PRIOR_STRENGTH)
+    }
 }
 
 impl FlakinessTracker {
+    /// Creates a new tracker whose `flakiness` uses a prior of the given strength. The default
+    /// strength is 1.0.
+    pub fn with_prior(prior_strength: f64) -> Self {
+        FlakinessTracker {
+            votes: BTreeMap::new(),
+            total_heads: 0,
+            total_tails: 0,
+            prior_strength,
+        }
+    }
+
     /// Adds a vote to the internal statistics. With low flakiness, false votes are expected to have
     /// smaller indices than true votes.
     pub fn report(&mut self, index: usize, heads: bool) {
@@ -40,6 +78,27 @@ impl FlakinessTracker {
         }
     }
 
+    /// Records an indeterminate result as if the index had voted both ways, which counts as an
+    /// inversion. `total_heads` and `total_tails` are not affected.
+    pub fn report_indeterminate(&mut self, index: usize) {
+        let value = self.votes.entry(index).or_insert((0, 0));
+        value.0 += 1;
+        value.1 += 1;
+    }
+
+    /// Adds the votes from another tracker, e.g. one which tracked retries on a different machine.
+    /// The result is the same as if this tracker had seen all of the votes. This tracker's prior
+    /// strength is kept.
+    pub fn merge(&mut self, other: &FlakinessTracker) {
+        for (index, (tails, heads)) in &other.votes {
+            let value = self.votes.entry(*index).or_insert((0, 0));
+            value.0 += tails;
+            value.1 += heads;
+        }
+        self.total_heads += other.total_heads;
+        self.total_tails += other.total_tails;
+    }
+
     /// Returns the number of inversions and four times the number of "random" inverions.
     /// The "random" inversions is the number of inversions that would be expected if the votes were
     /// cast at the same indices but were randomly half heads and half tails. It is scaled by four
@@ -59,6 +118,14 @@ impl FlakinessTracker {
         (inverted, random_inversions)
     }
 
+    /// Returns `(index, tails, heads)` for each index which received a vote, in order of increasing
+    /// index. Indeterminate results count as one vote each way.
+    pub fn votes(&self) -> impl Iterator<Item = (usize, usize, usize)> + '_ {
+        self.votes
+            .iter()
+            .map(|(index, (tails, heads))| (*index, *tails, *heads))
+    }
+
     /// Returns the number of true votes.
     pub fn total_heads(&self) -> usize {
         self.total_heads
@@ -77,19 +144,63 @@ impl FlakinessTracker {
     /// Returns the estimated flakiness based on the votes, where 0.0 is deterministic and 1.0 is
     /// complete randomness.
     pub fn flakiness(&self) -> f64 {
+        self.flakiness_with_prior(self.prior_strength)
+    }
+
+    /// Same as `flakiness`, but with a prior of the given strength. The prior acts as a pseudo-count
+    /// of votes seen before any real ones, which on their own give a flakiness of 0.5. Larger values
+    /// keep the estimate near 0.5 for longer, and smaller values let it move more quickly.
+    pub fn flakiness_with_prior(&self, prior_strength: f64) -> f64 {
         // The formula used here is provided by flakiness_tuner.rs (and fit by
         // recovered_flakiness.plt), plus some numerical niceties and a Bayesian prior.
         // ar^2 + br - f = 0
         // (-b + sqrt(b^2 + 4af))/(2a)
         let (inv, rand_inv) = self.inversions();
-        let r = (inv + 1) as f64 / (rand_inv as f64 + 7.6143);
+        let r = (inv as f64 + prior_strength) / (rand_inv as f64 + 7.6143 * prior_strength);
         (0.1698 * r * r + 3.7844 * r).min(1.0).max(0.0)
     }
+
+    /// Returns separate flakiness estimates for the two sides of `pivot`, assuming that `pivot` is
+    /// the first index which should vote heads. `.0` is the estimate for indices below `pivot`, and
+    /// `.1` is the estimate for indices at or above `pivot`.
+    ///
+    /// A flaky test returns a random result, so on each side the fraction of votes landing on the
+    /// wrong side is half the flakiness. `prior` is the number of pseudo-votes added to each side,
+    /// which on their own give a flakiness of 0.5. This is useful when the test is noisier on one
+    /// side of the change than the other, e.g. for choosing asymmetric stiffness.
+    pub fn split_flakiness(&self, pivot: usize, prior: f64) -> (f64, f64) {
+        let (before, after) = self.split_votes(pivot);
+        (split_estimate(before, prior), split_estimate(after, prior))
+    }
+
+    /// Returns the number of wrong votes and the total number of votes below `pivot` and at or
+    /// above `pivot`, as used by `split_flakiness`.
+    pub(crate) fn split_votes(&self, pivot: usize) -> ((usize, usize), (usize, usize)) {
+        let mut before = (0, 0);
+        let mut after = (0, 0);
+        for (index, (tails, heads)) in &self.votes {
+            if *index < pivot {
+                before.0 += heads;
+                before.1 += heads + tails;
+            } else {
+                after.0 += tails;
+                after.1 += heads + tails;
+            }
+        }
+        (before, after)
+    }
+}
+
+/// Converts one side of `FlakinessTracker::split_votes` into a flakiness estimate. See
+/// `FlakinessTracker::split_flakiness`.
+pub(crate) fn split_estimate((wrong, votes): (usize, usize), prior: f64) -> f64 {
+    (2.0 * (wrong as f64 + prior / 4.0) / (votes as f64 + prior)).clamp(0.0, 1.0)
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::test_rng::TestRng;
 
     #[test]
     fn empty() {
@@ -290,4 +401,94 @@ mod tests {
             tracker.flakiness()
         );
     }
+
+    #[test]
+    fn indeterminate() {
+        let mut tracker = FlakinessTracker::default();
+        tracker.report(0, false);
+        tracker.report(1, true);
+        let flakiness = tracker.flakiness();
+        tracker.report_indeterminate(1);
+        assert_eq!(tracker.inversions(), (2, 13));
+        assert!(tracker.flakiness() > flakiness);
+        assert_eq!(tracker.total_votes(), 2);
+    }
+
+    #[test]
+    fn prior_strength() {
+        let mut strong = FlakinessTracker::with_prior(1000.0);
+        let mut weak = FlakinessTracker::with_prior(0.01);
+        let mut default = FlakinessTracker::default();
+        for i in 0..5 {
+            strong.report(i, i >= 3);
+            weak.report(i, i >= 3);
+            default.report(i, i >= 3);
+        }
+        assert_eq!(default.flakiness(), default.flakiness_with_prior(1.0));
+        assert!(
+            (strong.flakiness() - 0.5).abs() < 0.01,
+            "flakiness = {}",
+            strong.flakiness()
+        );
+        assert!(weak.flakiness() < 0.01, "flakiness = {}", weak.flakiness());
+        assert!(weak.flakiness() < default.flakiness());
+        assert!(default.flakiness() < strong.flakiness());
+    }
+
+    #[test]
+    fn merge() {
+        let mut all = FlakinessTracker::default();
+        let mut first = FlakinessTracker::default();
+        let mut second = FlakinessTracker::default();
+        let mut rng = TestRng::new(777);
+        for i in 0..200 {
+            let index = rng.below(50);
+            let heads = (index >= 20) != (rng.below(8) == 0);
+            let tracker = if i % 3 == 0 { &mut first } else { &mut second };
+            if i % 17 == 0 {
+                all.report_indeterminate(index);
+                tracker.report_indeterminate(index);
+            } else {
+                all.report(index, heads);
+                tracker.report(index, heads);
+            }
+        }
+        first.merge(&second);
+        assert_eq!(first.inversions(), all.inversions());
+        assert_eq!(first.total_heads(), all.total_heads());
+        assert_eq!(first.total_tails(), all.total_tails());
+        assert_eq!(first.flakiness(), all.flakiness());
+    }
+
+    #[test]
+    fn split_flakiness_empty() {
+        let tracker = FlakinessTracker::default();
+        assert_eq!(tracker.split_flakiness(5, 1.0), (0.5, 0.5));
+    }
+
+    #[test]
+    fn split_flakiness_noisy_before() {
+        let mut tracker = FlakinessTracker::default();
+        for i in 0..100 {
+            // Below the pivot, every other vote is random.
+            tracker.report(i % 10, i % 4 == 0);
+            tracker.report(10 + i % 10, true);
+        }
+        let (before, after) = tracker.split_flakiness(10, 1.0);
+        assert!((before - 0.5).abs() < 0.05, "before = {}", before);
+        assert!(after < 0.01, "after = {}", after);
+    }
+
+    #[test]
+    fn split_flakiness_noisy_after() {
+        let mut tracker = FlakinessTracker::default();
+        for i in 0..100 {
+            tracker.report(i % 10, false);
+            // At or above the pivot, every vote is random.
+            tracker.report(10 + i % 10, i % 2 == 0);
+        }
+        let (before, after) = tracker.split_flakiness(10, 1.0);
+        assert!(before < 0.01, "before = {}", before);
+        assert!((after - 1.0).abs() < 0.05, "after = {}", after);
+    }
 }
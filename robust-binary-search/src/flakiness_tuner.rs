@@ -12,15 +12,15 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+use rand::rngs::StdRng;
 use rand::Rng;
+use rand::SeedableRng;
 use robust_binary_search::flakiness_tracker::*;
 use robust_binary_search::*;
-use std::cell::RefCell;
 use std::env;
 use std::error::Error;
 use std::fs::File;
 use std::io::Write;
-use std::ops::DerefMut;
 use std::process;
 
 fn sample_inversions<R: Rng>(rng: &mut R, p: f64) -> (usize, usize) {
@@ -48,20 +48,34 @@ fn sample_inversions<R: Rng>(rng: &mut R, p: f64) -> (usize, usize) {
     tracker.inversions()
 }
 
+/// Removes `--seed <n>` from the arguments, if present, and returns a random number generator
+/// seeded with it, so that runs can be reproduced. Without `--seed`, the generator is seeded from
+/// the operating system.
+fn rng_from_args(args: &mut Vec<String>) -> Result<StdRng, Box<dyn Error>> {
+    match args.iter().position(|arg| arg == "--seed") {
+        None => Ok(StdRng::from_entropy()),
+        Some(i) => {
+            let seed = args.get(i + 1).ok_or("--seed requires a value")?.parse()?;
+            args.drain(i..i + 2);
+            Ok(StdRng::seed_from_u64(seed))
+        }
+    }
+}
+
 fn main() -> Result<(), Box<dyn Error>> {
-    let args: Vec<String> = env::args().collect();
+    let mut args: Vec<String> = env::args().collect();
+    let mut rng = rng_from_args(&mut args)?;
     if args.len() != 2 {
-        println!("Usage: main <output_file>");
+        println!("Usage: main [--seed <seed>] <output_file>");
         process::exit(1);
     }
     let mut f = File::create(&args[1])?;
-    let rng = RefCell::new(rand::thread_rng());
     for i in 0..80 {
         let p = i as f64 / 100.0;
         let mut inv_total = 0;
         let mut rand_inv_total = 0;
         for _ in 0..10000 {
-            let (inv, rand_inv) = sample_inversions(rng.borrow_mut().deref_mut(), p);
+            let (inv, rand_inv) = sample_inversions(&mut rng, p);
             inv_total += inv;
             rand_inv_total += rand_inv;
         }
@@ -70,3 +84,20 @@ fn main() -> Result<(), Box<dyn Error>> {
     }
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn same_seed_same_inversions() {
+        let mut rng1 = StdRng::seed_from_u64(7);
+        let mut rng2 = StdRng::seed_from_u64(7);
+        for p in &[0.0, 0.1, 0.5] {
+            assert_eq!(
+                sample_inversions(&mut rng1, *p),
+                sample_inversions(&mut rng2, *p)
+            );
+        }
+    }
+}
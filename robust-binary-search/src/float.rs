@@ -0,0 +1,73 @@
+// Copyright 2020 Google LLC
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Conversions used by code which is generic over the weight type. For `f64`, each of these is
+//! exactly equivalent to the corresponding `as` cast, so generic code reproduces the results of
+//! code written directly for `f64`.
+
+use num_traits::Float;
+
+/// Converts a constant or an `f64` argument to the weight type.
+///
+/// # Panics
+///
+/// Panics if the value is out of range for the weight type.
+pub(crate) fn float<F: Float>(x: f64) -> F {
+    F::from(x).unwrap()
+}
+
+/// Converts a count to the weight type, like `n as f64`.
+pub(crate) fn count<F: Float>(n: usize) -> F {
+    F::from(n).unwrap()
+}
+
+/// Converts a weight to `f64`.
+pub(crate) fn to_f64<F: Float>(x: F) -> f64 {
+    x.to_f64().unwrap()
+}
+
+/// Converts a weight to an index, like `x as usize`: the fractional part is dropped, out of range
+/// values saturate, and NaN becomes zero.
+pub(crate) fn to_index<F: Float>(x: F) -> usize {
+    match x.to_usize() {
+        Some(index) => index,
+        None if x > F::zero() => usize::MAX,
+        None => 0,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn to_index_matches_as() {
+        for x in &[
+            0.0,
+            -0.0,
+            0.5,
+            1.0,
+            2.9,
+            -0.5,
+            -3.0,
+            1e30,
+            f64::INFINITY,
+            f64::NEG_INFINITY,
+            f64::NAN,
+        ] {
+            assert_eq!(to_index(*x), *x as usize, "{}", x);
+            assert_eq!(to_index(*x as f32), *x as f32 as usize, "{}", x);
+        }
+    }
+}
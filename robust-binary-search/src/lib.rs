@@ -12,22 +12,64 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+#![cfg_attr(not(feature = "std"), no_std)]
+
+extern crate alloc;
+
+use alloc::collections::BTreeSet;
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::cell::Cell;
+use core::cell::Ref;
+use core::cell::RefCell;
+use core::cmp;
+use core::fmt;
+use core::ops::Bound;
+use core::ops::RangeBounds;
 use log::trace;
+use log::warn;
+use num_traits::Float;
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+#[cfg(feature = "std")]
 use std::borrow::Borrow;
-use std::cmp;
+#[cfg(feature = "std")]
 use std::collections::HashSet;
+#[cfg(feature = "std")]
+use std::error::Error;
+#[cfg(feature = "std")]
 use std::rc::Rc;
 
-#[doc(hidden)]
+mod float;
+use float::{count, float, to_f64, to_index};
 pub mod flakiness_tracker;
 use flakiness_tracker::*;
-mod range_map;
+pub mod range_map;
 use range_map::*;
+mod stopping_criterion;
+pub use stopping_criterion::*;
+mod stiffness_calculator;
+pub use stiffness_calculator::*;
+#[cfg(feature = "simulation")]
+mod simulation;
+#[cfg(feature = "simulation")]
+pub use simulation::simulate_steps;
+#[cfg(test)]
+mod test_rng;
+#[cfg(feature = "wasm")]
+mod wasm;
+#[cfg(feature = "wasm")]
+pub use wasm::WasmSearcher;
 
+#[cfg(feature = "std")]
 mod dag;
+#[cfg(feature = "std")]
+pub use dag::{Dag, DagError, DagNode};
 
 /// Reference to a node in a CompressedDag.
-#[derive(Default, Copy, Clone, Debug, PartialEq, Eq)]
+#[cfg(feature = "std")]
+#[derive(Default, Copy, Clone, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct CompressedDagNodeRef {
     /// Index of the segment in the CompressedDag.
     pub segment: usize,
@@ -35,20 +77,25 @@ pub struct CompressedDagNodeRef {
     pub index: usize,
 }
 
+#[cfg(feature = "std")]
 #[deprecated(note = "Use CompressedDagNodeRef instead.")]
 pub type CompressedDAGNodeRef = CompressedDagNodeRef;
 
 /// A segment in a CompressedDag. This is a node in a Dag but corresponds to a linear sequence of
 /// nodes in a conceptual expanded graph. The size is the number of nodes in the expanded graph
 /// represented by this segment.
+#[cfg(feature = "std")]
 #[derive(Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct CompressedDagSegment {
     len: usize,
 }
 
+#[cfg(feature = "std")]
 #[deprecated(note = "Use CompressedDagSegment instead.")]
 pub type CompressedDAGSegment = CompressedDagSegment;
 
+#[cfg(feature = "std")]
 impl CompressedDagSegment {
     /// Creates a CompressedDagSegment of a given size.
     pub fn new(len: usize) -> Self {
@@ -99,48 +146,62 @@ impl CompressedDagSegment {
 ///
 /// This representation allows many common graphs to be represented in a more compact form than
 /// directly as a Dag.
+///
+/// With the `serde` feature, a CompressedDag is serialized as a list of segments and their inputs.
+/// Deserialization rebuilds the ancestor sets and fails if the inputs are not sorted topologically.
+#[cfg(feature = "std")]
 pub type CompressedDag = dag::Dag<CompressedDagSegment>;
 
+#[cfg(feature = "std")]
+mod compressed_dag;
+#[cfg(feature = "std")]
+pub use compressed_dag::{BuildError, CompressedDagBuilder, DotStyle, SplitMapping};
+
+#[cfg(feature = "std")]
 mod compressed_dag_flakiness_tracker;
-use compressed_dag_flakiness_tracker::*;
+#[cfg(feature = "std")]
+pub use compressed_dag_flakiness_tracker::*;
 
 /// Finds the index such that the sum of values at indices [0, i] (inclusive) is as close as
 /// possible to the argument. Returns the index and the sum.
-fn confidence_percentile_nearest(range_map: &RangeMap<f64>, percentile: f64) -> (usize, f64) {
-    let mut sum = 0.0;
+// Without std, this and `confidence_percentile_ceil` are only used by Searcher, which uses prefix
+// sums instead if they're enabled.
+#[cfg(any(feature = "std", not(feature = "prefix_sums")))]
+fn confidence_percentile_nearest<F: Float>(range_map: &RangeMap<F>, percentile: F) -> (usize, F) {
+    let mut sum = F::zero();
     let mut index = 0;
     let mut best_index = 0;
-    let mut best_percentile = f64::NEG_INFINITY;
+    let mut best_percentile = F::neg_infinity();
     for w in range_map.ranges() {
-        let delta = w.len() as f64 * w.value();
+        let delta = count::<F>(w.len()) * *w.value();
         trace!(
             "percentile = {}, sum = {}, w.value = {}",
-            percentile,
-            sum,
-            w.value()
+            to_f64(percentile),
+            to_f64(sum),
+            to_f64(*w.value())
         );
         trace!(
             "(percentile - sum) / w.value() - 0.5 = {}",
-            (percentile - sum) / w.value() - 0.5
+            to_f64((percentile - sum) / *w.value() - float(0.5))
         );
         let ix = index
             + cmp::min(
                 w.len() - 1,
-                ((percentile - sum) / w.value() - 0.5).max(0.0) as usize,
+                to_index(((percentile - sum) / *w.value() - float(0.5)).max(F::zero())),
             );
-        let ix_percentile = sum + (ix - index + 1) as f64 * w.value();
-        trace!("ix = {} ix_percentile = {}", ix, ix_percentile);
+        let ix_percentile = sum + count::<F>(ix - index + 1) * *w.value();
+        trace!("ix = {} ix_percentile = {}", ix, to_f64(ix_percentile));
         if (ix_percentile - percentile).abs() < (best_percentile - percentile).abs() {
             best_index = ix;
             best_percentile = ix_percentile;
         }
-        sum += delta;
+        sum = sum + delta;
         index += w.len();
     }
-    assert!(best_percentile > f64::NEG_INFINITY);
+    assert!(best_percentile > F::neg_infinity());
     trace!(
         "confidence_percentile_nearest returning {:?}",
-        (best_index, best_percentile)
+        (best_index, to_f64(best_percentile))
     );
     (best_index, best_percentile)
 }
@@ -148,101 +209,865 @@ fn confidence_percentile_nearest(range_map: &RangeMap<f64>, percentile: f64) ->
 /// Finds the smallest index such that the sum of values at indices [0, i] (inclusive) is greater
 /// than or equal to the argument. Returns the index and the sum. If no sum is greater than or equal
 /// to the argument, returns the last index and the sum over all values.
-fn confidence_percentile_ceil(range_map: &RangeMap<f64>, percentile: f64) -> (usize, f64) {
-    let mut sum = 0.0;
+#[cfg(any(feature = "std", not(feature = "prefix_sums")))]
+fn confidence_percentile_ceil<F: Float>(range_map: &RangeMap<F>, percentile: F) -> (usize, F) {
+    let mut sum = F::zero();
     let mut index = 0;
     for w in range_map.ranges() {
-        let delta = w.len() as f64 * w.value();
+        let delta = count::<F>(w.len()) * *w.value();
         if sum + delta >= percentile {
-            let ix = index + ((percentile - sum) / w.value() - 1e-9) as usize;
-            let ret = (ix, sum + (ix - index + 1) as f64 * w.value());
-            trace!("confidence_percentile_ceil returning {:?}", ret);
+            let ix = index + to_index((percentile - sum) / *w.value() - float(1e-9));
+            let ret = (ix, sum + count::<F>(ix - index + 1) * *w.value());
+            trace!(
+                "confidence_percentile_ceil returning {:?}",
+                (ret.0, to_f64(ret.1))
+            );
             return ret;
         }
-        sum += delta;
+        sum = sum + delta;
         index += w.len();
     }
     (range_map.len() - 1, sum)
 }
 
-// Does not normalize.
-fn report_range(weights: &mut RangeMap<f64>, index: usize, heads: bool, stiffness: f64) {
-    if heads {
-        for w in weights.split(index).0 {
-            *w.value_mut() *= 1.0 + stiffness;
+/// Finds the largest index such that the sum of values at indices [0, i] (inclusive) is less than
+/// or equal to the argument. Returns the index and the sum. If no sum is less than or equal to the
+/// argument, returns the first index and its value.
+fn confidence_percentile_floor<F: Float>(range_map: &RangeMap<F>, percentile: F) -> (usize, F) {
+    let mut sum = F::zero();
+    let mut index = 0;
+    let mut ret = (0, *range_map.range_for_index(0).value());
+    for w in range_map.ranges() {
+        let delta = count::<F>(w.len()) * *w.value();
+        if sum + delta <= percentile {
+            ret = (index + w.len() - 1, sum + delta);
+        } else {
+            let n = to_index((percentile - sum) / *w.value() + float(1e-9));
+            if n > 0 {
+                ret = (index + n - 1, sum + count::<F>(n) * *w.value());
+            }
+            break;
         }
-        let (mut left, _right) = weights.split(index + 1);
-        *left.next_back().unwrap().value_mut() *= 1.0 + stiffness;
+        sum = sum + delta;
+        index += w.len();
+    }
+    trace!(
+        "confidence_percentile_floor returning {:?}",
+        (ret.0, to_f64(ret.1))
+    );
+    ret
+}
+
+// Multiplies the weight at each index `i` by `1 + stiffness` if the vote agrees with a transition
+// at `i`, i.e. `i <= index` for heads and `i > index` for tails, and leaves the other weights
+// unchanged. Every index is scaled exactly once or not at all. Does not normalize. Returns the
+// increase in the sum of the weights.
+fn report_range<F: Float>(weights: &mut RangeMap<F>, index: usize, heads: bool, stiffness: F) -> F {
+    let mut added = F::zero();
+    let mut scale = |w: &mut RangeMapEntry<F>| {
+        added = added + *w.value() * count::<F>(w.len()) * stiffness;
+        *w.value_mut() = *w.value() * (F::one() + stiffness);
+    };
+    let (left, right) = weights.split(index + 1);
+    if heads {
+        left.for_each(&mut scale);
     } else {
-        let _ = weights.split(index);
-        let (_left, right) = weights.split(index + 1);
-        for w in right {
-            *w.value_mut() *= 1.0 + stiffness;
+        right.for_each(&mut scale);
+    }
+    added
+}
+
+/// Returns the sum of the individual values in `weights`.
+fn weight_sum<F: Float>(weights: &RangeMap<F>) -> F {
+    weights
+        .ranges()
+        .fold(F::zero(), |sum, w| sum + *w.value() * count::<F>(w.len()))
+}
+
+/// Scales the weights so that they sum to 1.
+fn normalize<F: Float>(weights: &mut RangeMap<F>) {
+    let weight_sum = weight_sum(weights);
+    for w in weights.ranges_mut() {
+        *w.value_mut() = *w.value() / weight_sum;
+    }
+}
+
+/// Determines which index is chosen when no index splits the remaining probability exactly in half.
+/// The default is `Nearest`, which is what `Searcher::next_index` uses.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Rounding {
+    /// Chooses the index whose cumulative likelihood is closest to one half.
+    #[default]
+    Nearest,
+    /// Chooses the largest index whose cumulative likelihood is at most one half.
+    Down,
+    /// Chooses the smallest index whose cumulative likelihood is at least one half.
+    Up,
+}
+
+/// A hard constraint on the location of the transition. See `Searcher::add_constraint`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Constraint {
+    /// Every index less than the given index is known to be good (i.e. would vote false).
+    GoodBelow(usize),
+    /// Every index greater than the given index is known to be bad (i.e. would vote true).
+    BadAbove(usize),
+}
+
+/// Error returned by `Searcher::add_constraint`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ConstraintError {
+    /// The constraint's index is not less than the searcher's length.
+    OutOfRange,
+    /// The constraint contradicts a previously added constraint, leaving no possible index.
+    Conflict,
+}
+
+impl fmt::Display for ConstraintError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ConstraintError::OutOfRange => write!(f, "constraint index out of range"),
+            ConstraintError::Conflict => {
+                write!(f, "constraint conflicts with existing constraints")
+            }
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl Error for ConstraintError {}
+
+/// Error returned by `Searcher::try_report`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ReportError {
+    /// The flakiness was not finite or was outside the range (0.0, 1.0].
+    InvalidFlakiness(f64),
+    /// The index was not a testable index, i.e. it was not less than the Searcher's length.
+    IndexOutOfRange {
+        /// The offending index.
+        index: usize,
+        /// The Searcher's length.
+        len: usize,
+    },
+}
+
+impl fmt::Display for ReportError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ReportError::InvalidFlakiness(flakiness) => write!(
+                f,
+                "invalid flakiness {}, must be in the range (0.0, 1.0]",
+                flakiness
+            ),
+            ReportError::IndexOutOfRange { index, len } => write!(
+                f,
+                "index {} out of range for Searcher of length {}",
+                index, len
+            ),
         }
     }
 }
 
+#[cfg(feature = "std")]
+impl Error for ReportError {}
+
+/// The evidence for or against a single index, returned by `Searcher::explain`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Explanation {
+    /// The index being explained.
+    pub index: usize,
+    /// The likelihood of the index.
+    pub likelihood: f64,
+    /// The number of indices which are more likely, so the most likely index has rank 0. Indices
+    /// with equal likelihoods share a rank.
+    pub rank: usize,
+    /// The total likelihood of the indices below this one.
+    pub mass_below: f64,
+    /// The total likelihood of the indices above this one, up to and including `len`.
+    pub mass_above: f64,
+    /// The likelihood of the index minus the greatest likelihood of any other index. This is
+    /// positive only for the single most likely index, where it is the margin over the runner-up,
+    /// and otherwise says how far the index trails the leader.
+    pub margin: f64,
+}
+
+/// Smallest flakiness used by `Searcher::report`. Smaller values are clamped to this.
+const MIN_FLAKINESS: f64 = 1e-6;
+
+/// Clamps flakiness into [`MIN_FLAKINESS`, 1.0], treating NaN as 1.0.
+fn clamp_flakiness(flakiness: f64) -> f64 {
+    if flakiness.is_nan() {
+        1.0
+    } else {
+        flakiness.clamp(MIN_FLAKINESS, 1.0)
+    }
+}
+
 /// Performs a robust binary search over a linear range.
+///
+/// The weights are stored as `F`, which defaults to `f64`. For huge ranges, `Searcher<f32>` (created
+/// with `Searcher::<f32>::with_len`) halves the memory used by the weights at the cost of
+/// precision. The API uses `f64` regardless of `F`.
+///
+/// With the `serde` feature, Searchers can be serialized and deserialized, e.g. to persist a
+/// search across processes. The stiffness function set with `set_stiffness_fn` is not serialized;
+/// deserialized Searchers use `optimal_stiffness`.
+#[derive(Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct Searcher<F: Float = f64> {
+    /// Weights of each index. Normalization is deferred until the weights are read (see
+    /// `weights()`) or are at risk of overflowing, so that consecutive reports are cheap.
+    weights: RefCell<RangeMap<F>>,
+    /// Whether `weights` currently sums to 1.
+    normalized: Cell<bool>,
+    /// Approximate sum of `weights`, used to detect when they need to be normalized early.
+    total: Cell<F>,
+    skips: BTreeSet<usize>,
+    len: usize,
+    trace: Option<Vec<TracedVote>>,
+    #[cfg_attr(feature = "serde", serde(skip, default = "default_stiffness_fn"))]
+    stiffness_fn: fn(f64) -> f64,
+    /// Tightest `Constraint::GoodBelow`, if any.
+    good_below: Option<usize>,
+    /// Tightest `Constraint::BadAbove`, if any.
+    bad_above: Option<usize>,
+    /// Number of votes reported with `report_indeterminate`.
+    indeterminate: usize,
+    /// Cumulative sums of the normalized weights, built on demand and cleared by `normalize`.
+    #[cfg(feature = "prefix_sums")]
+    #[cfg_attr(feature = "serde", serde(skip))]
+    prefix_sums: RefCell<Option<PrefixSumRangeMap<F>>>,
+}
+
+/// Returns `optimal_stiffness`, for use as a serde default.
+#[cfg(feature = "serde")]
+fn default_stiffness_fn() -> fn(f64) -> f64 {
+    optimal_stiffness
+}
+
+/// A vote recorded by a Searcher with tracing enabled.
+#[derive(Clone, Copy, Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+struct TracedVote {
+    index: usize,
+    heads: bool,
+    stiffness: f64,
+}
+
+/// A snapshot of the votes, skips, and constraints applied to a Searcher, created by
+/// `Searcher::checkpoint` and applied with `Searcher::restore`.
 #[derive(Clone, Debug)]
-pub struct Searcher {
-    weights: RangeMap<f64>,
-    skips: HashSet<usize>,
+pub struct SearcherCheckpoint<F: Float = f64> {
+    weights: RangeMap<F>,
+    normalized: bool,
+    total: F,
+    skips: BTreeSet<usize>,
     len: usize,
+    trace: Option<Vec<TracedVote>>,
+    good_below: Option<usize>,
+    bad_above: Option<usize>,
+    indeterminate: usize,
 }
 
 impl Searcher {
-    /// Creates a new Searcher over a range with the given number of testable indices.
+    /// Creates a new Searcher over a range with the given number of testable indices, with `f64`
+    /// weights. See `with_len` for other weight types.
     pub fn new(len: usize) -> Self {
+        Searcher::with_len(len)
+    }
+
+    /// Creates a new Searcher over a range with the given number of testable indices, with the
+    /// likelihood of each index proportional to `prior(index)` rather than uniform. As with
+    /// `likelihood`, `prior` is called for every index from 0 to len, inclusive; index `len`
+    /// represents every testable index being good. The prior does not need to be normalized.
+    ///
+    /// # Panics
+    ///
+    /// Panics if any prior value is negative or not finite, or if the prior sums to zero.
+    pub fn with_prior<F: Fn(usize) -> f64>(len: usize, prior: F) -> Self {
+        let mut searcher = Searcher::new(len);
+        *searcher.weights.get_mut() = RangeMap::from_fn(len + 1, |index| {
+            let value = prior(index);
+            assert!(
+                value >= 0.0 && value.is_finite(),
+                "invalid prior {} for index {}",
+                value,
+                index
+            );
+            value
+        });
+        let sum: f64 = searcher
+            .weights
+            .get_mut()
+            .ranges()
+            .map(|w| w.value() * w.len() as f64)
+            .sum();
+        assert!(sum > 0.0, "prior must not sum to zero");
+        searcher.normalize();
+        searcher
+    }
+
+    /// Returns true if the given stopping criterion says the search has converged.
+    pub fn is_converged(&self, criterion: &dyn StoppingCriterion) -> bool {
+        criterion.should_stop(self)
+    }
+}
+
+impl<F: Float> Searcher<F> {
+    /// Creates a new Searcher over a range with the given number of testable indices, with
+    /// weights of type `F`. For `f64`, this is the same as `new`.
+    pub fn with_len(len: usize) -> Self {
         Searcher {
-            weights: RangeMap::new(len + 1, 1.0 / (len as f64 + 1.0)),
+            weights: RefCell::new(RangeMap::new(
+                len + 1,
+                F::one() / (count::<F>(len) + F::one()),
+            )),
+            normalized: Cell::new(true),
+            total: Cell::new(F::one()),
             len,
-            skips: HashSet::default(),
+            skips: BTreeSet::default(),
+            trace: None,
+            stiffness_fn: optimal_stiffness,
+            good_below: None,
+            bad_above: None,
+            indeterminate: 0,
+            #[cfg(feature = "prefix_sums")]
+            prefix_sums: RefCell::new(None),
+        }
+    }
+
+    /// Returns the number of testable indices. If this is non-zero, `next_index` and `best_index`
+    /// always return values less than it.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Returns true if there are no testable indices.
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Adds a hard constraint on the location of the transition. Unlike votes, constraints are
+    /// never outweighed: the likelihood of every index ruled out by a constraint is set to zero
+    /// and stays zero after every subsequent `report`, no matter how the votes go.
+    ///
+    /// Returns an error (and leaves the searcher unchanged) if the constraint's index is out of
+    /// range or if the constraint conflicts with previously added constraints, e.g.
+    /// `GoodBelow(50)` together with `BadAbove(30)`.
+    pub fn add_constraint(&mut self, constraint: Constraint) -> Result<(), ConstraintError> {
+        let (good_below, bad_above) = match constraint {
+            Constraint::GoodBelow(index) => (
+                Some(self.good_below.map_or(index, |g| cmp::max(g, index))),
+                self.bad_above,
+            ),
+            Constraint::BadAbove(index) => (
+                self.good_below,
+                Some(self.bad_above.map_or(index, |b| cmp::min(b, index))),
+            ),
+        };
+        match constraint {
+            Constraint::GoodBelow(index) | Constraint::BadAbove(index) if index >= self.len => {
+                return Err(ConstraintError::OutOfRange);
+            }
+            _ => (),
+        }
+        if let (Some(good_below), Some(bad_above)) = (good_below, bad_above) {
+            // The first bad index must be in good_below..=bad_above + 1.
+            if good_below > bad_above + 1 {
+                return Err(ConstraintError::Conflict);
+            }
+        }
+        self.good_below = good_below;
+        self.bad_above = bad_above;
+        self.apply_constraints();
+        Ok(())
+    }
+
+    /// Zeroes the weights of indices ruled out by constraints, and normalizes if that changed
+    /// anything.
+    fn apply_constraints(&mut self) {
+        let mut changed = false;
+        let mut zero = |w: &mut RangeMapEntry<F>| {
+            changed |= *w.value() != F::zero();
+            *w.value_mut() = F::zero();
+        };
+        let weights = self.weights.get_mut();
+        if let Some(good_below) = self.good_below {
+            for w in weights.split(good_below).0 {
+                zero(w);
+            }
+        }
+        if let Some(bad_above) = self.bad_above {
+            for w in weights.split(bad_above + 2).1 {
+                zero(w);
+            }
+        }
+        if changed {
+            self.normalize();
+        }
+    }
+
+    /// Scales the weights so that they sum to 1.
+    fn normalize(&self) {
+        normalize(&mut self.weights.borrow_mut());
+        self.normalized.set(true);
+        self.total.set(F::one());
+        #[cfg(feature = "prefix_sums")]
+        self.prefix_sums.replace(None);
+    }
+
+    /// Normalizes now if the weights are at risk of overflowing or underflowing.
+    fn normalize_if_needed(&mut self) {
+        // The bounds are 1e-100 and 1e100 for f64. Narrower types use the square roots of their
+        // smallest and largest positive values instead.
+        let min = 1e-100f64.max(to_f64(F::min_positive_value()).sqrt());
+        let max = 1e100f64.min(to_f64(F::max_value()).sqrt());
+        if !(min..=max).contains(&to_f64(self.total.get())) {
+            self.normalize();
+        }
+    }
+
+    /// Returns the weights, normalizing them first if needed.
+    fn weights(&self) -> Ref<'_, RangeMap<F>> {
+        if !self.normalized.get() {
+            self.normalize();
+        }
+        self.weights.borrow()
+    }
+
+    /// Returns cumulative sums of the normalized weights.
+    #[cfg(feature = "prefix_sums")]
+    fn prefix_sums(&self) -> Ref<'_, PrefixSumRangeMap<F>> {
+        let weights = self.weights();
+        if self.prefix_sums.borrow().is_none() {
+            self.prefix_sums
+                .replace(Some(PrefixSumRangeMap::new(&weights)));
+        }
+        Ref::map(self.prefix_sums.borrow(), |sums| sums.as_ref().unwrap())
+    }
+
+    /// Returns the index found by `confidence_percentile_ceil` applied to the weights, using
+    /// cumulative sums.
+    #[cfg(feature = "prefix_sums")]
+    fn percentile_ceil(&self, percentile: f64) -> usize {
+        self.prefix_sums().index_at_cumulative(float(percentile)).0
+    }
+
+    /// Returns the index found by `confidence_percentile_ceil` applied to the weights.
+    #[cfg(not(feature = "prefix_sums"))]
+    fn percentile_ceil(&self, percentile: f64) -> usize {
+        confidence_percentile_ceil(&self.weights(), float(percentile)).0
+    }
+
+    /// Returns the index found by `confidence_percentile_nearest` applied to the weights, using
+    /// cumulative sums.
+    #[cfg(feature = "prefix_sums")]
+    fn percentile_nearest(&self, percentile: f64) -> usize {
+        self.prefix_sums()
+            .nearest_index_at_cumulative(float(percentile))
+            .0
+    }
+
+    /// Returns the index found by `confidence_percentile_nearest` applied to the weights.
+    #[cfg(not(feature = "prefix_sums"))]
+    fn percentile_nearest(&self, percentile: f64) -> usize {
+        confidence_percentile_nearest(&self.weights(), float(percentile)).0
+    }
+
+    /// Restores the state of a newly created Searcher of the same length, discarding all votes,
+    /// skips, and constraints, while reusing existing allocations where possible. The stiffness
+    /// function is kept, and if tracing is enabled, it stays enabled with an empty trace.
+    pub fn reset(&mut self) {
+        self.weights
+            .get_mut()
+            .fill(F::one() / (count::<F>(self.len) + F::one()));
+        self.normalized.set(true);
+        self.total.set(F::one());
+        #[cfg(feature = "prefix_sums")]
+        self.prefix_sums.replace(None);
+        self.skips.clear();
+        if let Some(trace) = &mut self.trace {
+            trace.clear();
+        }
+        self.good_below = None;
+        self.bad_above = None;
+        self.indeterminate = 0;
+    }
+
+    /// Appends `additional` testable indices to the end of the range, e.g. when new commits land
+    /// on a branch which is being bisected, keeping the evidence from previous votes.
+    ///
+    /// Each new index, and the new index `len`, starts with the weight of the old index `len`
+    /// (i.e. every old index being good) before the weights are renormalized. With a uniform prior,
+    /// this is the weight they would have had if they had been part of the range all along, since
+    /// every previous vote was cast below them. If the search has already converged below the old
+    /// end, the new indices are therefore unlikely.
+    pub fn grow(&mut self, additional: usize) {
+        self.weights.get_mut().grow(additional);
+        self.len += additional;
+        self.normalize();
+    }
+
+    /// Returns a snapshot of the current state, which `restore` can return to. This allows a caller
+    /// to try out a vote, e.g. to estimate how much information testing an index would yield, and
+    /// then undo it.
+    ///
+    /// This copies the weights and skips (and the trace, if tracing is enabled), so it takes time
+    /// and memory linear in the number of weight entries, which grows by at most two per vote, and
+    /// the number of skips. The weights are not normalized first.
+    pub fn checkpoint(&self) -> SearcherCheckpoint<F> {
+        SearcherCheckpoint {
+            weights: self.weights.borrow().clone(),
+            normalized: self.normalized.get(),
+            total: self.total.get(),
+            skips: self.skips.clone(),
+            len: self.len,
+            trace: self.trace.clone(),
+            good_below: self.good_below,
+            bad_above: self.bad_above,
+            indeterminate: self.indeterminate,
         }
     }
 
+    /// Returns to the state captured by `checkpoint`, discarding any votes, skips, and constraints
+    /// added since then. The stiffness function is kept. Tracing is restored to whatever it was
+    /// when the checkpoint was taken. Takes constant time, apart from dropping the current state.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the checkpoint was taken from a Searcher of a different length.
+    pub fn restore(&mut self, checkpoint: SearcherCheckpoint<F>) {
+        assert_eq!(
+            checkpoint.len, self.len,
+            "checkpoint of length {} restored into Searcher of length {}",
+            checkpoint.len, self.len
+        );
+        *self.weights.get_mut() = checkpoint.weights;
+        self.normalized.set(checkpoint.normalized);
+        self.total.set(checkpoint.total);
+        #[cfg(feature = "prefix_sums")]
+        self.prefix_sums.replace(None);
+        self.skips = checkpoint.skips;
+        self.trace = checkpoint.trace;
+        self.good_below = checkpoint.good_below;
+        self.bad_above = checkpoint.bad_above;
+        self.indeterminate = checkpoint.indeterminate;
+    }
+
+    /// Sets the function used by `report` to convert flakiness into stiffness. Defaults to
+    /// `optimal_stiffness`.
+    pub fn set_stiffness_fn(&mut self, stiffness_fn: fn(f64) -> f64) {
+        self.stiffness_fn = stiffness_fn;
+    }
+
+    /// Returns the stiffness that `report` would use for the given flakiness, without reporting
+    /// anything.
+    pub fn stiffness_for(&self, flakiness: f64) -> f64 {
+        (self.stiffness_fn)(flakiness)
+    }
+
     /// Adds an index which cannot be tested. `next_index` will never return this index.
     pub fn add_skip(&mut self, skip: usize) {
         self.skips.insert(skip);
     }
 
-    /// Same as `report` but with a specified stiffness. Only public for use by the tuner, not for
-    /// public use.
+    /// Starts recording reported votes so that they can be replayed with `rebase_votes`. Votes
+    /// reported before this is called are not recorded.
+    pub fn enable_trace(&mut self) {
+        if self.trace.is_none() {
+            self.trace = Some(Vec::new());
+        }
+    }
+
+    /// Creates a new Searcher over a range with `new_len` testable indices and replays the recorded
+    /// votes onto it, with the same stiffness they were originally reported with. Each index
+    /// (including skips) is translated with `index_map`; votes and skips that map to `None` or to
+    /// an index `>= new_len` are dropped. Tracing is enabled on the returned Searcher. Constraints are
+    /// not carried over.
+    ///
+    /// This is useful for re-bisecting after the range has changed, e.g. when new commits are
+    /// appended.
     ///
     /// # Panics
     ///
-    /// Panics if `index >= len`.
-    #[doc(hidden)]
+    /// Panics if `enable_trace` was not called.
+    pub fn rebase_votes<M: Fn(usize) -> Option<usize>>(
+        &self,
+        new_len: usize,
+        index_map: M,
+    ) -> Searcher<F> {
+        let trace = self
+            .trace
+            .as_ref()
+            .expect("rebase_votes requires enable_trace");
+        let mut searcher = Searcher::with_len(new_len);
+        searcher.stiffness_fn = self.stiffness_fn;
+        searcher.enable_trace();
+        for skip in &self.skips {
+            if let Some(new_skip) = index_map(*skip).filter(|i| *i < new_len) {
+                searcher.add_skip(new_skip);
+            }
+        }
+        for vote in trace {
+            if let Some(new_index) = index_map(vote.index).filter(|i| *i < new_len) {
+                searcher.report_with_stiffness(new_index, vote.heads, vote.stiffness);
+            }
+        }
+        searcher
+    }
+
+    /// Same as `report`, but with the stiffness given directly rather than computed from flakiness.
+    /// This is for callers which have tuned their own mapping from flakiness to stiffness.
+    ///
+    /// A true vote multiplies the weight of every index up to and including `index` by
+    /// `1 + stiffness`, and a false vote multiplies the weight of every index after it by
+    /// `1 + stiffness`. The weights are then renormalized as usual. A stiffness of 0.0 leaves the
+    /// likelihoods unchanged, and larger values trust the vote more. `report` is equivalent to
+    /// calling this with `optimal_stiffness(flakiness)` (unless `set_stiffness_fn` was called).
+    ///
+    /// # Panics
+    ///
+    /// Panics if `index >= len` or if `stiffness` is negative or not finite.
     pub fn report_with_stiffness(&mut self, index: usize, heads: bool, stiffness: f64) {
         assert!(index < self.len);
-        report_range(&mut self.weights, index, heads, stiffness);
-        let weight_sum: f64 = self
-            .weights
-            .ranges()
-            .map(|w| w.value() * w.len() as f64)
-            .sum();
-        for w in self.weights.ranges_mut() {
-            *w.value_mut() /= weight_sum;
+        assert!(
+            stiffness >= 0.0 && stiffness.is_finite(),
+            "invalid stiffness {}",
+            stiffness
+        );
+        self.apply_vote(index, heads, stiffness);
+        self.apply_constraints();
+        self.normalize_if_needed();
+    }
+
+    /// Records and applies a single vote without applying constraints or normalizing.
+    fn apply_vote(&mut self, index: usize, heads: bool, stiffness: f64) {
+        if let Some(trace) = &mut self.trace {
+            trace.push(TracedVote {
+                index,
+                heads,
+                stiffness,
+            });
+        }
+        let added = report_range(self.weights.get_mut(), index, heads, float(stiffness));
+        self.total.set(self.total.get() + added);
+        self.normalized.set(false);
+    }
+
+    /// Returns the stiffness for the given flakiness after clamping it into the range (0.0, 1.0].
+    fn checked_stiffness_for(&self, flakiness: f64) -> f64 {
+        let clamped = clamp_flakiness(flakiness);
+        if clamped != flakiness {
+            warn!("Invalid flakiness {} clamped to {}", flakiness, clamped);
         }
+        self.stiffness_for(clamped)
     }
 
     /// Adds a vote to the internal statistics. With low flakiness, false votes are expected to have
     /// smaller indices than true votes. In other words, false means the index is probably too low,
     /// and true means the index is probably correct or too high.
     ///
+    /// Flakiness should be in the range (0.0, 1.0]. Values outside that range are clamped into it
+    /// (with NaN treated as 1.0) and a warning is logged; use `try_report` to detect invalid input.
+    ///
     /// # Panics
     ///
     /// Panics if `index >= len`.
     pub fn report(&mut self, index: usize, heads: bool, flakiness: f64) {
-        self.report_with_stiffness(index, heads, optimal_stiffness(flakiness));
+        self.report_with_stiffness(index, heads, self.checked_stiffness_for(flakiness));
+    }
+
+    /// Same as `report`, but the stiffness is scaled by `reliability`, so that a vote from a less
+    /// trustworthy test (e.g. a quick smoke test rather than a full integration run) moves the
+    /// distribution less. The flakiness is first converted into stiffness as usual (by
+    /// `optimal_stiffness`, unless `set_stiffness_fn` was called), and then multiplied by
+    /// `reliability`. A reliability of 1.0 is the same as `report`.
+    ///
+    /// Note that `optimal_stiffness` is tuned for votes which all share the same flakiness, so
+    /// scaling it down is a heuristic rather than the optimal stiffness for a noisier test. If the
+    /// flakiness of the less reliable test is known, reporting it with `report` is preferable.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `index >= len` or if `reliability` is not in the range (0.0, 1.0].
+    pub fn report_weighted(&mut self, index: usize, heads: bool, flakiness: f64, reliability: f64) {
+        assert!(
+            reliability > 0.0 && reliability <= 1.0,
+            "invalid reliability {}",
+            reliability
+        );
+        let stiffness = self.checked_stiffness_for(flakiness) * reliability;
+        self.report_with_stiffness(index, heads, stiffness);
+    }
+
+    /// Same as `report`, but for a test whose outcome is a degree of badness in `[0.0, 1.0]`
+    /// rather than pass/fail, e.g. a latency which crosses a threshold gradually.
+    ///
+    /// With stiffness `s` (converted from flakiness as in `report`), a true vote multiplies the
+    /// weight of every index up to and including `index` by `1 + s`, and a false vote multiplies
+    /// the weight of every index after it by `1 + s`. A graded vote interpolates linearly between
+    /// the two: indices up to and including `index` are multiplied by `1 + badness * s`, and
+    /// indices after it by `1 + (1 - badness) * s`. A badness of 1.0 is the same as a true vote and
+    /// 0.0 is the same as a false vote. A badness of 0.5 scales every index equally, so it leaves
+    /// the likelihoods unchanged.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `index >= len` or if `badness` is not in the range [0.0, 1.0].
+    pub fn report_graded(&mut self, index: usize, badness: f64, flakiness: f64) {
+        assert!(index < self.len);
+        assert!(
+            (0.0..=1.0).contains(&badness),
+            "invalid badness {}",
+            badness
+        );
+        let stiffness = self.checked_stiffness_for(flakiness);
+        // The two halves scale disjoint sets of indices, so they can be applied as separate votes.
+        if badness > 0.0 {
+            self.apply_vote(index, true, badness * stiffness);
+        }
+        if badness < 1.0 {
+            self.apply_vote(index, false, (1.0 - badness) * stiffness);
+        }
+        self.apply_constraints();
+        self.normalize_if_needed();
+    }
+
+    /// Same as `report`, but returns true if the vote changed `best_index`. This lets drivers
+    /// detect when further votes have stopped moving the estimate.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `index >= len`.
+    pub fn report_tracked(&mut self, index: usize, heads: bool, flakiness: f64) -> bool {
+        let before = self.best_index();
+        self.report(index, heads, flakiness);
+        self.best_index() != before
+    }
+
+    /// Same as `report`, but uses the given calculator instead of the Searcher's stiffness function
+    /// to convert flakiness into stiffness. Flakiness is not clamped.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `index >= len`.
+    pub fn report_with_calculator(
+        &mut self,
+        index: usize,
+        heads: bool,
+        flakiness: f64,
+        calculator: &dyn StiffnessCalculator,
+    ) {
+        self.report_with_stiffness(index, heads, calculator.stiffness(flakiness));
+    }
+
+    /// Combines the evidence from another Searcher over the same range, e.g. one which ran the same
+    /// search on another machine, by multiplying the weights of each index. This assumes that both
+    /// Searchers started with the same uniform prior and that their votes are independent. Skips
+    /// are also merged, but the other Searcher's constraints and trace are not.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the Searchers have different lengths.
+    pub fn merge(&mut self, other: &Searcher<F>) {
+        assert_eq!(
+            self.len, other.len,
+            "cannot merge Searchers of different lengths"
+        );
+        let other_weights = other.weights();
+        let weights = self.weights.get_mut();
+        // Make sure each of our entries lies within a single entry of the other map.
+        for w in other_weights.ranges() {
+            let _ = weights.split(w.offset());
+        }
+        let mut other_ranges = other_weights.ranges().peekable();
+        for w in weights.ranges_mut() {
+            while other_ranges.peek().unwrap().end() <= w.offset() {
+                other_ranges.next();
+            }
+            *w.value_mut() = *w.value() * *other_ranges.peek().unwrap().value();
+        }
+        self.skips.extend(other.skips.iter().cloned());
+        self.apply_constraints();
+        self.normalize();
+    }
+
+    /// Records a test result which was neither clearly good nor bad, e.g. because of a timeout or
+    /// an infrastructure error. The weights are untouched, so this doesn't change `next_index` or
+    /// `best_index`; the vote is only counted (see `indeterminate_count`). `AutoSearcher` also
+    /// treats such votes as evidence of flakiness.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `index >= len`.
+    pub fn report_indeterminate(&mut self, index: usize) {
+        assert!(index < self.len);
+        self.indeterminate += 1;
+    }
+
+    /// Returns the number of votes reported with `report_indeterminate`.
+    pub fn indeterminate_count(&self) -> usize {
+        self.indeterminate
+    }
+
+    /// Adds several `(index, heads, flakiness)` votes, with the same result as calling `report`
+    /// for each in order, except that if any index is out of range, no votes are applied.
+    ///
+    /// # Panics
+    ///
+    /// Panics if any index is `>= len`.
+    pub fn report_many(&mut self, votes: &[(usize, bool, f64)]) {
+        for (index, _, _) in votes {
+            assert!(*index < self.len);
+        }
+        for (index, heads, flakiness) in votes {
+            let stiffness = self.checked_stiffness_for(*flakiness);
+            self.report_with_stiffness(*index, *heads, stiffness);
+        }
+    }
+
+    /// Same as `report`, but returns an error without modifying the Searcher if `index >= len` or
+    /// if `flakiness` is not finite or is outside the range (0.0, 1.0]. This suits long-running
+    /// services, where one malformed input shouldn't bring down the process.
+    pub fn try_report(
+        &mut self,
+        index: usize,
+        heads: bool,
+        flakiness: f64,
+    ) -> Result<(), ReportError> {
+        if index >= self.len {
+            return Err(ReportError::IndexOutOfRange {
+                index,
+                len: self.len,
+            });
+        }
+        if !(flakiness > 0.0 && flakiness <= 1.0) {
+            return Err(ReportError::InvalidFlakiness(flakiness));
+        }
+        self.report(index, heads, flakiness);
+        Ok(())
     }
 
     /// Returns the next index that should be tested. Can return values in the range 0 to len,
-    /// exclusive.
+    /// exclusive. Equivalent to `next_index_with_rounding(Rounding::Nearest)`.
     pub fn next_index(&self) -> Option<usize> {
-        let original_ix = cmp::min(
-            confidence_percentile_nearest(&self.weights, 0.5).0,
-            self.len - 1,
-        );
+        self.next_index_with_rounding(Rounding::Nearest)
+    }
+
+    /// Returns the next index that should be tested, using the given rounding to choose between
+    /// the indices on either side of the median. Can return values in the range 0 to len,
+    /// exclusive. Returns None if there are no testable indices, i.e. `len` is zero or every index
+    /// is skipped.
+    pub fn next_index_with_rounding(&self, rounding: Rounding) -> Option<usize> {
+        if self.len == 0 {
+            return None;
+        }
+        let median = match rounding {
+            Rounding::Nearest => self.percentile_nearest(0.5),
+            Rounding::Down => confidence_percentile_floor(&self.weights(), float(0.5)).0,
+            Rounding::Up => self.percentile_ceil(0.5),
+        };
+        let original_ix = cmp::min(median, self.len - 1);
         let mut ix = original_ix;
         let mut attempt = 0;
         let mut can_inc = true;
@@ -278,39 +1103,583 @@ impl Searcher {
         Some(ix)
     }
 
-    /// Returns the current estimate of the best index. Can return values in the range 0 to len,
-    /// inclusive.
-    pub fn best_index(&self) -> usize {
-        confidence_percentile_ceil(&self.weights, 0.5).0
-    }
-
-    /// Only public for use by the tuner, not for public use.
-    #[doc(hidden)]
-    pub fn confidence_percentile_ceil(&self, percentile: f64) -> usize {
-        confidence_percentile_ceil(&self.weights, percentile).0
+    /// Returns a rough estimate of how many more tests at `next_index` are needed before the
+    /// likelihood of `best_index` reaches `target_likelihood`, e.g. to tell a user about how long a
+    /// search will take. Returns 0 if the target has already been reached.
+    ///
+    /// This is an approximation. It divides the difference between the current `entropy` and the
+    /// entropy of a distribution with `target_likelihood` on one index (and the rest spread evenly)
+    /// by the `expected_info_gain` of testing `next_index`, as if every future test were as
+    /// informative as the next one. Returns `usize::MAX` if the next test is expected to yield no
+    /// information.
+    pub fn estimated_remaining_probes(&self, target_likelihood: f64, flakiness: f64) -> usize {
+        if self.likelihood(self.best_index()) >= target_likelihood {
+            return 0;
+        }
+        let index = match self.next_index() {
+            Some(index) => index,
+            None => return 0,
+        };
+        let gain = self.expected_info_gain(index, flakiness);
+        if gain <= 0.0 {
+            return usize::MAX;
+        }
+        let binary_entropy = |p: f64| {
+            if p <= 0.0 || p >= 1.0 {
+                0.0
+            } else {
+                -p * p.log2() - (1.0 - p) * (1.0 - p).log2()
+            }
+        };
+        // The remaining likelihood is spread over the other len indices (including index len).
+        let target_entropy = binary_entropy(target_likelihood)
+            + (1.0 - target_likelihood) * (self.len as f64).log2();
+        let remaining = ((self.entropy() - target_entropy) / gain).ceil();
+        (remaining as usize).max(1)
     }
 
-    /// Returns the likelihood of the given index.
+    /// Returns the expected reduction in `entropy`, in bits, from testing `index`
+    /// and reporting the result with the given flakiness. The result is assumed to be heads with
+    /// probability `m * (1 - f / 2) + (1 - m) * f / 2`, where `m` is `probability_mass(..=index)`
+    /// and `f` is the flakiness, i.e. a flaky result is wrong half the time. Each outcome is
+    /// simulated on a copy of this Searcher, using `checkpoint` and `restore` to undo the first, so
+    /// this takes time linear in the number of weight entries. Flakiness is clamped into
+    /// (0.0, 1.0] as in `report`, but without logging a warning.
     ///
     /// # Panics
     ///
-    /// Panics if `index > len`.
-    pub fn likelihood(&self, index: usize) -> f64 {
-        *self.weights.range_for_index(index).value()
+    /// Panics if `index >= len`.
+    pub fn expected_info_gain(&self, index: usize, flakiness: f64) -> f64 {
+        assert!(
+            index < self.len,
+            "index {} out of range for Searcher of length {}",
+            index,
+            self.len
+        );
+        let mut scratch = self.clone();
+        scratch.trace = None;
+        self.expected_info_gain_with(&mut scratch, index, flakiness)
     }
-}
 
-/// INTERNAL ONLY.
+    /// Same as `expected_info_gain`, but simulates the outcomes on `scratch`, which must be in the
+    /// same state as `self` and is left in that state.
+    fn expected_info_gain_with(&self, scratch: &mut Self, index: usize, flakiness: f64) -> f64 {
+        // Clamped the same way as `report`, which would otherwise warn about it.
+        let flakiness = clamp_flakiness(flakiness);
+        let mass = self.probability_mass(..=index);
+        let p_heads = mass * (1.0 - flakiness / 2.0) + (1.0 - mass) * flakiness / 2.0;
+        let checkpoint = scratch.checkpoint();
+        let mut expected = 0.0;
+        for (heads, p) in &[(true, p_heads), (false, 1.0 - p_heads)] {
+            if *p > 0.0 {
+                scratch.report(index, *heads, flakiness);
+                expected += p * scratch.entropy();
+                scratch.restore(checkpoint.clone());
+            }
+        }
+        self.entropy() - expected
+    }
+
+    /// Returns the testable index with the greatest `expected_info_gain`, preferring the lowest
+    /// such index. Skipped indices are not considered. With low flakiness, this is the index which
+    /// splits the likelihood most evenly, like `next_index`, but with high flakiness it may differ.
+    /// This evaluates every index, so it takes time proportional to `len` times the number of
+    /// weight entries.
+    ///
+    /// # Panics
+    ///
+    /// Panics if there are no testable indices, i.e. `len` is zero or every index is skipped.
+    pub fn best_probe(&self, flakiness: f64) -> usize {
+        let mut scratch = self.clone();
+        scratch.trace = None;
+        let mut best: Option<(usize, f64)> = None;
+        for index in (0..self.len).filter(|i| !self.skips.contains(i)) {
+            let gain = self.expected_info_gain_with(&mut scratch, index, flakiness);
+            match best {
+                Some((_, best_gain)) if best_gain >= gain => (),
+                _ => best = Some((index, gain)),
+            }
+        }
+        best.expect("no testable indices").0
+    }
+
+    /// Returns the current estimate of the best index. Always returns a testable index, i.e. in the
+    /// range 0 to len, exclusive. If every index appears to be good, this is `len - 1`. If `len` is
+    /// zero, there are no testable indices and this returns 0.
+    pub fn best_index(&self) -> usize {
+        cmp::min(self.percentile_ceil(0.5), self.len.saturating_sub(1))
+    }
+
+    /// Summarizes the evidence for or against `index`, e.g. to understand a surprising result.
+    /// Like `likelihood`, this accepts index `len`. Note that `best_index` is the median of the
+    /// distribution rather than the most likely index, so it usually, but not always, has rank 0.
+    /// Takes time linear in the number of weight entries.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `index > len`.
+    pub fn explain(&self, index: usize) -> Explanation {
+        let weights = self.weights();
+        let likelihood = *weights.range_for_index(index).value();
+        let mut rank = 0;
+        let mut mass_below = F::zero();
+        let mut mass_above = F::zero();
+        let mut runner_up = None;
+        for w in weights.ranges() {
+            let value = *w.value();
+            let others = if w.offset() <= index && index < w.end() {
+                mass_below = mass_below + value * count::<F>(index - w.offset());
+                mass_above = mass_above + value * count::<F>(w.end() - index - 1);
+                w.len() - 1
+            } else {
+                if w.end() <= index {
+                    mass_below = mass_below + value * count::<F>(w.len());
+                } else {
+                    mass_above = mass_above + value * count::<F>(w.len());
+                }
+                w.len()
+            };
+            if others == 0 {
+                continue;
+            }
+            if value > likelihood {
+                rank += others;
+            }
+            if runner_up.is_none_or(|runner_up| value > runner_up) {
+                runner_up = Some(value);
+            }
+        }
+        Explanation {
+            index,
+            likelihood: to_f64(likelihood),
+            rank,
+            mass_below: to_f64(mass_below),
+            mass_above: to_f64(mass_above),
+            margin: to_f64(likelihood) - runner_up.map_or(0.0, to_f64),
+        }
+    }
+
+    /// Returns the bounds of a credible interval containing the best index with probability `p`,
+    /// i.e. the indices at the `0.5 - p / 2` and `0.5 + p / 2` percentiles. Both bounds are
+    /// inclusive and are clamped to testable indices like `best_index`.
+    pub fn confidence_interval(&self, p: f64) -> (usize, usize) {
+        let max = self.len.saturating_sub(1);
+        let low = self.percentile_ceil(0.5 - p / 2.0);
+        let high = self.percentile_ceil(0.5 + p / 2.0);
+        (cmp::min(low, max), cmp::min(high, max))
+    }
+
+    /// Returns the bounds of the shortest interval whose total likelihood is at least `mass`, i.e.
+    /// a highest density interval. Unlike `confidence_interval`, which is centered on the median,
+    /// this hugs the most likely indices, so for a converged search it is a single index. Among
+    /// intervals of the same length, the one with the greatest likelihood is chosen, and then the
+    /// lowest. The interval is found among the indices from 0 to `len`, inclusive, and then both
+    /// bounds are clamped to testable indices like `best_index`. Takes `O(n log n)` time, where
+    /// `n` is the number of weight entries.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `mass` is not in the range [0.0, 1.0].
+    pub fn highest_density_interval(&self, mass: f64) -> (usize, usize) {
+        assert!((0.0..=1.0).contains(&mass), "invalid mass {}", mass);
+        let (lo, hi) = self.unclamped_highest_density_interval(mass);
+        let max = self.len.saturating_sub(1);
+        (cmp::min(lo, max), cmp::min(hi, max))
+    }
+
+    /// Same as `highest_density_interval`, but the bounds may include `len`.
+    fn unclamped_highest_density_interval(&self, mass: f64) -> (usize, usize) {
+        let weights = self.weights();
+        let entries: Vec<(usize, usize, f64)> = weights
+            .ranges()
+            .map(|w| (w.offset(), w.len(), to_f64(*w.value())))
+            .collect();
+        // starts[i] is the sum of the likelihoods before entries[i].
+        let mut starts = Vec::with_capacity(entries.len() + 1);
+        starts.push(0.0);
+        for (_, len, value) in &entries {
+            starts.push(starts[starts.len() - 1] + *len as f64 * value);
+        }
+        let total = starts[entries.len()];
+        // Allow for rounding error, and for the likelihoods summing to slightly less than 1.
+        let target = (mass - 1e-12).min(total);
+        // The sum of the likelihoods of the indices before `index`.
+        let cumulative = |index: usize| {
+            let i = entries.partition_point(|(offset, _, _)| *offset <= index) - 1;
+            starts[i] + (index - entries[i].0) as f64 * entries[i].2
+        };
+        let mut best: Option<(usize, usize, f64)> = None;
+        let mut consider = |lo: usize, hi: usize| {
+            let interval_mass = cumulative(hi + 1) - cumulative(lo);
+            let better = match best {
+                None => true,
+                Some((best_lo, best_hi, best_mass)) => {
+                    let (len, best_len) = (hi - lo, best_hi - best_lo);
+                    len < best_len
+                        || (len == best_len
+                            && (interval_mass > best_mass
+                                || (interval_mass == best_mass && lo < best_lo)))
+                }
+            };
+            if better {
+                best = Some((lo, hi, interval_mass));
+            }
+        };
+        // Some shortest interval starts at the beginning of an entry or ends at the end of one,
+        // because otherwise it could be slid towards its more likely end until it did, without
+        // losing likelihood.
+        for (i, (offset, len, _)) in entries.iter().enumerate() {
+            if *len == 0 {
+                continue;
+            }
+            // Shortest interval starting at `offset`.
+            let goal = starts[i] + target;
+            let j = starts[1..].partition_point(|sum| *sum < goal);
+            if j < entries.len() {
+                let (j_offset, j_len, j_value) = entries[j];
+                let k = if j_value > 0.0 {
+                    ((goal - starts[j]) / j_value).ceil() as usize
+                } else {
+                    1
+                };
+                let hi = cmp::max(j_offset + k.clamp(1, j_len) - 1, *offset);
+                consider(*offset, hi);
+            }
+            // Shortest interval ending at the end of the entry.
+            let hi = offset + len - 1;
+            let goal = starts[i + 1] - target;
+            let j = starts[..entries.len()].partition_point(|sum| *sum <= goal);
+            if j > 0 {
+                let (j_offset, j_len, j_value) = entries[j - 1];
+                let k = if j_value > 0.0 {
+                    ((goal - starts[j - 1]) / j_value).floor() as usize
+                } else {
+                    0
+                };
+                let lo = cmp::min(j_offset + cmp::min(k, j_len - 1), hi);
+                consider(lo, hi);
+            }
+        }
+        match best {
+            Some((lo, hi, _)) => (lo, hi),
+            None => (0, self.len),
+        }
+    }
+
+    /// Only public for use by the tuner, not for public use.
+    #[doc(hidden)]
+    pub fn confidence_percentile_ceil(&self, percentile: f64) -> usize {
+        self.percentile_ceil(percentile)
+    }
+
+    /// Returns the likelihood of the given index.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `index > len`.
+    pub fn likelihood(&self, index: usize) -> f64 {
+        to_f64(*self.weights().range_for_index(index).value())
+    }
+
+    /// Returns the likelihood of every index, in order. Like `likelihood`, this includes index
+    /// `len`, so it yields `len + 1` values which sum to 1.
+    pub fn likelihoods(&self) -> impl Iterator<Item = f64> + '_ {
+        let ranges: Vec<(f64, usize)> = self
+            .weights()
+            .ranges()
+            .map(|w| (to_f64(*w.value()), w.len()))
+            .collect();
+        ranges
+            .into_iter()
+            .flat_map(|(value, len)| core::iter::repeat_n(value, len))
+    }
+
+    /// Returns the likelihoods as runs of equal values, as `(offset, len, likelihood)` triples in
+    /// order of increasing offset. Together the runs cover indices `0` through `len`, inclusive.
+    /// This is more compact than `likelihoods` when few votes have been cast. Adjacent runs may
+    /// have the same likelihood.
+    pub fn weight_ranges(&self) -> impl Iterator<Item = (usize, usize, f64)> + '_ {
+        let ranges: Vec<(usize, usize, f64)> = self
+            .weights()
+            .ranges()
+            .map(|w| (w.offset(), w.len(), to_f64(*w.value())))
+            .collect();
+        ranges.into_iter()
+    }
+
+    /// Returns the local maxima of the likelihood which are separated from each other, as
+    /// `(index, likelihood)` pairs in order of increasing index. This can reveal when the votes
+    /// point to more than one transition, e.g. if two independent changes both cause failures.
+    ///
+    /// The heuristic is that two maxima are separated if some index between them has a likelihood
+    /// less than or equal to `threshold`. The indices with a likelihood greater than `threshold`
+    /// form contiguous regions, and each region contributes its most likely index (the lowest one,
+    /// if there is a tie). A converged search therefore returns a single mode for any threshold
+    /// below the best likelihood, and an empty vector if `threshold` is at least the best
+    /// likelihood. Like `likelihood`, this includes index `len`.
+    pub fn modes(&self, threshold: f64) -> Vec<(usize, f64)> {
+        let mut modes = Vec::new();
+        let mut region: Option<(usize, f64)> = None;
+        for w in self.weights().ranges() {
+            let value = to_f64(*w.value());
+            if value > threshold {
+                match region {
+                    Some((_, best)) if best >= value => (),
+                    _ => region = Some((w.offset(), value)),
+                }
+            } else if let Some(mode) = region.take() {
+                modes.push(mode);
+            }
+        }
+        modes.extend(region);
+        modes
+    }
+
+    /// Returns every index paired with the cumulative likelihood of the indices up to and including
+    /// it, in order, i.e. `(i, probability_mass(..=i))`. Like `likelihoods`, this includes index
+    /// `len`, so the last cumulative likelihood is 1. This takes a single pass over the weights.
+    pub fn cdf(&self) -> impl Iterator<Item = (usize, f64)> + '_ {
+        self.likelihoods()
+            .enumerate()
+            .scan(0.0, |sum, (index, likelihood)| {
+                *sum += likelihood;
+                Some((index, *sum))
+            })
+    }
+
+    /// Returns the total likelihood of the indices in `range`, i.e. the probability that the best
+    /// index is in `range`. Like `likelihood`, this includes index `len`, so
+    /// `probability_mass(..)` is 1.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the range ends after `len + 1` or starts after it ends.
+    pub fn probability_mass<R: RangeBounds<usize>>(&self, range: R) -> f64 {
+        let start = match range.start_bound() {
+            Bound::Included(start) => *start,
+            Bound::Excluded(start) => start + 1,
+            Bound::Unbounded => 0,
+        };
+        let end = match range.end_bound() {
+            Bound::Included(end) => end + 1,
+            Bound::Excluded(end) => *end,
+            Bound::Unbounded => self.len + 1,
+        };
+        assert!(
+            start <= end && end <= self.len + 1,
+            "range {}..{} out of bounds for Searcher of length {}",
+            start,
+            end,
+            self.len
+        );
+        self.weights()
+            .ranges()
+            .skip_while(|w| w.end() <= start)
+            .take_while(|w| w.offset() < end)
+            .map(|w| {
+                let overlap = cmp::min(w.end(), end) - cmp::max(w.offset(), start);
+                to_f64(*w.value()) * overlap as f64
+            })
+            .sum()
+    }
+
+    /// Renders the likelihoods as a sparkline of `width` Unicode block characters, for displaying
+    /// the progress of a search in a terminal. The indices from 0 to `len`, inclusive, are divided
+    /// into `width` columns of nearly equal size, and each column's height is its
+    /// `probability_mass` relative to the largest column's, rounded to one of eight levels. A
+    /// column which rounds to zero is a space, so a converged search shows a single tall column.
+    ///
+    /// ```
+    /// use robust_binary_search::Searcher;
+    ///
+    /// let mut searcher = Searcher::new(7);
+    /// assert_eq!(searcher.sparkline(4), "████");
+    /// searcher.report(3, true, 0.01);
+    /// assert_eq!(searcher.sparkline(4), "██▁▁");
+    /// ```
+    pub fn sparkline(&self, width: usize) -> String {
+        const BLOCKS: [char; 9] = [' ', '▁', '▂', '▃', '▄', '▅', '▆', '▇', '█'];
+        let n = self.len + 1;
+        let masses: Vec<f64> = (0..width)
+            .map(|column| self.probability_mass(column * n / width..(column + 1) * n / width))
+            .collect();
+        let max = masses.iter().cloned().fold(0.0, f64::max);
+        masses
+            .iter()
+            .map(|mass| {
+                if max > 0.0 {
+                    BLOCKS[(mass / max * 8.0).round() as usize]
+                } else {
+                    ' '
+                }
+            })
+            .collect()
+    }
+
+    /// Returns up to `k` testable indices (i.e. excluding `len`) with the highest likelihoods, along
+    /// with their likelihoods, sorted by descending likelihood. Ties are broken by lower index.
+    pub fn top_k(&self, k: usize) -> Vec<(usize, f64)> {
+        let weights = self.weights();
+        let mut ranges: Vec<&RangeMapEntry<F>> = weights.ranges().collect();
+        ranges.sort_by(|a, b| {
+            b.value()
+                .partial_cmp(a.value())
+                .unwrap_or(cmp::Ordering::Equal)
+                .then(a.offset().cmp(&b.offset()))
+        });
+        ranges
+            .into_iter()
+            .flat_map(|w| {
+                (w.offset()..cmp::min(w.end(), self.len)).map(move |i| (i, to_f64(*w.value())))
+            })
+            .take(k)
+            .collect()
+    }
+
+    /// Returns the Shannon entropy, in bits, of the distribution of the best index. This is about
+    /// `log2(len + 1)` for a new Searcher and approaches zero as the search converges.
+    pub fn entropy(&self) -> f64 {
+        self.weights()
+            .ranges()
+            .map(|w| {
+                let p = to_f64(*w.value());
+                if p > 0.0 {
+                    -p * p.log2() * w.len() as f64
+                } else {
+                    0.0
+                }
+            })
+            .sum()
+    }
+
+    /// Returns up to `k` distinct indices to be tested in parallel, in increasing order. Since none
+    /// of the results are known until the whole batch has been tested, the indices are chosen
+    /// jointly to maximize the expected information gained (the entropy of the outcome, assuming
+    /// non-flaky results) per unit of total cost, which keeps them from clustering around the
+    /// median.
+    ///
+    /// Choosing the optimal batch is expensive, so this uses a greedy approximation: indices are
+    /// added one at a time, and each step considers splitting each interval between previously
+    /// chosen indices at its conditional median, picking the split with the best gain per cost.
+    /// Fewer than `k` indices are returned if no interval can be split further. `cost` must return
+    /// positive values.
+    pub fn next_batch<C: Fn(usize) -> f64>(&self, k: usize, cost: C) -> Vec<usize> {
+        fn entropy_term(p: f64) -> f64 {
+            if p > 0.0 {
+                -p * p.ln()
+            } else {
+                0.0
+            }
+        }
+        let mut batch: Vec<usize> = Vec::new();
+        while batch.len() < k {
+            let mut best: Option<(f64, usize)> = None;
+            // Intervals are inclusive ranges of weight indices between chosen indices.
+            let mut lo = 0;
+            for hi in batch.iter().cloned().chain(core::iter::once(self.len)) {
+                if let Some(t) = self.conditional_median(lo, hi) {
+                    let below = self.mass(lo, t);
+                    let above = self.mass(t + 1, hi);
+                    let gain =
+                        entropy_term(below) + entropy_term(above) - entropy_term(below + above);
+                    let score = gain / cost(t);
+                    let better = match best {
+                        Some((best_score, _)) => score > best_score,
+                        None => true,
+                    };
+                    if better {
+                        best = Some((score, t));
+                    }
+                }
+                lo = hi + 1;
+            }
+            match best {
+                Some((_, t)) => {
+                    let position = batch.binary_search(&t).unwrap_err();
+                    batch.insert(position, t);
+                }
+                None => break,
+            }
+        }
+        batch
+    }
+
+    /// Returns the total weight of indices in `lo..=hi`.
+    fn mass(&self, lo: usize, hi: usize) -> f64 {
+        self.weights()
+            .ranges()
+            .map(|w| {
+                let start = cmp::max(w.offset(), lo);
+                let end = cmp::min(w.end(), hi + 1);
+                if start < end {
+                    to_f64(*w.value()) * (end - start) as f64
+                } else {
+                    0.0
+                }
+            })
+            .sum()
+    }
+
+    /// Returns the non-skipped index `t` in `lo..hi` closest to the median of the weights in
+    /// `lo..=hi`, or None if there is no such index.
+    fn conditional_median(&self, lo: usize, hi: usize) -> Option<usize> {
+        if lo >= hi {
+            return None;
+        }
+        let half = self.mass(lo, hi) / 2.0;
+        let mut cumulative = 0.0;
+        let mut median = hi - 1;
+        for w in self.weights().ranges() {
+            let start = cmp::max(w.offset(), lo);
+            let end = cmp::min(w.end(), hi);
+            if start >= end {
+                continue;
+            }
+            let value = to_f64(*w.value());
+            let range_mass = value * (end - start) as f64;
+            if cumulative + range_mass >= half && value > 0.0 {
+                let n = ((half - cumulative) / value).ceil().max(1.0) as usize;
+                median = cmp::min(start + n - 1, end - 1);
+                break;
+            }
+            cumulative += range_mass;
+        }
+        (0..hi - lo)
+            .flat_map(|d| {
+                core::iter::once(median.checked_sub(d)).chain(core::iter::once(Some(median + d)))
+            })
+            .flatten()
+            .find(|t| *t >= lo && *t < hi && !self.skips.contains(t))
+    }
+}
+
+/// INTERNAL ONLY.
 ///
-/// Returns the stiffness which should be optimal for the given flakiness.
+/// Returns the stiffness which should be optimal for the given flakiness. The result is finite and
+/// positive for any non-negative flakiness, and never exceeds `MAX_STIFFNESS`.
 #[doc(hidden)]
-pub fn optimal_stiffness(flakiness: f64) -> f64 {
+pub fn optimal_stiffness<F: Float>(flakiness: F) -> F {
+    let flakiness = flakiness.min(F::one());
     // Values calculated by tuner.rs
-    (2.6 / flakiness.powf(0.37))
-        .min(0.58 / flakiness.powf(0.97))
-        .min(0.19 / flakiness.powf(2.4))
+    (float::<F>(2.6) / flakiness.powf(float(0.37)))
+        .min(float::<F>(0.58) / flakiness.powf(float(0.97)))
+        .min(float::<F>(0.19) / flakiness.powf(float(2.4)))
+        .min(float(MAX_STIFFNESS))
 }
 
+/// Largest stiffness returned by `optimal_stiffness`. This is the largest stiffness considered by
+/// tuner.rs.
+const MAX_STIFFNESS: f64 = 128.0;
+
+/// Largest factor which `CompressedDagSearcher::report_counts` applies to the weights between
+/// renormalizations. This leaves plenty of headroom below `f64::MAX`, even when the weights being
+/// scaled are already large relative to the rest.
+#[cfg(feature = "std")]
+const MAX_REPORT_COUNTS_FACTOR: f64 = 1e100;
+
+/// Estimated flakiness above which `AutoSearcher::is_hopeless` considers the votes to be noise.
+const HOPELESS_FLAKINESS: f64 = 0.8;
+
 /// Performs a robust binary search over a linear range and automatically infers the flakiness based
 /// on the votes.
 #[derive(Clone, Debug)]
@@ -328,6 +1697,13 @@ impl AutoSearcher {
         }
     }
 
+    /// Restores the state of a newly created AutoSearcher of the same length, discarding all votes,
+    /// while reusing existing allocations where possible.
+    pub fn reset(&mut self) {
+        self.searcher.reset();
+        self.flakiness_tracker = FlakinessTracker::default();
+    }
+
     /// Adds a vote to the internal statistics. With low flakiness, false votes are expected to have
     /// smaller indices than true votes.
     ///
@@ -340,14 +1716,27 @@ impl AutoSearcher {
             .report(index, heads, self.flakiness_tracker.flakiness());
     }
 
+    /// Records a test result which was neither clearly good nor bad. The weights are untouched, so
+    /// this doesn't change `next_index` or `best_index`, but flakiness inference treats it as
+    /// ambiguous evidence (as if the index had voted both ways), which raises the estimated
+    /// flakiness.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `index >= len`.
+    pub fn report_indeterminate(&mut self, index: usize) {
+        self.searcher.report_indeterminate(index);
+        self.flakiness_tracker.report_indeterminate(index);
+    }
+
     /// Returns the next index that should be tested. Can return values in the range 0 to len,
     /// exclusive.
     pub fn next_index(&self) -> Option<usize> {
         self.searcher.next_index()
     }
 
-    /// Returns the current estimate of the best index. Can return values in the range 0 to len,
-    /// inclusive.
+    /// Returns the current estimate of the best index. Always returns a testable index, i.e. in the
+    /// range 0 to len, exclusive.
     pub fn best_index(&self) -> usize {
         self.searcher.best_index()
     }
@@ -360,26 +1749,160 @@ impl AutoSearcher {
     pub fn likelihood(&self, index: usize) -> f64 {
         self.searcher.likelihood(index)
     }
+
+    /// Returns the estimated flakiness.
+    pub fn flakiness(&self) -> f64 {
+        self.flakiness_tracker.flakiness()
+    }
+
+    /// Returns the total number of votes reported.
+    pub fn total_votes(&self) -> usize {
+        self.flakiness_tracker.total_votes()
+    }
+
+    /// Returns true if, after at least `min_votes` votes, the votes look like pure noise: the
+    /// estimated flakiness is above 0.8 and no index is more likely than not. A driver can use this
+    /// to give up on a search which would otherwise never converge.
+    ///
+    /// Flakiness is underestimated when the votes are completely random, typically landing between
+    /// 0.8 and 0.9, so the threshold is not closer to 1.0. Random votes can still make some index
+    /// look likely for a while, so this may need to be checked more than once before it returns
+    /// true.
+    pub fn is_hopeless(&self, min_votes: usize) -> bool {
+        self.total_votes() >= min_votes
+            && self.flakiness() > HOPELESS_FLAKINESS
+            && self.likelihood(self.best_index()) < 0.5
+    }
+
+    /// Returns `(index, tails, heads)` for each index which has been tested, in order of increasing
+    /// index. This is the evidence behind both the likelihoods and the flakiness estimate, so it
+    /// helps to explain a search which is not converging. Indeterminate results count as one vote
+    /// each way.
+    pub fn vote_summary(&self) -> Vec<(usize, usize, usize)> {
+        self.flakiness_tracker.votes().collect()
+    }
+
+    /// Returns true if the likelihood of the best index is at least `target_likelihood`, i.e. the
+    /// search can stop. See `recommended_target` for a target which adapts to the flakiness.
+    pub fn converged(&self, target_likelihood: f64) -> bool {
+        self.likelihood(self.best_index()) >= target_likelihood
+    }
+
+    /// Returns a target likelihood for `converged` based on the estimated flakiness. The target
+    /// is `1 - 0.1 / (1 + 9 * flakiness)`, which ranges from 0.9 for a deterministic test to 0.99
+    /// for a completely random one. A flaky test is more likely to have misled the search, so more
+    /// certainty is needed before stopping. Before any votes, the flakiness estimate is 0.5.
+    pub fn recommended_target(&self) -> f64 {
+        1.0 - 0.1 / (1.0 + 9.0 * self.flakiness())
+    }
+}
+
+/// Runs a complete search over a linear range of `len` indices using an AutoSearcher, and returns
+/// the best index.
+///
+/// `test` is called with each index to be tested and must return true if the index is bad (i.e.
+/// heads, at or after the transition) and false if it is good. Results may be flaky. The search
+/// stops once the likelihood of the best index is at least `min_likelihood`, or after
+/// `max_iterations` calls to `test` if specified, in which case the best guess so far is returned.
+pub fn search_linear<F: FnMut(usize) -> bool>(
+    len: usize,
+    min_likelihood: f64,
+    max_iterations: Option<usize>,
+    mut test: F,
+) -> usize {
+    let mut searcher = AutoSearcher::new(len);
+    let mut iterations = 0;
+    loop {
+        let best = searcher.best_index();
+        if searcher.likelihood(best) >= min_likelihood {
+            return best;
+        }
+        match max_iterations {
+            Some(max_iterations) if iterations >= max_iterations => return best,
+            _ => (),
+        }
+        let index = match searcher.next_index() {
+            Some(index) => index,
+            None => return best,
+        };
+        let heads = test(index);
+        trace!("search_linear: index = {}, heads = {}", index, heads);
+        searcher.report(index, heads);
+        iterations += 1;
+    }
+}
+
+/// Tests nodes for `run_dag_search`.
+#[cfg(feature = "std")]
+pub trait Oracle {
+    /// Returns true if the node is bad (i.e. heads, the transition is at the node or one of its
+    /// ancestors) and false if it is good. Results may be flaky.
+    fn test(&mut self, node: CompressedDagNodeRef) -> bool;
+}
+
+#[cfg(feature = "std")]
+impl<F: FnMut(CompressedDagNodeRef) -> bool> Oracle for F {
+    fn test(&mut self, node: CompressedDagNodeRef) -> bool {
+        self(node)
+    }
+}
+
+/// Runs a complete search over a CompressedDag using an AutoCompressedDagSearcher, and returns the
+/// best node. The search stops once the likelihood of the best node is at least `min_likelihood`,
+/// or after `max_iterations` calls to the oracle if specified, in which case the best guess so far
+/// is returned. Without `max_iterations`, a sufficiently noisy oracle may never let the search stop.
+#[cfg(feature = "std")]
+pub fn run_dag_search<O: Oracle>(
+    graph: Rc<CompressedDag>,
+    min_likelihood: f64,
+    max_iterations: Option<usize>,
+    oracle: &mut O,
+) -> CompressedDagNodeRef {
+    let mut searcher = AutoCompressedDagSearcher::new(graph);
+    let mut iterations = 0;
+    loop {
+        let best = searcher.best_node();
+        if searcher.likelihood(best) >= min_likelihood {
+            return best;
+        }
+        match max_iterations {
+            Some(max_iterations) if iterations >= max_iterations => return best,
+            _ => (),
+        }
+        let node = searcher.next_node();
+        let heads = oracle.test(node);
+        trace!("run_dag_search: node = {:?}, heads = {}", node, heads);
+        searcher.report(node, heads);
+        iterations += 1;
+    }
 }
 
 /// Performs a robust binary search over a CompressedDag.
+///
+/// With the `serde` feature, CompressedDagSearchers can be serialized and deserialized. The graph
+/// is serialized by value, so a deserialized searcher has its own copy of the graph.
+#[cfg(feature = "std")]
 #[derive(Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct CompressedDagSearcher {
     graph: Rc<CompressedDag>,
     segment_range_maps: Vec<RangeMap<f64>>,
+    /// Cached result of `compute_segment_percentile_ranges`, cleared when the weights change.
+    #[cfg_attr(feature = "serde", serde(skip))]
+    percentile_ranges: RefCell<Option<Vec<(f64, f64)>>>,
+    #[cfg_attr(feature = "serde", serde(default))]
+    skips: HashSet<CompressedDagNodeRef>,
 }
 
+#[cfg(feature = "std")]
 #[deprecated(note = "Use CompressedDagSearcher instead.")]
 pub type CompressedDAGSearcher = CompressedDagSearcher;
 
+#[cfg(feature = "std")]
 impl CompressedDagSearcher {
     /// Creates a new CompressedDagSearcher.
     pub fn new(graph: Rc<CompressedDag>) -> Self {
-        let n = graph
-            .nodes()
-            .iter()
-            .map(|node| node.value().len())
-            .sum::<usize>();
+        let n = graph.total_len();
         let segment_range_maps = graph
             .nodes()
             .iter()
@@ -388,14 +1911,43 @@ impl CompressedDagSearcher {
         CompressedDagSearcher {
             graph,
             segment_range_maps,
+            percentile_ranges: RefCell::new(None),
+            skips: HashSet::default(),
+        }
+    }
+
+    /// Restores the state of a newly created CompressedDagSearcher over the same graph, discarding
+    /// all votes and skips, while reusing existing allocations where possible.
+    pub fn reset(&mut self) {
+        let n = self
+            .segment_range_maps
+            .iter()
+            .map(|range_map| range_map.len())
+            .sum::<usize>();
+        for range_map in &mut self.segment_range_maps {
+            range_map.fill(1.0 / n as f64);
         }
+        *self.percentile_ranges.get_mut() = None;
+        self.skips.clear();
     }
 
     /// Returns the sums at the beginning and end of every segment. Each vector entry corresponds to
     /// a single segment. The first entry in the tuple is the sum of all weights in the segment's
     /// ancestors (i.e. source segments will have a start of 0.0), and the second entry is the sum
     /// of all weights in the segment and its ancestors.
-    fn segment_percentile_ranges(&self) -> Vec<(f64, f64)> {
+    ///
+    /// The result is cached until the next call to `report`.
+    fn segment_percentile_ranges(&self) -> Ref<'_, Vec<(f64, f64)>> {
+        if self.percentile_ranges.borrow().is_none() {
+            *self.percentile_ranges.borrow_mut() = Some(self.compute_segment_percentile_ranges());
+        }
+        Ref::map(self.percentile_ranges.borrow(), |ranges| {
+            ranges.as_ref().unwrap()
+        })
+    }
+
+    /// Computes the value returned by `segment_percentile_ranges`.
+    fn compute_segment_percentile_ranges(&self) -> Vec<(f64, f64)> {
         let mut segment_ranges = Vec::<(f64, f64)>::new();
         let mut segment_sums = Vec::<f64>::new();
         let graph: &CompressedDag = self.graph.borrow();
@@ -455,6 +2007,34 @@ impl CompressedDagSearcher {
         best_node
     }
 
+    /// Like `confidence_percentile_nearest`, but ignores skipped nodes. Returns None if every node
+    /// is skipped. This visits every node individually, so it is only used when the nearest node
+    /// is skipped.
+    fn confidence_percentile_nearest_unskipped(
+        &self,
+        percentile: f64,
+    ) -> Option<CompressedDagNodeRef> {
+        let segment_ranges = self.segment_percentile_ranges();
+        let mut best_node = None;
+        let mut best_value = f64::NEG_INFINITY;
+        for (segment, range_map) in self.segment_range_maps.iter().enumerate() {
+            let mut value = segment_ranges[segment].0;
+            for w in range_map.ranges() {
+                for index in w.offset()..w.end() {
+                    value += w.value();
+                    let node = CompressedDagNodeRef { segment, index };
+                    if !self.skips.contains(&node)
+                        && (percentile - value).abs() < (percentile - best_value).abs()
+                    {
+                        best_node = Some(node);
+                        best_value = value;
+                    }
+                }
+            }
+        }
+        best_node
+    }
+
     /// Returns the node whose percentile (i.e. the sum of weights over the node and its ancestors)
     /// is smallest but greater than or equal to the argument.
     pub fn confidence_percentile_ceil(&self, percentile: f64) -> CompressedDagNodeRef {
@@ -495,9 +2075,51 @@ impl CompressedDagSearcher {
         self.confidence_percentile_ceil(0.5)
     }
 
-    /// Returns the next node that should be tested.
+    /// Returns the next node that should be tested. Skipped nodes are avoided unless every node is
+    /// skipped.
     pub fn next_node(&self) -> CompressedDagNodeRef {
-        self.confidence_percentile_nearest(0.5)
+        let node = self.confidence_percentile_nearest(0.5);
+        if !self.skips.contains(&node) {
+            return node;
+        }
+        self.confidence_percentile_nearest_unskipped(0.5)
+            .unwrap_or(node)
+    }
+
+    /// Adds a node which cannot be tested. `next_node` will avoid returning this node. The weights
+    /// are untouched, so the node can still be the best node.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the node is out of range.
+    pub fn add_skip(&mut self, node: CompressedDagNodeRef) {
+        assert!(
+            node.index < self.segment_range_maps[node.segment].len(),
+            "{:?} out of range",
+            node
+        );
+        self.skips.insert(node);
+    }
+
+    /// Returns true if the node was added with `add_skip`.
+    pub fn is_skipped(&self, node: CompressedDagNodeRef) -> bool {
+        self.skips.contains(&node)
+    }
+
+    /// Same as `report`, but returns true if the vote changed `best_node`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the node is out of range.
+    pub fn report_tracked(
+        &mut self,
+        node: CompressedDagNodeRef,
+        heads: bool,
+        flakiness: f64,
+    ) -> bool {
+        let before = self.best_node();
+        self.report(node, heads, flakiness);
+        self.best_node() != before
     }
 
     /// Adds a vote to the internal statistics. With low flakiness, nodes with false votes are
@@ -507,24 +2129,60 @@ impl CompressedDagSearcher {
     ///
     /// Panics if the node is out of range.
     pub fn report(&mut self, node: CompressedDagNodeRef, heads: bool, flakiness: f64) {
-        let stiffness = optimal_stiffness(flakiness);
+        self.report_with_stiffness(node, heads, optimal_stiffness(flakiness));
+    }
+
+    /// Same as calling `report` `heads` times with a true vote and `tails` times with a false vote
+    /// for the same node, but renormalizes far less often. This is for nodes which were retested
+    /// many times.
+    ///
+    /// A true vote multiplies the weights of the node and its ancestors by `1 + stiffness`, and a
+    /// false vote multiplies the weights of every other node by the same factor, so the votes
+    /// partly cancel out. This applies the net factor `(1 + stiffness)^|heads - tails|` to
+    /// whichever side received more votes. So that the weights stay finite, the factor is applied
+    /// in chunks of at most `MAX_REPORT_COUNTS_FACTOR`, with a renormalization after each. The
+    /// results match repeated calls to `report` up to floating point error.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the node is out of range.
+    pub fn report_counts(
+        &mut self,
+        node: CompressedDagNodeRef,
+        heads: usize,
+        tails: usize,
+        flakiness: f64,
+    ) {
+        assert!(
+            node.index < self.segment_range_maps[node.segment].len(),
+            "{:?} out of range",
+            node
+        );
+        let net = heads.abs_diff(tails);
+        if net == 0 {
+            return;
+        }
+        let base = 1.0 + optimal_stiffness(flakiness);
+        let chunk = cmp::max(1, (MAX_REPORT_COUNTS_FACTOR.ln() / base.ln()) as usize);
+        let mut remaining = net;
+        while remaining > 0 {
+            let votes = cmp::min(chunk, remaining);
+            self.report_with_stiffness(node, heads > tails, base.powf(votes as f64) - 1.0);
+            remaining -= votes;
+        }
+    }
+
+    /// Applies a single vote with the given stiffness and renormalizes.
+    fn report_with_stiffness(&mut self, node: CompressedDagNodeRef, heads: bool, stiffness: f64) {
         let graph: &CompressedDag = self.graph.borrow();
+        let ancestor_segments = graph.node(node.segment).ancestors();
+        *self.percentile_ranges.get_mut() = None;
         if heads {
-            for segment in graph.node(node.segment).ancestors() {
+            for segment in ancestor_segments {
                 for w in self.segment_range_maps[*segment].ranges_mut() {
                     *w.value_mut() *= 1.0 + stiffness;
                 }
             }
-        } else {
-            let ancestor_segments = graph.node(node.segment).ancestors();
-            for segment in 0..graph.nodes().len() {
-                if ancestor_segments.contains(&segment) || segment == node.segment {
-                    continue;
-                }
-                for w in self.segment_range_maps[segment].ranges_mut() {
-                    *w.value_mut() *= 1.0 + stiffness;
-                }
-            }
         }
         report_range(
             &mut self.segment_range_maps[node.segment],
@@ -532,19 +2190,24 @@ impl CompressedDagSearcher {
             heads,
             stiffness,
         );
-        let weight_sum: f64 = self
-            .segment_range_maps
-            .iter()
-            .map(|range_map| {
-                range_map
-                    .ranges()
-                    .map(|w| w.value() * w.len() as f64)
-                    .sum::<f64>()
-            })
-            .sum();
-        for range_map in &mut self.segment_range_maps {
+        // For false votes, every segment other than the node and its ancestors needs to be scaled.
+        // That is folded into the pass which sums the weights, so that each segment is visited
+        // twice rather than three times.
+        let mut weight_sum = 0.0;
+        for (segment, range_map) in self.segment_range_maps.iter_mut().enumerate() {
+            let scale = !heads && segment != node.segment && !ancestor_segments.contains(&segment);
+            let mut segment_sum = 0.0;
             for w in range_map.ranges_mut() {
-                *w.value_mut() /= weight_sum;
+                if scale {
+                    *w.value_mut() *= 1.0 + stiffness;
+                }
+                segment_sum += w.value() * w.len() as f64;
+            }
+            weight_sum += segment_sum;
+        }
+        for range_map in &mut self.segment_range_maps {
+            for w in range_map.ranges_mut() {
+                *w.value_mut() /= weight_sum;
             }
         }
     }
@@ -559,19 +2222,135 @@ impl CompressedDagSearcher {
             .range_for_index(node.index)
             .value()
     }
+
+    /// Returns the likelihood of the node at the given index in the conceptual expanded graph, as
+    /// numbered by `CompressedDag::node_ref_to_linear`. Takes time linear in the number of
+    /// segments.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the index is greater than or equal to the total length of all segments.
+    pub fn likelihood_linear(&self, index: usize) -> f64 {
+        self.likelihood(self.graph.linear_to_node_ref(index))
+    }
+
+    /// Returns the sum of the likelihoods of the node and all of its ancestors, i.e. the
+    /// probability that the transition is at or before the node. This is the counterpart of a
+    /// cumulative distribution for a linear range, and never decreases from a node to its
+    /// descendants. Takes time linear in the number of weight entries in the node's ancestors.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the node is out of range.
+    pub fn ancestor_mass(&self, node: CompressedDagNodeRef) -> f64 {
+        let graph: &CompressedDag = self.graph.borrow();
+        let range_map = &self.segment_range_maps[node.segment];
+        assert!(
+            node.index < range_map.len(),
+            "index {} out of range for segment {} of length {}",
+            node.index,
+            node.segment,
+            range_map.len()
+        );
+        let segment_mass = |range_map: &RangeMap<f64>| {
+            range_map
+                .ranges()
+                .map(|w| w.value() * w.len() as f64)
+                .sum::<f64>()
+        };
+        let ancestors = graph
+            .node(node.segment)
+            .ancestors()
+            .iter()
+            .map(|segment| segment_mass(&self.segment_range_maps[*segment]))
+            .sum::<f64>();
+        let own = range_map
+            .ranges()
+            .take_while(|w| w.offset() <= node.index)
+            .map(|w| w.value() * (w.end().min(node.index + 1) - w.offset()) as f64)
+            .sum::<f64>();
+        ancestors + own
+    }
+
+    /// Returns the likelihood of every node, ordered by segment and then by index. The likelihoods
+    /// sum to 1.0, modulo rounding.
+    pub fn likelihood_distribution(&self) -> Vec<(CompressedDagNodeRef, f64)> {
+        self.segment_range_maps
+            .iter()
+            .enumerate()
+            .flat_map(|(segment, range_map)| {
+                range_map
+                    .iter_expanded()
+                    .enumerate()
+                    .map(move |(index, w)| (CompressedDagNodeRef { segment, index }, *w))
+            })
+            .collect()
+    }
+
+    /// Returns up to `k` nodes with the highest likelihoods, along with their likelihoods, sorted
+    /// by descending likelihood. Ties are broken by lower segment, then lower index.
+    pub fn best_n_nodes(&self, k: usize) -> Vec<(CompressedDagNodeRef, f64)> {
+        let mut ranges: Vec<(usize, &RangeMapEntry<f64>)> = self
+            .segment_range_maps
+            .iter()
+            .enumerate()
+            .flat_map(|(segment, range_map)| range_map.ranges().map(move |w| (segment, w)))
+            .collect();
+        ranges.sort_by(|(a_segment, a), (b_segment, b)| {
+            b.value()
+                .partial_cmp(a.value())
+                .unwrap_or(cmp::Ordering::Equal)
+                .then(a_segment.cmp(b_segment))
+                .then(a.offset().cmp(&b.offset()))
+        });
+        ranges
+            .into_iter()
+            .flat_map(|(segment, w)| {
+                (w.offset()..w.end())
+                    .map(move |index| (CompressedDagNodeRef { segment, index }, *w.value()))
+            })
+            .take(k)
+            .collect()
+    }
+
+    /// Returns the fraction of nodes whose likelihood is greater than `threshold`, which indicates
+    /// how much of the graph is still plausible. Every node counts equally, regardless of the
+    /// length of its segment. This starts at 1.0 for thresholds below the initial likelihood of
+    /// `1 / n` and approaches `1 / n` as the search converges on a single node. Returns 0.0 for an
+    /// empty graph.
+    pub fn plausible_fraction(&self, threshold: f64) -> f64 {
+        let mut plausible = 0;
+        let mut total = 0;
+        for range_map in &self.segment_range_maps {
+            for w in range_map.ranges() {
+                if *w.value() > threshold {
+                    plausible += w.len();
+                }
+                total += w.len();
+            }
+        }
+        if total == 0 {
+            0.0
+        } else {
+            plausible as f64 / total as f64
+        }
+    }
 }
 
 /// Performs a robust binary search over a CompressedDag and automatically infers the flakiness
 /// based on the votes.
+#[cfg(feature = "std")]
 #[derive(Clone, Debug)]
 pub struct AutoCompressedDagSearcher {
     searcher: CompressedDagSearcher,
     flakiness_tracker: CompressedDagFlakinessTracker,
 }
 
+#[cfg(feature = "std")]
 #[deprecated(note = "Use AutoCompressedDagSearcher instead.")]
 pub type AutoCompressedDAGSearcher = AutoCompressedDagSearcher;
 
+#[cfg(feature = "std")]
 impl AutoCompressedDagSearcher {
     /// Creates a new AutoCompressedDagSearcher.
     pub fn new(graph: Rc<CompressedDag>) -> Self {
@@ -581,6 +2360,13 @@ impl AutoCompressedDagSearcher {
         }
     }
 
+    /// Restores the state of a newly created AutoCompressedDagSearcher over the same graph,
+    /// discarding all votes, while reusing existing allocations where possible.
+    pub fn reset(&mut self) {
+        self.searcher.reset();
+        self.flakiness_tracker.reset();
+    }
+
     /// Adds a vote to the internal statistics. With low flakiness, nodes with false votes are
     /// expected not to nodes with true votes as ancestors.
     ///
@@ -593,11 +2379,27 @@ impl AutoCompressedDagSearcher {
             .report(node, heads, self.flakiness_tracker.flakiness());
     }
 
-    /// Returns the next node that should be tested.
+    /// Returns the next node that should be tested. Skipped nodes are avoided unless every node is
+    /// skipped.
     pub fn next_node(&self) -> CompressedDagNodeRef {
         self.searcher.next_node()
     }
 
+    /// Adds a node which cannot be tested. `next_node` will avoid returning this node. Unlike a
+    /// vote, this is not treated as evidence of flakiness.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the node is out of range.
+    pub fn add_skip(&mut self, node: CompressedDagNodeRef) {
+        self.searcher.add_skip(node);
+    }
+
+    /// Returns true if the node was added with `add_skip`.
+    pub fn is_skipped(&self, node: CompressedDagNodeRef) -> bool {
+        self.searcher.is_skipped(node)
+    }
+
     /// Returns the current estimate of the best node.
     pub fn best_node(&self) -> CompressedDagNodeRef {
         self.searcher.best_node()
@@ -612,15 +2414,101 @@ impl AutoCompressedDagSearcher {
         self.searcher.likelihood(index)
     }
 
+    /// Returns the likelihood of every node. See `CompressedDagSearcher::likelihood_distribution`.
+    pub fn likelihood_distribution(&self) -> Vec<(CompressedDagNodeRef, f64)> {
+        self.searcher.likelihood_distribution()
+    }
+
+    /// Returns up to `k` nodes with the highest likelihoods, along with their likelihoods, sorted
+    /// by descending likelihood. See `CompressedDagSearcher::best_n_nodes`.
+    pub fn best_n_nodes(&self, k: usize) -> Vec<(CompressedDagNodeRef, f64)> {
+        self.searcher.best_n_nodes(k)
+    }
+
+    /// Returns the likelihood of the node at the given linear index. See
+    /// `CompressedDagSearcher::likelihood_linear`.
+    pub fn likelihood_linear(&self, index: usize) -> f64 {
+        self.searcher.likelihood_linear(index)
+    }
+
+    /// Returns the probability that the transition is at or before the node. See
+    /// `CompressedDagSearcher::ancestor_mass`.
+    pub fn ancestor_mass(&self, node: CompressedDagNodeRef) -> f64 {
+        self.searcher.ancestor_mass(node)
+    }
+
+    /// Returns the fraction of nodes whose likelihood is greater than `threshold`. See
+    /// `CompressedDagSearcher::plausible_fraction`.
+    pub fn plausible_fraction(&self, threshold: f64) -> f64 {
+        self.searcher.plausible_fraction(threshold)
+    }
+
     /// Returns the estimated flakiness.
     pub fn flakiness(&self) -> f64 {
         self.flakiness_tracker.flakiness()
     }
+
+    /// Returns how many times the node returned by `next_node` should be tested before moving on,
+    /// given the estimated flakiness `f`. A flaky test returns a random result with probability
+    /// `f`, so each result is wrong with probability `f / 2`. This returns the smallest `n >= 1`
+    /// for which the chance of all `n` results being wrong, `(f / 2)^n`, is at most 1%, i.e.
+    /// `ceil(ln(0.01) / ln(f / 2))`. This ranges from 1 for `f <= 0.02` up to 7 for a completely
+    /// random test, and never decreases as `f` increases.
+    pub fn suggested_repeats(&self) -> usize {
+        const MAX_ERROR: f64 = 0.01;
+        let error = self.flakiness() / 2.0;
+        if error <= MAX_ERROR {
+            1
+        } else {
+            (MAX_ERROR.ln() / error.min(0.5).ln()).ceil() as usize
+        }
+    }
+
+    /// Returns the estimated flakiness of the given segment's branch, considering only votes in the
+    /// segment and its ancestors.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the segment is out of range.
+    pub fn branch_flakiness(&self, segment: usize) -> f64 {
+        self.flakiness_tracker.branch_flakiness(segment)
+    }
+
+    /// Returns the estimated flakiness of the given segment, considering only votes within it.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the segment is out of range.
+    pub fn segment_flakiness(&self, segment: usize) -> f64 {
+        self.flakiness_tracker.segment_flakiness(segment)
+    }
+
+    /// Returns the tracker used to estimate flakiness, e.g. for diagnosing which part of the graph
+    /// is noisy.
+    pub fn flakiness_tracker(&self) -> &CompressedDagFlakinessTracker {
+        &self.flakiness_tracker
+    }
+
+    /// Returns the number of votes reported with `report`.
+    pub fn total_votes(&self) -> usize {
+        self.flakiness_tracker.total_votes()
+    }
+
+    /// Returns separate flakiness estimates for the nodes before and after `best_node`, i.e. for
+    /// the nodes which should vote false and for `best_node` and its descendants, which should vote
+    /// true. The estimates differing widely suggests that the test is noisier on one side of the
+    /// change, e.g. because the feature is simply broken after it.
+    pub fn split_flakiness(&self) -> (f64, f64) {
+        self.flakiness_tracker
+            .split_flakiness(self.best_node(), DEFAULT_PRIOR_STRENGTH)
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::test_rng::TestRng;
+    use proptest::prelude::*;
 
     const DEFAULT_FLAKINESS: f64 = 0.01;
 
@@ -647,9 +2535,9 @@ mod tests {
     fn one_element_one() {
         let mut s = Searcher::new(1);
         assert_index!(s, 0, 0, false, DEFAULT_FLAKINESS);
-        assert_index!(s, 0, 1, false, DEFAULT_FLAKINESS);
-        assert_index!(s, 0, 1, false, DEFAULT_FLAKINESS);
-        assert_index!(s, 0, 1, false, DEFAULT_FLAKINESS);
+        assert_index!(s, 0, 0, false, DEFAULT_FLAKINESS);
+        assert_index!(s, 0, 0, false, DEFAULT_FLAKINESS);
+        assert_index!(s, 0, 0, false, DEFAULT_FLAKINESS);
     }
 
     #[test]
@@ -681,9 +2569,9 @@ mod tests {
     fn two_elements_two() {
         let mut s = Searcher::new(2);
         assert_index!(s, 1, 1, false, DEFAULT_FLAKINESS);
-        assert_index!(s, 1, 2, false, DEFAULT_FLAKINESS);
-        assert_index!(s, 1, 2, false, DEFAULT_FLAKINESS);
-        assert_index!(s, 1, 2, false, DEFAULT_FLAKINESS);
+        assert_index!(s, 1, 1, false, DEFAULT_FLAKINESS);
+        assert_index!(s, 1, 1, false, DEFAULT_FLAKINESS);
+        assert_index!(s, 1, 1, false, DEFAULT_FLAKINESS);
     }
 
     #[test]
@@ -724,9 +2612,9 @@ mod tests {
         let mut s = Searcher::new(3);
         assert_index!(s, 1, 1, false, DEFAULT_FLAKINESS);
         assert_index!(s, 2, 2, false, DEFAULT_FLAKINESS);
-        assert_index!(s, 2, 3, false, DEFAULT_FLAKINESS);
-        assert_index!(s, 2, 3, false, DEFAULT_FLAKINESS);
-        assert_index!(s, 2, 3, false, DEFAULT_FLAKINESS);
+        assert_index!(s, 2, 2, false, DEFAULT_FLAKINESS);
+        assert_index!(s, 2, 2, false, DEFAULT_FLAKINESS);
+        assert_index!(s, 2, 2, false, DEFAULT_FLAKINESS);
     }
 
     #[test]
@@ -762,73 +2650,31 @@ mod tests {
         assert_index!(s, 1021, 1021, false, DEFAULT_FLAKINESS);
         assert_index!(s, 1022, 1023, false, DEFAULT_FLAKINESS);
         assert_index!(s, 1023, 1023, false, DEFAULT_FLAKINESS);
-        assert_index!(s, 1023, 1024, false, DEFAULT_FLAKINESS);
-        assert_index!(s, 1023, 1024, false, DEFAULT_FLAKINESS);
-        assert_index!(s, 1023, 1024, false, DEFAULT_FLAKINESS);
-    }
-
-    #[test]
-    fn one_element_skip_zero() {
-        let mut s = Searcher::new(1);
-        s.add_skip(0);
-        assert_eq!(s.next_index(), None);
-    }
-
-    #[test]
-    fn two_elements_zero_skip_zero() {
-        let mut s = Searcher::new(2);
-        s.add_skip(0);
-        assert_index!(s, 1, 1, true, DEFAULT_FLAKINESS);
-        assert_index!(s, 1, 1, true, DEFAULT_FLAKINESS);
-        assert_index!(s, 1, 1, true, DEFAULT_FLAKINESS);
-    }
-
-    #[test]
-    fn two_elements_zero_skip_one() {
-        let mut s = Searcher::new(2);
-        s.add_skip(1);
-        assert_index!(s, 0, 1, true, DEFAULT_FLAKINESS);
-        assert_index!(s, 0, 0, true, DEFAULT_FLAKINESS);
-        assert_index!(s, 0, 0, true, DEFAULT_FLAKINESS);
-        assert_index!(s, 0, 0, true, DEFAULT_FLAKINESS);
-    }
-
-    #[test]
-    fn two_elements_one_skip_one() {
-        let mut s = Searcher::new(2);
-        s.add_skip(1);
-        assert_index!(s, 0, 1, false, DEFAULT_FLAKINESS);
-        assert_index!(s, 0, 1, false, DEFAULT_FLAKINESS);
-        assert_index!(s, 0, 1, false, DEFAULT_FLAKINESS);
+        assert_index!(s, 1023, 1023, false, DEFAULT_FLAKINESS);
+        assert_index!(s, 1023, 1023, false, DEFAULT_FLAKINESS);
+        assert_index!(s, 1023, 1023, false, DEFAULT_FLAKINESS);
     }
 
     #[test]
-    fn many_elements_first_skip_mid() {
+    fn with_len_f64_matches_new() {
+        let mut generic = Searcher::<f64>::with_len(1024);
         let mut s = Searcher::new(1024);
-        s.add_skip(512);
-        assert_index!(s, 513, 512, true, DEFAULT_FLAKINESS);
-        assert_index!(s, 273, 273, true, DEFAULT_FLAKINESS);
-        assert_index!(s, 145, 145, true, DEFAULT_FLAKINESS);
-        assert_index!(s, 77, 77, true, DEFAULT_FLAKINESS);
-        assert_index!(s, 41, 41, true, DEFAULT_FLAKINESS);
-        assert_index!(s, 21, 22, true, DEFAULT_FLAKINESS);
-        assert_index!(s, 11, 11, true, DEFAULT_FLAKINESS);
-        assert_index!(s, 5, 6, true, DEFAULT_FLAKINESS);
-        assert_index!(s, 2, 3, true, DEFAULT_FLAKINESS);
-        assert_index!(s, 1, 1, true, DEFAULT_FLAKINESS);
-        assert_index!(s, 0, 1, true, DEFAULT_FLAKINESS);
-        assert_index!(s, 0, 0, true, DEFAULT_FLAKINESS);
-        assert_index!(s, 0, 0, true, DEFAULT_FLAKINESS);
-        assert_index!(s, 0, 0, true, DEFAULT_FLAKINESS);
+        for (index, heads) in &[(512, false), (751, true), (600, false), (700, true)] {
+            generic.report(*index, *heads, DEFAULT_FLAKINESS);
+            s.report(*index, *heads, DEFAULT_FLAKINESS);
+            assert_eq!(generic.next_index(), s.next_index());
+            assert_eq!(generic.best_index(), s.best_index());
+        }
+        for i in 0..=1024 {
+            assert_eq!(generic.likelihood(i).to_bits(), s.likelihood(i).to_bits());
+        }
     }
 
     #[test]
-    fn many_elements_first_skip_mid2() {
-        let mut s = Searcher::new(1024);
-        s.add_skip(512);
-        s.add_skip(513);
-        assert_index!(s, 511, 512, true, DEFAULT_FLAKINESS);
-        assert_index!(s, 272, 272, true, DEFAULT_FLAKINESS);
+    fn many_elements_first_f32() {
+        let mut s = Searcher::<f32>::with_len(1024);
+        assert_index!(s, 512, 512, true, DEFAULT_FLAKINESS);
+        assert_index!(s, 272, 273, true, DEFAULT_FLAKINESS);
         assert_index!(s, 144, 145, true, DEFAULT_FLAKINESS);
         assert_index!(s, 76, 77, true, DEFAULT_FLAKINESS);
         assert_index!(s, 40, 41, true, DEFAULT_FLAKINESS);
@@ -844,97 +2690,1402 @@ mod tests {
     }
 
     #[test]
-    fn many_elements_first_skip_mid3() {
-        let mut s = Searcher::new(1024);
-        s.add_skip(512);
-        s.add_skip(513);
-        s.add_skip(511);
-        assert_index!(s, 514, 512, true, DEFAULT_FLAKINESS);
-        assert_index!(s, 273, 274, true, DEFAULT_FLAKINESS);
-        assert_index!(s, 145, 145, true, DEFAULT_FLAKINESS);
-        assert_index!(s, 77, 77, true, DEFAULT_FLAKINESS);
-        assert_index!(s, 41, 41, true, DEFAULT_FLAKINESS);
-        assert_index!(s, 21, 22, true, DEFAULT_FLAKINESS);
-        assert_index!(s, 11, 11, true, DEFAULT_FLAKINESS);
-        assert_index!(s, 5, 6, true, DEFAULT_FLAKINESS);
-        assert_index!(s, 2, 3, true, DEFAULT_FLAKINESS);
-        assert_index!(s, 1, 1, true, DEFAULT_FLAKINESS);
-        assert_index!(s, 0, 1, true, DEFAULT_FLAKINESS);
-        assert_index!(s, 0, 0, true, DEFAULT_FLAKINESS);
-        assert_index!(s, 0, 0, true, DEFAULT_FLAKINESS);
-        assert_index!(s, 0, 0, true, DEFAULT_FLAKINESS);
+    fn many_elements_last_f32() {
+        let mut s = Searcher::<f32>::with_len(1024);
+        assert_index!(s, 512, 512, false, DEFAULT_FLAKINESS);
+        assert_index!(s, 751, 752, false, DEFAULT_FLAKINESS);
+        assert_index!(s, 879, 879, false, DEFAULT_FLAKINESS);
+        assert_index!(s, 947, 947, false, DEFAULT_FLAKINESS);
+        assert_index!(s, 983, 983, false, DEFAULT_FLAKINESS);
+        assert_index!(s, 1002, 1003, false, DEFAULT_FLAKINESS);
+        assert_index!(s, 1012, 1013, false, DEFAULT_FLAKINESS);
+        assert_index!(s, 1018, 1018, false, DEFAULT_FLAKINESS);
+        assert_index!(s, 1021, 1021, false, DEFAULT_FLAKINESS);
+        assert_index!(s, 1022, 1023, false, DEFAULT_FLAKINESS);
+        assert_index!(s, 1023, 1023, false, DEFAULT_FLAKINESS);
+        assert_index!(s, 1023, 1023, false, DEFAULT_FLAKINESS);
+        assert_index!(s, 1023, 1023, false, DEFAULT_FLAKINESS);
+        assert_index!(s, 1023, 1023, false, DEFAULT_FLAKINESS);
     }
 
     #[test]
-    fn many_elements_first_skip_mid4() {
-        let mut s = Searcher::new(1024);
-        s.add_skip(512);
-        s.add_skip(513);
-        s.add_skip(511);
-        s.add_skip(514);
-        assert_index!(s, 510, 512, true, DEFAULT_FLAKINESS);
-        assert_index!(s, 271, 272, true, DEFAULT_FLAKINESS);
-        assert_index!(s, 144, 144, true, DEFAULT_FLAKINESS);
-        assert_index!(s, 76, 77, true, DEFAULT_FLAKINESS);
-        assert_index!(s, 40, 41, true, DEFAULT_FLAKINESS);
-        assert_index!(s, 21, 21, true, DEFAULT_FLAKINESS);
-        assert_index!(s, 11, 11, true, DEFAULT_FLAKINESS);
-        assert_index!(s, 5, 6, true, DEFAULT_FLAKINESS);
-        assert_index!(s, 2, 3, true, DEFAULT_FLAKINESS);
-        assert_index!(s, 1, 1, true, DEFAULT_FLAKINESS);
-        assert_index!(s, 0, 1, true, DEFAULT_FLAKINESS);
-        assert_index!(s, 0, 0, true, DEFAULT_FLAKINESS);
-        assert_index!(s, 0, 0, true, DEFAULT_FLAKINESS);
-        assert_index!(s, 0, 0, true, DEFAULT_FLAKINESS);
+    fn f32_long_search_stays_normalized() {
+        let mut s = Searcher::<f32>::with_len(1 << 20);
+        for _ in 0..2000 {
+            let index = s.next_index().unwrap();
+            s.report(index, index >= 12345, DEFAULT_FLAKINESS);
+        }
+        assert_eq!(s.best_index(), 12345);
+        let total: f64 = s.likelihoods().sum();
+        assert!((total - 1.0).abs() < 1e-3, "{}", total);
     }
 
     #[test]
-    fn many_elements_mid_skip_mid() {
-        let mut s = Searcher::new(1024);
-        s.add_skip(512);
-        assert_index!(s, 513, 512, true, DEFAULT_FLAKINESS);
-        assert_index!(s, 273, 273, false, DEFAULT_FLAKINESS);
-        assert_index!(s, 401, 401, false, DEFAULT_FLAKINESS);
-        assert_index!(s, 469, 469, false, DEFAULT_FLAKINESS);
-        assert_index!(s, 505, 506, false, DEFAULT_FLAKINESS);
-        assert_index!(s, 687, 687, true, DEFAULT_FLAKINESS);
-        assert_index!(s, 529, 530, true, DEFAULT_FLAKINESS);
-        assert_index!(s, 509, 509, false, DEFAULT_FLAKINESS);
-        assert_index!(s, 513, 512, true, DEFAULT_FLAKINESS);
-        assert_index!(s, 511, 511, false, DEFAULT_FLAKINESS);
-        assert_index!(s, 513, 512, true, DEFAULT_FLAKINESS);
-        assert_index!(s, 513, 512, true, DEFAULT_FLAKINESS);
-        assert_index!(s, 513, 512, true, DEFAULT_FLAKINESS);
+    fn with_prior_uniform() {
+        let s = Searcher::with_prior(100, |_| 3.0);
+        let fresh = Searcher::new(100);
+        for i in 0..=100 {
+            assert!((s.likelihood(i) - fresh.likelihood(i)).abs() < 1e-12);
+        }
+        assert_eq!(s.next_index(), fresh.next_index());
+        assert_eq!(s.best_index(), fresh.best_index());
     }
 
     #[test]
-    fn graph_confidence_percentile_nearest_singleton() {
-        let mut graph = CompressedDag::default();
-        graph.add_node(CompressedDagSegment::new(1), vec![]);
-        let searcher = CompressedDagSearcher::new(Rc::new(graph));
-        assert_eq!(
-            searcher.confidence_percentile_nearest(0.5),
-            CompressedDagNodeRef {
-                segment: 0,
-                index: 0
-            }
-        );
+    fn with_prior_step() {
+        let s = Searcher::with_prior(1024, |i| if i >= 512 { 9.0 } else { 1.0 });
+        let total: f64 = s.likelihoods().sum();
+        assert!((total - 1.0).abs() < 1e-9);
+        assert!((s.likelihood(600) - 9.0 * s.likelihood(100)).abs() < 1e-12);
+        assert!(s.next_index().unwrap() > 700, "{:?}", s.next_index());
     }
 
     #[test]
-    fn graph_confidence_percentile_nearest_single_segment() {
-        let mut graph = CompressedDag::default();
-        graph.add_node(CompressedDagSegment::new(10), vec![]);
-        let searcher = CompressedDagSearcher::new(Rc::new(graph));
-        assert_eq!(
-            searcher.confidence_percentile_nearest(0.5),
-            CompressedDagNodeRef {
-                segment: 0,
-                index: 4
+    fn merge() {
+        let votes = [
+            (500, true),
+            (250, false),
+            (375, true),
+            (300, false),
+            (340, true),
+            (320, false),
+            (330, true),
+            (250, true),
+        ];
+        let mut whole = Searcher::new(1000);
+        let mut first = Searcher::new(1000);
+        let mut second = Searcher::new(1000);
+        for (i, (index, heads)) in votes.iter().enumerate() {
+            whole.report(*index, *heads, 0.1);
+            if i % 2 == 0 {
+                first.report(*index, *heads, 0.1);
+            } else {
+                second.report(*index, *heads, 0.1);
             }
-        );
-    }
-
+        }
+        second.add_skip(17);
+        first.merge(&second);
+        for i in 0..=1000 {
+            assert!(
+                (first.likelihood(i) - whole.likelihood(i)).abs() < 1e-12,
+                "index {}: {} != {}",
+                i,
+                first.likelihood(i),
+                whole.likelihood(i)
+            );
+        }
+        assert_eq!(first.best_index(), whole.best_index());
+        assert!(first.skips.contains(&17));
+    }
+
+    #[test]
+    #[should_panic(expected = "cannot merge Searchers of different lengths")]
+    fn merge_different_lengths() {
+        Searcher::new(10).merge(&Searcher::new(11));
+    }
+
+    fn values(weights: &RangeMap<f64>) -> Vec<f64> {
+        (0..weights.len())
+            .map(|i| *weights.range_for_index(i).value())
+            .collect()
+    }
+
+    fn check_report_range(weights: &RangeMap<f64>, index: usize, heads: bool) {
+        let before = values(weights);
+        let mut weights = weights.clone();
+        let added = report_range(&mut weights, index, heads, 0.5);
+        let after = values(&weights);
+        let mut expected_added = 0.0;
+        for (i, (b, a)) in before.iter().zip(&after).enumerate() {
+            let expected = if (i <= index) == heads { b * 1.5 } else { *b };
+            assert_eq!(*a, expected, "index {} heads {} at {}", index, heads, i);
+            expected_added += expected - b;
+        }
+        assert!((added - expected_added).abs() < 1e-12);
+    }
+
+    #[test]
+    fn report_range_scales_each_index_once() {
+        // Entries cover [0, 3), [3, 7), and [7, 10).
+        let weights = RangeMap::from_fn(10, |i| match i {
+            0..=2 => 1.0,
+            3..=6 => 2.0,
+            _ => 4.0,
+        });
+        assert_eq!(weights.ranges().count(), 3);
+        for &heads in &[false, true] {
+            // Range boundaries.
+            check_report_range(&weights, 2, heads);
+            check_report_range(&weights, 3, heads);
+            check_report_range(&weights, 6, heads);
+            // Mid-range.
+            check_report_range(&weights, 4, heads);
+            // First and last indices.
+            check_report_range(&weights, 0, heads);
+            check_report_range(&weights, 9, heads);
+        }
+        // Single entry.
+        let weights = RangeMap::new(1, 1.0);
+        check_report_range(&weights, 0, true);
+        check_report_range(&weights, 0, false);
+    }
+
+    #[test]
+    fn lazy_normalization() {
+        let mut lazy = Searcher::new(1024);
+        let mut eager = RangeMap::new(1025, 1.0 / 1025.0);
+        let mut rng = TestRng::new(54321);
+        for i in 0..5000 {
+            let index = rng.below(1024);
+            let heads = (index >= 300) != (i % 7 == 0);
+            lazy.report_with_stiffness(index, heads, 0.5);
+            report_range(&mut eager, index, heads, 0.5);
+            normalize(&mut eager);
+        }
+        for i in 0..=1024 {
+            let expected = *eager.range_for_index(i).value();
+            assert!(
+                (lazy.likelihood(i) - expected).abs() < 1e-9,
+                "index {}: {} != {}",
+                i,
+                lazy.likelihood(i),
+                expected
+            );
+        }
+        assert_eq!(lazy.best_index(), confidence_percentile_ceil(&eager, 0.5).0);
+    }
+
+    #[test]
+    fn report_weighted() {
+        let mut weighted = Searcher::new(1024);
+        let mut s = Searcher::new(1024);
+        for (index, heads) in &[(512, false), (751, true), (600, false)] {
+            weighted.report_weighted(*index, *heads, DEFAULT_FLAKINESS, 1.0);
+            s.report(*index, *heads, DEFAULT_FLAKINESS);
+        }
+        for i in 0..=1024 {
+            assert_eq!(weighted.likelihood(i).to_bits(), s.likelihood(i).to_bits());
+        }
+
+        let start = Searcher::new(1024).best_index();
+        let mut reliable = Searcher::new(1024);
+        reliable.report_weighted(512, false, DEFAULT_FLAKINESS, 1.0);
+        let mut unreliable = Searcher::new(1024);
+        unreliable.report_weighted(512, false, DEFAULT_FLAKINESS, 0.1);
+        assert!(
+            unreliable.best_index() - start < reliable.best_index() - start,
+            "{} {} {}",
+            start,
+            unreliable.best_index(),
+            reliable.best_index()
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "invalid reliability")]
+    fn report_weighted_invalid_reliability() {
+        Searcher::new(10).report_weighted(5, true, DEFAULT_FLAKINESS, 0.0);
+    }
+
+    #[test]
+    fn report_tracked() {
+        let mut s = Searcher::new(1024);
+        let mut changed = false;
+        for _ in 0..15 {
+            let index = s.next_index().unwrap();
+            changed |= s.report_tracked(index, index >= 300, DEFAULT_FLAKINESS);
+        }
+        assert!(changed);
+        assert_eq!(s.best_index(), 300);
+        assert!(!s.report_tracked(300, true, DEFAULT_FLAKINESS));
+        assert!(!s.report_tracked(299, false, DEFAULT_FLAKINESS));
+        assert!(!s.report_tracked(500, true, DEFAULT_FLAKINESS));
+        // Enough contradictory votes eventually move the estimate.
+        let mut contradictions = 0;
+        while !s.report_tracked(300, false, DEFAULT_FLAKINESS) {
+            assert_eq!(s.best_index(), 300);
+            contradictions += 1;
+            assert!(contradictions < 10);
+        }
+        assert_ne!(s.best_index(), 300);
+    }
+
+    #[test]
+    fn report_many() {
+        let mut sequential = Searcher::new(4096);
+        let mut batched = Searcher::new(4096);
+        let mut rng = TestRng::new(12345);
+        let mut votes = Vec::new();
+        for _ in 0..500 {
+            let index = rng.below(4096);
+            let flip = rng.below(10) == 0;
+            votes.push((index, (index >= 3000) != flip, 0.1));
+        }
+        for (index, heads, flakiness) in &votes {
+            sequential.report(*index, *heads, *flakiness);
+        }
+        batched.report_many(&votes);
+        for (a, b) in sequential.likelihoods().zip(batched.likelihoods()) {
+            assert!((a - b).abs() < 1e-9, "{} != {}", a, b);
+        }
+        assert_eq!(sequential.best_index(), batched.best_index());
+        assert_eq!(sequential.next_index(), batched.next_index());
+    }
+
+    #[test]
+    fn reset() {
+        let mut s = Searcher::new(100);
+        s.add_skip(50);
+        s.add_constraint(Constraint::GoodBelow(10)).unwrap();
+        s.report(50, true, 0.1);
+        s.report(25, false, 0.1);
+        s.reset();
+        let mut fresh = Searcher::new(100);
+        for heads in &[true, false, false, true, true] {
+            assert_eq!(s.next_index(), fresh.next_index());
+            assert_eq!(s.best_index(), fresh.best_index());
+            assert_eq!(
+                s.likelihoods().collect::<Vec<_>>(),
+                fresh.likelihoods().collect::<Vec<_>>()
+            );
+            let next = s.next_index().unwrap();
+            s.report(next, *heads, 0.1);
+            fresh.report(next, *heads, 0.1);
+        }
+    }
+
+    #[test]
+    fn auto_searcher_reset() {
+        let mut s = AutoSearcher::new(100);
+        s.report(50, true);
+        s.report(50, false);
+        s.reset();
+        let fresh = AutoSearcher::new(100);
+        assert_eq!(s.total_votes(), 0);
+        assert_eq!(s.flakiness(), fresh.flakiness());
+        assert_eq!(s.next_index(), fresh.next_index());
+        assert_eq!(s.best_index(), fresh.best_index());
+    }
+
+    #[test]
+    fn likelihoods() {
+        let mut s = Searcher::new(100);
+        s.report(50, true, 0.1);
+        s.report(25, false, 0.1);
+        s.report(37, true, 0.1);
+        assert_eq!(s.likelihoods().count(), 101);
+        let sum: f64 = s.likelihoods().sum();
+        assert!((sum - 1.0).abs() < 1e-9, "sum = {}", sum);
+        for i in 0..=100 {
+            assert_eq!(s.likelihoods().nth(i), Some(s.likelihood(i)));
+        }
+    }
+
+    #[test]
+    fn probability_mass() {
+        let mut s = Searcher::new(100);
+        s.report(50, true, 0.1);
+        s.report(25, false, 0.1);
+        s.report(37, true, 0.1);
+        let brute_force =
+            |start: usize, end: usize| -> f64 { (start..end).map(|i| s.likelihood(i)).sum() };
+        assert!((s.probability_mass(..) - 1.0).abs() < 1e-9);
+        assert!((s.probability_mass(0..=100) - 1.0).abs() < 1e-9);
+        for &(start, end) in &[
+            (0, 0),
+            (0, 25),
+            (20, 30),
+            (26, 37),
+            (30, 60),
+            (37, 38),
+            (90, 101),
+        ] {
+            assert!(
+                (s.probability_mass(start..end) - brute_force(start, end)).abs() < 1e-12,
+                "{}..{}",
+                start,
+                end
+            );
+        }
+        assert!((s.probability_mass(30..=60) - brute_force(30, 61)).abs() < 1e-12);
+        assert!((s.probability_mass(..37) - brute_force(0, 37)).abs() < 1e-12);
+        assert!((s.probability_mass(51..) - brute_force(51, 101)).abs() < 1e-12);
+        assert!(s.probability_mass(26..=37) > 0.5);
+    }
+
+    #[test]
+    fn cdf() {
+        let mut s = Searcher::new(100);
+        s.report(50, true, 0.1);
+        s.report(25, false, 0.1);
+        s.report(37, true, 0.1);
+        let cdf: Vec<(usize, f64)> = s.cdf().collect();
+        assert_eq!(cdf.len(), 101);
+        let mut previous = 0.0;
+        for (i, &(index, cumulative)) in cdf.iter().enumerate() {
+            assert_eq!(index, i);
+            assert!(
+                cumulative >= previous,
+                "{}: {} < {}",
+                i,
+                cumulative,
+                previous
+            );
+            assert!(
+                (cumulative - s.probability_mass(..=i)).abs() < 1e-12,
+                "{}: {} != {}",
+                i,
+                cumulative,
+                s.probability_mass(..=i)
+            );
+            previous = cumulative;
+        }
+        assert!((previous - 1.0).abs() < 1e-9, "{}", previous);
+    }
+
+    #[test]
+    #[should_panic(expected = "out of bounds")]
+    fn probability_mass_out_of_bounds() {
+        Searcher::new(100).probability_mass(50..102);
+    }
+
+    #[test]
+    fn top_k() {
+        let mut s = Searcher::new(100);
+        s.report(50, true, 0.1);
+        s.report(25, false, 0.1);
+        s.report(37, true, 0.1);
+        s.report(31, false, 0.1);
+        s.report(33, true, 0.1);
+        s.report(32, false, 0.1);
+        let top = s.top_k(5);
+        assert_eq!(top.len(), 5);
+        assert_eq!(top[0].0, s.best_index());
+        for (i, (index, likelihood)) in top.iter().enumerate() {
+            assert_eq!(*likelihood, s.likelihood(*index));
+            if i > 0 {
+                let (prev_index, prev_likelihood) = top[i - 1];
+                assert!(
+                    prev_likelihood > *likelihood
+                        || (prev_likelihood == *likelihood && prev_index < *index)
+                );
+            }
+        }
+        assert_eq!(s.top_k(1000).len(), 100);
+        assert!(s.top_k(0).is_empty());
+    }
+
+    #[test]
+    fn entropy_fresh() {
+        let s = Searcher::new(1024);
+        assert!((s.entropy() - 1025f64.log2()).abs() < 1e-9);
+    }
+
+    #[test]
+    fn entropy_decreases() {
+        let mut s = Searcher::new(1024);
+        let mut entropy = s.entropy();
+        for _ in 0..30 {
+            s.report(s.next_index().unwrap(), true, DEFAULT_FLAKINESS);
+            assert!(s.entropy() < entropy, "{} >= {}", s.entropy(), entropy);
+            entropy = s.entropy();
+        }
+        assert!(entropy < 0.1, "entropy = {}", entropy);
+    }
+
+    #[test]
+    fn zero_length() {
+        let s = Searcher::new(0);
+        assert!(s.is_empty());
+        assert_eq!(s.next_index(), None);
+        assert_eq!(s.next_index_with_rounding(Rounding::Down), None);
+        assert_eq!(s.next_index_with_rounding(Rounding::Up), None);
+        assert_eq!(s.best_index(), 0);
+        assert_eq!(s.likelihood(0), 1.0);
+        assert!(s.next_batch(3, |_| 1.0).is_empty());
+        let s = AutoSearcher::new(0);
+        assert_eq!(s.next_index(), None);
+        assert_eq!(s.best_index(), 0);
+    }
+
+    #[test]
+    fn len() {
+        for len in &[0, 1, 2, 10, 1024] {
+            let s = Searcher::new(*len);
+            assert_eq!(s.len(), *len);
+            assert_eq!(s.is_empty(), *len == 0);
+        }
+    }
+
+    #[test]
+    fn confidence_interval_fresh() {
+        let s = Searcher::new(1024);
+        let (low, high) = s.confidence_interval(0.8);
+        assert!((100..=105).contains(&low), "low = {}", low);
+        assert!((920..=925).contains(&high), "high = {}", high);
+    }
+
+    #[test]
+    fn confidence_interval_narrows() {
+        let mut s = Searcher::new(1024);
+        let mut width = 1024;
+        for _ in 0..20 {
+            let (low, high) = s.confidence_interval(0.8);
+            assert!(low <= s.best_index() && s.best_index() <= high);
+            assert!(high - low <= width, "{} > {}", high - low, width);
+            width = high - low;
+            s.report(s.next_index().unwrap(), true, DEFAULT_FLAKINESS);
+        }
+        assert_eq!(s.confidence_interval(0.8), (0, 0));
+    }
+
+    #[test]
+    fn best_index_in_range_when_all_good() {
+        let mut s = Searcher::new(10);
+        for _ in 0..20 {
+            for i in 0..10 {
+                s.report(i, false, DEFAULT_FLAKINESS);
+            }
+        }
+        assert!(s.likelihood(10) > 0.99);
+        assert_eq!(s.best_index(), 9);
+    }
+
+    #[test]
+    fn one_element_skip_zero() {
+        let mut s = Searcher::new(1);
+        s.add_skip(0);
+        assert_eq!(s.next_index(), None);
+    }
+
+    #[test]
+    fn two_elements_zero_skip_zero() {
+        let mut s = Searcher::new(2);
+        s.add_skip(0);
+        assert_index!(s, 1, 1, true, DEFAULT_FLAKINESS);
+        assert_index!(s, 1, 1, true, DEFAULT_FLAKINESS);
+        assert_index!(s, 1, 1, true, DEFAULT_FLAKINESS);
+    }
+
+    #[test]
+    fn two_elements_zero_skip_one() {
+        let mut s = Searcher::new(2);
+        s.add_skip(1);
+        assert_index!(s, 0, 1, true, DEFAULT_FLAKINESS);
+        assert_index!(s, 0, 0, true, DEFAULT_FLAKINESS);
+        assert_index!(s, 0, 0, true, DEFAULT_FLAKINESS);
+        assert_index!(s, 0, 0, true, DEFAULT_FLAKINESS);
+    }
+
+    #[test]
+    fn two_elements_one_skip_one() {
+        let mut s = Searcher::new(2);
+        s.add_skip(1);
+        assert_index!(s, 0, 1, false, DEFAULT_FLAKINESS);
+        assert_index!(s, 0, 1, false, DEFAULT_FLAKINESS);
+        assert_index!(s, 0, 1, false, DEFAULT_FLAKINESS);
+    }
+
+    #[test]
+    fn many_elements_first_skip_mid() {
+        let mut s = Searcher::new(1024);
+        s.add_skip(512);
+        assert_index!(s, 513, 512, true, DEFAULT_FLAKINESS);
+        assert_index!(s, 273, 273, true, DEFAULT_FLAKINESS);
+        assert_index!(s, 145, 145, true, DEFAULT_FLAKINESS);
+        assert_index!(s, 77, 77, true, DEFAULT_FLAKINESS);
+        assert_index!(s, 41, 41, true, DEFAULT_FLAKINESS);
+        assert_index!(s, 21, 22, true, DEFAULT_FLAKINESS);
+        assert_index!(s, 11, 11, true, DEFAULT_FLAKINESS);
+        assert_index!(s, 5, 6, true, DEFAULT_FLAKINESS);
+        assert_index!(s, 2, 3, true, DEFAULT_FLAKINESS);
+        assert_index!(s, 1, 1, true, DEFAULT_FLAKINESS);
+        assert_index!(s, 0, 1, true, DEFAULT_FLAKINESS);
+        assert_index!(s, 0, 0, true, DEFAULT_FLAKINESS);
+        assert_index!(s, 0, 0, true, DEFAULT_FLAKINESS);
+        assert_index!(s, 0, 0, true, DEFAULT_FLAKINESS);
+    }
+
+    #[test]
+    fn many_elements_first_skip_mid2() {
+        let mut s = Searcher::new(1024);
+        s.add_skip(512);
+        s.add_skip(513);
+        assert_index!(s, 511, 512, true, DEFAULT_FLAKINESS);
+        assert_index!(s, 272, 272, true, DEFAULT_FLAKINESS);
+        assert_index!(s, 144, 145, true, DEFAULT_FLAKINESS);
+        assert_index!(s, 76, 77, true, DEFAULT_FLAKINESS);
+        assert_index!(s, 40, 41, true, DEFAULT_FLAKINESS);
+        assert_index!(s, 21, 21, true, DEFAULT_FLAKINESS);
+        assert_index!(s, 11, 11, true, DEFAULT_FLAKINESS);
+        assert_index!(s, 5, 6, true, DEFAULT_FLAKINESS);
+        assert_index!(s, 2, 3, true, DEFAULT_FLAKINESS);
+        assert_index!(s, 1, 1, true, DEFAULT_FLAKINESS);
+        assert_index!(s, 0, 1, true, DEFAULT_FLAKINESS);
+        assert_index!(s, 0, 0, true, DEFAULT_FLAKINESS);
+        assert_index!(s, 0, 0, true, DEFAULT_FLAKINESS);
+        assert_index!(s, 0, 0, true, DEFAULT_FLAKINESS);
+    }
+
+    #[test]
+    fn many_elements_first_skip_mid3() {
+        let mut s = Searcher::new(1024);
+        s.add_skip(512);
+        s.add_skip(513);
+        s.add_skip(511);
+        assert_index!(s, 514, 512, true, DEFAULT_FLAKINESS);
+        assert_index!(s, 273, 274, true, DEFAULT_FLAKINESS);
+        assert_index!(s, 145, 145, true, DEFAULT_FLAKINESS);
+        assert_index!(s, 77, 77, true, DEFAULT_FLAKINESS);
+        assert_index!(s, 41, 41, true, DEFAULT_FLAKINESS);
+        assert_index!(s, 21, 22, true, DEFAULT_FLAKINESS);
+        assert_index!(s, 11, 11, true, DEFAULT_FLAKINESS);
+        assert_index!(s, 5, 6, true, DEFAULT_FLAKINESS);
+        assert_index!(s, 2, 3, true, DEFAULT_FLAKINESS);
+        assert_index!(s, 1, 1, true, DEFAULT_FLAKINESS);
+        assert_index!(s, 0, 1, true, DEFAULT_FLAKINESS);
+        assert_index!(s, 0, 0, true, DEFAULT_FLAKINESS);
+        assert_index!(s, 0, 0, true, DEFAULT_FLAKINESS);
+        assert_index!(s, 0, 0, true, DEFAULT_FLAKINESS);
+    }
+
+    #[test]
+    fn many_elements_first_skip_mid4() {
+        let mut s = Searcher::new(1024);
+        s.add_skip(512);
+        s.add_skip(513);
+        s.add_skip(511);
+        s.add_skip(514);
+        assert_index!(s, 510, 512, true, DEFAULT_FLAKINESS);
+        assert_index!(s, 271, 272, true, DEFAULT_FLAKINESS);
+        assert_index!(s, 144, 144, true, DEFAULT_FLAKINESS);
+        assert_index!(s, 76, 77, true, DEFAULT_FLAKINESS);
+        assert_index!(s, 40, 41, true, DEFAULT_FLAKINESS);
+        assert_index!(s, 21, 21, true, DEFAULT_FLAKINESS);
+        assert_index!(s, 11, 11, true, DEFAULT_FLAKINESS);
+        assert_index!(s, 5, 6, true, DEFAULT_FLAKINESS);
+        assert_index!(s, 2, 3, true, DEFAULT_FLAKINESS);
+        assert_index!(s, 1, 1, true, DEFAULT_FLAKINESS);
+        assert_index!(s, 0, 1, true, DEFAULT_FLAKINESS);
+        assert_index!(s, 0, 0, true, DEFAULT_FLAKINESS);
+        assert_index!(s, 0, 0, true, DEFAULT_FLAKINESS);
+        assert_index!(s, 0, 0, true, DEFAULT_FLAKINESS);
+    }
+
+    #[test]
+    fn many_elements_mid_skip_mid() {
+        let mut s = Searcher::new(1024);
+        s.add_skip(512);
+        assert_index!(s, 513, 512, true, DEFAULT_FLAKINESS);
+        assert_index!(s, 273, 273, false, DEFAULT_FLAKINESS);
+        assert_index!(s, 401, 401, false, DEFAULT_FLAKINESS);
+        assert_index!(s, 469, 469, false, DEFAULT_FLAKINESS);
+        assert_index!(s, 505, 506, false, DEFAULT_FLAKINESS);
+        assert_index!(s, 687, 687, true, DEFAULT_FLAKINESS);
+        assert_index!(s, 529, 530, true, DEFAULT_FLAKINESS);
+        assert_index!(s, 509, 509, false, DEFAULT_FLAKINESS);
+        assert_index!(s, 513, 512, true, DEFAULT_FLAKINESS);
+        assert_index!(s, 511, 511, false, DEFAULT_FLAKINESS);
+        assert_index!(s, 513, 512, true, DEFAULT_FLAKINESS);
+        assert_index!(s, 513, 512, true, DEFAULT_FLAKINESS);
+        assert_index!(s, 513, 512, true, DEFAULT_FLAKINESS);
+    }
+
+    #[test]
+    fn next_index_with_rounding_symmetric() {
+        let s = Searcher::new(4);
+        assert_eq!(s.next_index_with_rounding(Rounding::Down), Some(1));
+        assert_eq!(s.next_index_with_rounding(Rounding::Up), Some(2));
+        assert_eq!(
+            s.next_index_with_rounding(Rounding::Nearest),
+            s.next_index()
+        );
+    }
+
+    #[test]
+    fn next_index_with_rounding_skewed() {
+        let mut s = Searcher::new(4);
+        s.report(3, true, DEFAULT_FLAKINESS);
+        let down = s.next_index_with_rounding(Rounding::Down).unwrap();
+        let up = s.next_index_with_rounding(Rounding::Up).unwrap();
+        let nearest = s.next_index_with_rounding(Rounding::Nearest).unwrap();
+        assert!(down < up, "down = {}, up = {}", down, up);
+        assert!(nearest == down || nearest == up, "nearest = {}", nearest);
+    }
+
+    /// Returns a RangeMap with `entries` entries of pseudo-random lengths and values. Some values
+    /// are zero, and some neighbors are equal.
+    #[cfg(feature = "prefix_sums")]
+    fn random_weights(seed: u64, entries: usize) -> RangeMap<f64> {
+        let mut rng = TestRng::new(seed);
+        let mut values = Vec::new();
+        let mut splits = Vec::new();
+        for _ in 0..entries {
+            let value = match rng.below(8) {
+                0 => 0.0,
+                1 => values.last().cloned().unwrap_or(1.0),
+                n => n as f64 * (1.0 + rng.below(1000) as f64 / 1000.0),
+            };
+            splits.push(values.len());
+            for _ in 0..1 + rng.below(5) {
+                values.push(value);
+            }
+        }
+        let mut map = RangeMap::from_fn(values.len(), |i| values[i]);
+        // Keep neighboring entries with equal values apart.
+        for split in splits {
+            let _ = map.split(split);
+        }
+        normalize(&mut map);
+        map
+    }
+
+    #[cfg(feature = "prefix_sums")]
+    #[test]
+    fn prefix_sums_match_linear_scan() {
+        for seed in 0..50 {
+            let map = random_weights(seed, 1 + seed as usize * 3);
+            let sums = PrefixSumRangeMap::new(&map);
+            for i in 0..=100 {
+                let percentile = i as f64 / 100.0;
+                assert_eq!(
+                    sums.index_at_cumulative(percentile),
+                    confidence_percentile_ceil(&map, percentile),
+                    "seed {}, percentile {}",
+                    seed,
+                    percentile
+                );
+                assert_eq!(
+                    sums.nearest_index_at_cumulative(percentile),
+                    confidence_percentile_nearest(&map, percentile),
+                    "seed {}, percentile {}",
+                    seed,
+                    percentile
+                );
+            }
+        }
+    }
+
+    #[cfg(feature = "prefix_sums")]
+    #[test]
+    fn prefix_sums_many_entries() {
+        let map = random_weights(12345, 100000);
+        let start = std::time::Instant::now();
+        let sums = PrefixSumRangeMap::new(&map);
+        trace!(
+            "Building prefix sums over 100000 entries took {} seconds",
+            start.elapsed().as_secs_f64()
+        );
+        let start = std::time::Instant::now();
+        for i in 0..100 {
+            let percentile = i as f64 / 100.0;
+            assert_eq!(
+                sums.nearest_index_at_cumulative(percentile),
+                confidence_percentile_nearest(&map, percentile)
+            );
+        }
+        trace!(
+            "100 queries over 100000 entries took {} seconds",
+            start.elapsed().as_secs_f64()
+        );
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn serde_round_trip() {
+        let mut searcher = Searcher::new(100);
+        searcher.add_skip(17);
+        searcher.enable_trace();
+        for (index, heads) in &[(50, true), (25, false), (37, true), (31, true), (28, false)] {
+            searcher.report(*index, *heads, 0.1);
+        }
+        let json = serde_json::to_string(&searcher).unwrap();
+        let mut restored: Searcher = serde_json::from_str(&json).unwrap();
+        for i in 0..=100 {
+            assert_eq!(searcher.likelihood(i), restored.likelihood(i));
+        }
+        for heads in &[false, true, true, false, true] {
+            assert_eq!(searcher.best_index(), restored.best_index());
+            let next = searcher.next_index().unwrap();
+            assert_eq!(Some(next), restored.next_index());
+            searcher.report(next, *heads, 0.1);
+            restored.report(next, *heads, 0.1);
+        }
+        assert_eq!(searcher.best_index(), restored.best_index());
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn graph_serde_round_trip() {
+        //   1
+        //  / \
+        // 0   3
+        //  \ /
+        //   2
+        let mut graph = CompressedDag::default();
+        graph.add_node(CompressedDagSegment::new(10), vec![]);
+        graph.add_node(CompressedDagSegment::new(10), vec![0]);
+        graph.add_node(CompressedDagSegment::new(10), vec![0]);
+        graph.add_node(CompressedDagSegment::new(10), vec![1, 2]);
+        let mut searcher = CompressedDagSearcher::new(Rc::new(graph));
+        let target = 2;
+        let report = |s: &mut CompressedDagSearcher, node: CompressedDagNodeRef| {
+            let heads =
+                node.segment == target || s.graph.node(node.segment).ancestors().contains(&target);
+            s.report(node, heads, 0.1);
+        };
+        for _ in 0..5 {
+            let node = searcher.next_node();
+            report(&mut searcher, node);
+        }
+        let json = serde_json::to_string(&searcher).unwrap();
+        let mut restored: CompressedDagSearcher = serde_json::from_str(&json).unwrap();
+        assert_eq!(restored.graph.nodes().len(), 4);
+        assert_eq!(
+            restored.graph.node(3).ancestors(),
+            searcher.graph.node(3).ancestors()
+        );
+        for _ in 0..10 {
+            assert_eq!(searcher.best_node(), restored.best_node());
+            let node = searcher.next_node();
+            assert_eq!(node, restored.next_node());
+            report(&mut searcher, node);
+            report(&mut restored, node);
+        }
+        assert_eq!(searcher.best_node(), restored.best_node());
+        assert_eq!(
+            serde_json::to_string(&restored).unwrap(),
+            serde_json::to_string(&searcher).unwrap()
+        );
+    }
+
+    #[test]
+    fn optimal_stiffness_bounded() {
+        for flakiness in &[0.0, 1e-300, 1e-6, 0.5, 1.0, 2.0, 1e300] {
+            let stiffness = optimal_stiffness(*flakiness);
+            assert!(stiffness.is_finite(), "flakiness {}", flakiness);
+            assert!(stiffness > 0.0, "flakiness {}", flakiness);
+            assert!(stiffness <= MAX_STIFFNESS, "flakiness {}", flakiness);
+        }
+    }
+
+    #[test]
+    fn report_with_calculator() {
+        let calculator = InterpolatingStiffnessCalculator::new(vec![(0.1, 2.0)]);
+        let mut searcher = Searcher::new(10);
+        let mut expected = Searcher::new(10);
+        searcher.report_with_calculator(5, true, 0.1, &calculator);
+        expected.report_with_stiffness(5, true, 2.0);
+        assert_eq!(
+            searcher.likelihoods().collect::<Vec<_>>(),
+            expected.likelihoods().collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn zero_flakiness_crash() {
+        let mut searcher = Searcher::new(10);
+        searcher.report(5, true, 0.0);
+        searcher.report(4, false, 0.0);
+        assert_eq!(searcher.best_index(), 5);
+
+        let mut searcher = Searcher::new(10);
+        searcher.report_with_stiffness(5, true, optimal_stiffness(0.0));
+        searcher.report_with_stiffness(4, false, optimal_stiffness(0.0));
+        assert_eq!(searcher.best_index(), 5);
+        assert!(searcher.likelihood(5).is_finite());
+    }
+
+    #[test]
+    fn try_report_invalid_flakiness() {
+        for flakiness in &[0.0, -0.5, f64::NAN, 1.5, f64::INFINITY] {
+            let mut searcher = Searcher::new(10);
+            match searcher.try_report(5, true, *flakiness) {
+                Err(ReportError::InvalidFlakiness(f)) => {
+                    assert!(f == *flakiness || (f.is_nan() && flakiness.is_nan()))
+                }
+                Ok(()) => panic!("flakiness {} was accepted", flakiness),
+                Err(e) => panic!("unexpected error {}", e),
+            }
+            assert_eq!(searcher.likelihood(5), 1.0 / 11.0);
+        }
+        let mut searcher = Searcher::new(10);
+        assert_eq!(searcher.try_report(5, true, 1.0), Ok(()));
+        assert_eq!(searcher.try_report(5, true, 0.01), Ok(()));
+    }
+
+    /// Generates a length and a sequence of `(index, heads, flakiness)` votes for it. Lengths,
+    /// indices, and the number of votes shrink toward zero, and flakiness toward its smallest
+    /// value, which is the most likely to overflow.
+    fn votes() -> impl Strategy<Value = (usize, Vec<(usize, bool, f64)>)> {
+        let flakiness = prop_oneof![Just(1.0), 1e-12f64..=1.0];
+        (1usize..200).prop_flat_map(move |len| {
+            (
+                Just(len),
+                proptest::collection::vec((0..len, any::<bool>(), flakiness.clone()), 0..100),
+            )
+        })
+    }
+
+    proptest! {
+        #[test]
+        fn likelihoods_normalized_and_finite((len, votes) in votes()) {
+            let mut s = Searcher::new(len);
+            for (index, heads, flakiness) in votes {
+                s.report(index, heads, flakiness);
+                let mut sum = 0.0;
+                for (i, likelihood) in s.likelihoods().enumerate() {
+                    prop_assert!(likelihood.is_finite(), "likelihood({}) = {}", i, likelihood);
+                    prop_assert!(likelihood >= 0.0, "likelihood({}) = {}", i, likelihood);
+                    sum += likelihood;
+                }
+                prop_assert!((sum - 1.0).abs() < 1e-9, "sum = {}", sum);
+            }
+        }
+    }
+
+    #[test]
+    fn try_report_index_out_of_range() {
+        let mut searcher = Searcher::new(10);
+        let before = searcher.clone();
+        for &index in &[10, 11, usize::MAX] {
+            assert_eq!(
+                searcher.try_report(index, true, DEFAULT_FLAKINESS),
+                Err(ReportError::IndexOutOfRange { index, len: 10 })
+            );
+        }
+        assert_eq!(
+            searcher.likelihoods().collect::<Vec<_>>(),
+            before.likelihoods().collect::<Vec<_>>()
+        );
+        assert_eq!(
+            ReportError::IndexOutOfRange { index: 10, len: 10 }.to_string(),
+            "index 10 out of range for Searcher of length 10"
+        );
+        assert_eq!(searcher.try_report(9, true, DEFAULT_FLAKINESS), Ok(()));
+        assert!(searcher.likelihood(9) > searcher.likelihood(10));
+        // An empty Searcher has no testable indices.
+        assert_eq!(
+            Searcher::new(0).try_report(0, false, DEFAULT_FLAKINESS),
+            Err(ReportError::IndexOutOfRange { index: 0, len: 0 })
+        );
+    }
+
+    #[test]
+    fn report_clamps_invalid_flakiness() {
+        for flakiness in &[0.0, -0.5, f64::NAN, 1.5, f64::INFINITY] {
+            let mut searcher = Searcher::new(10);
+            searcher.report(5, true, *flakiness);
+            searcher.report(4, false, *flakiness);
+            for i in 0..=10 {
+                assert!(
+                    searcher.likelihood(i).is_finite(),
+                    "flakiness {}",
+                    flakiness
+                );
+            }
+            searcher.best_index();
+        }
+    }
+
+    #[test]
+    fn auto_searcher_flakiness() {
+        let mut s = AutoSearcher::new(100);
+        for i in 0..10 {
+            s.report(i * 10, i >= 5);
+        }
+        assert_eq!(s.total_votes(), 10);
+        let clean = s.flakiness();
+        for _ in 0..5 {
+            s.report(30, true);
+            s.report(30, false);
+        }
+        assert_eq!(s.total_votes(), 20);
+        assert!(s.flakiness() > clean, "{} <= {}", s.flakiness(), clean);
+    }
+
+    #[test]
+    fn report_indeterminate() {
+        let mut s = Searcher::new(100);
+        s.report(50, true, 0.1);
+        let next = s.next_index();
+        let best = s.best_index();
+        let likelihoods: Vec<f64> = s.likelihoods().collect();
+        s.report_indeterminate(30);
+        assert_eq!(s.next_index(), next);
+        assert_eq!(s.best_index(), best);
+        assert_eq!(s.likelihoods().collect::<Vec<_>>(), likelihoods);
+        assert_eq!(s.indeterminate_count(), 1);
+    }
+
+    #[test]
+    fn grow() {
+        let mut s = Searcher::new(100);
+        s.report(50, true, DEFAULT_FLAKINESS);
+        s.report(25, false, DEFAULT_FLAKINESS);
+        s.report(37, true, DEFAULT_FLAKINESS);
+        let before = s.likelihoods().collect::<Vec<_>>();
+        let best = s.best_index();
+        s.grow(50);
+        assert_eq!(s.len(), 150);
+        let after = s.likelihoods().collect::<Vec<_>>();
+        assert_eq!(after.len(), 151);
+        assert!((after.iter().sum::<f64>() - 1.0).abs() < 1e-9);
+        // Old indices keep their relative likelihoods.
+        let scale = after[0] / before[0];
+        for i in 0..100 {
+            assert!((after[i] - before[i] * scale).abs() < 1e-12, "index {}", i);
+        }
+        // Each new index gets the weight of the old end index.
+        for &likelihood in &after[100..] {
+            assert!((likelihood - before[100] * scale).abs() < 1e-12);
+            assert!(likelihood < after[best] / 100.0);
+        }
+        assert_eq!(s.best_index(), best);
+        // Growing a fresh Searcher matches creating a longer one.
+        let mut s = Searcher::new(10);
+        s.grow(5);
+        let fresh = Searcher::new(15);
+        assert_eq!(
+            s.likelihoods().collect::<Vec<_>>(),
+            fresh.likelihoods().collect::<Vec<_>>()
+        );
+        assert_eq!(s.next_index(), fresh.next_index());
+    }
+
+    #[test]
+    fn report_graded_extremes() {
+        for &heads in &[false, true] {
+            let mut graded = Searcher::new(100);
+            let mut s = Searcher::new(100);
+            for &index in &[50, 20, 70] {
+                graded.report_graded(index, if heads { 1.0 } else { 0.0 }, DEFAULT_FLAKINESS);
+                s.report(index, heads, DEFAULT_FLAKINESS);
+            }
+            assert_eq!(
+                graded.likelihoods().collect::<Vec<_>>(),
+                s.likelihoods().collect::<Vec<_>>()
+            );
+            assert_eq!(graded.next_index(), s.next_index());
+        }
+    }
+
+    #[test]
+    fn report_graded_half() {
+        // Symmetric.
+        let mut s = Searcher::new(100);
+        let before = s.likelihoods().collect::<Vec<_>>();
+        s.report_graded(50, 0.5, DEFAULT_FLAKINESS);
+        for (i, (a, b)) in s.likelihoods().zip(&before).enumerate() {
+            assert!((a - b).abs() < 1e-15, "index {}: {} != {}", i, a, b);
+        }
+        // Not symmetric.
+        s.report(30, true, DEFAULT_FLAKINESS);
+        let before = s.likelihoods().collect::<Vec<_>>();
+        s.report_graded(60, 0.5, DEFAULT_FLAKINESS);
+        for (i, (a, b)) in s.likelihoods().zip(&before).enumerate() {
+            assert!((a - b).abs() < 1e-15, "index {}: {} != {}", i, a, b);
+        }
+        // Partly bad shifts the likelihood toward lower indices, but less than a full vote.
+        let mut graded = Searcher::new(100);
+        graded.report_graded(50, 0.8, DEFAULT_FLAKINESS);
+        let mut full = Searcher::new(100);
+        full.report(50, true, DEFAULT_FLAKINESS);
+        assert!(graded.likelihood(10) > graded.likelihood(90));
+        assert!(graded.likelihood(10) < full.likelihood(10));
+    }
+
+    #[test]
+    #[should_panic(expected = "invalid badness 1.5")]
+    fn report_graded_invalid() {
+        Searcher::new(100).report_graded(50, 1.5, DEFAULT_FLAKINESS);
+    }
+
+    #[test]
+    fn report_with_stiffness_matches_report() {
+        for &flakiness in &[0.001, DEFAULT_FLAKINESS, 0.2, 1.0] {
+            let mut s = Searcher::new(100);
+            let mut expected = Searcher::new(100);
+            for &(index, heads) in &[(50, true), (25, false), (37, true), (90, false)] {
+                s.report_with_stiffness(index, heads, optimal_stiffness(flakiness));
+                expected.report(index, heads, flakiness);
+            }
+            assert_eq!(
+                s.likelihoods().collect::<Vec<_>>(),
+                expected.likelihoods().collect::<Vec<_>>()
+            );
+            assert_eq!(s.next_index(), expected.next_index());
+        }
+    }
+
+    #[test]
+    fn report_with_stiffness_zero() {
+        let mut s = Searcher::new(100);
+        s.report(30, true, DEFAULT_FLAKINESS);
+        let before = s.likelihoods().collect::<Vec<_>>();
+        s.report_with_stiffness(60, false, 0.0);
+        assert_eq!(s.likelihoods().collect::<Vec<_>>(), before);
+    }
+
+    #[test]
+    #[should_panic(expected = "invalid stiffness -1")]
+    fn report_with_stiffness_negative() {
+        Searcher::new(100).report_with_stiffness(50, true, -1.0);
+    }
+
+    #[test]
+    fn explain() {
+        let mut s = Searcher::new(100);
+        for &(index, heads) in &[
+            (50, true),
+            (25, false),
+            (37, true),
+            (31, false),
+            (34, true),
+            (32, false),
+            (33, true),
+        ] {
+            s.report(index, heads, DEFAULT_FLAKINESS);
+        }
+        let likelihoods = s.likelihoods().collect::<Vec<_>>();
+        for index in 0..=100 {
+            let explanation = s.explain(index);
+            assert_eq!(explanation.index, index);
+            assert_eq!(explanation.likelihood, s.likelihood(index));
+            let sum = explanation.mass_below + explanation.likelihood + explanation.mass_above;
+            assert!((sum - 1.0).abs() < 1e-9, "index {}: {}", index, sum);
+            let below = likelihoods[..index].iter().sum::<f64>();
+            assert!((explanation.mass_below - below).abs() < 1e-9);
+            let rank = likelihoods
+                .iter()
+                .filter(|l| **l > explanation.likelihood)
+                .count();
+            assert_eq!(explanation.rank, rank);
+            let runner_up = likelihoods
+                .iter()
+                .enumerate()
+                .filter(|(i, _)| *i != index)
+                .map(|(_, l)| *l)
+                .fold(0.0, f64::max);
+            assert_eq!(explanation.margin, explanation.likelihood - runner_up);
+        }
+        let best = s.explain(s.best_index());
+        assert_eq!(best.rank, 0);
+        assert!(best.margin > 0.0);
+        let explanations = (0..=100).map(|i| s.explain(i)).collect::<Vec<_>>();
+        assert_eq!(explanations.iter().filter(|e| e.rank == 0).count(), 1);
+
+        // Every index ties for the lead at the start.
+        let explanation = Searcher::new(3).explain(1);
+        assert_eq!(explanation.rank, 0);
+        assert_eq!(explanation.margin, 0.0);
+        assert_eq!(explanation.mass_below, 0.25);
+        assert_eq!(explanation.mass_above, 0.5);
+    }
+
+    #[test]
+    fn estimated_remaining_probes() {
+        let mut s = Searcher::new(1000);
+        let mut estimates = vec![s.estimated_remaining_probes(0.9, DEFAULT_FLAKINESS)];
+        while let Some(index) = s.next_index() {
+            if estimates.last() == Some(&0) {
+                break;
+            }
+            s.report(index, index >= 300, DEFAULT_FLAKINESS);
+            estimates.push(s.estimated_remaining_probes(0.9, DEFAULT_FLAKINESS));
+        }
+        assert_eq!(estimates.last(), Some(&0));
+        assert!(s.likelihood(s.best_index()) >= 0.9);
+        // The estimate counts down roughly one test at a time.
+        for window in estimates.windows(2) {
+            assert!(window[1] <= window[0], "{:?}", estimates);
+        }
+        let tests = estimates.len() - 1;
+        assert!(
+            (estimates[0] as f64 - tests as f64).abs() <= 0.5 * tests as f64,
+            "{:?}",
+            estimates
+        );
+        assert_eq!(s.estimated_remaining_probes(0.5, DEFAULT_FLAKINESS), 0);
+    }
+
+    #[test]
+    fn weight_ranges() {
+        let mut s = Searcher::new(100);
+        assert_eq!(
+            s.weight_ranges().collect::<Vec<_>>(),
+            vec![(0, 101, 1.0 / 101.0)]
+        );
+        s.report(50, true, DEFAULT_FLAKINESS);
+        s.report(20, false, DEFAULT_FLAKINESS);
+        s.report(35, true, DEFAULT_FLAKINESS);
+        let ranges = s.weight_ranges().collect::<Vec<_>>();
+        assert!(ranges.len() < 101);
+        let mut expected_offset = 0;
+        let mut expanded = Vec::new();
+        for &(offset, len, value) in &ranges {
+            assert_eq!(offset, expected_offset);
+            assert!(len > 0);
+            expected_offset += len;
+            expanded.extend(core::iter::repeat_n(value, len));
+        }
+        assert_eq!(expected_offset, 101);
+        for (i, value) in expanded.iter().enumerate() {
+            assert_eq!(*value, s.likelihood(i));
+        }
+    }
+
+    #[test]
+    fn auto_searcher_converged() {
+        // Same votes as many_elements_first, but the stiffness adapts as the flakiness estimate drops.
+        let mut s = AutoSearcher::new(1024);
+        let mut converged_at = None;
+        for i in 0..20 {
+            if s.converged(0.9) {
+                converged_at = Some(i);
+                break;
+            }
+            s.report(s.next_index().unwrap(), true);
+        }
+        assert_eq!(converged_at, Some(14));
+        assert_eq!(s.best_index(), 0);
+        assert!(!s.converged(1.0));
+    }
+
+    #[test]
+    fn auto_searcher_is_hopeless() {
+        let mut rng = TestRng::new(99);
+        let mut noise = AutoSearcher::new(100);
+        let mut hopeless_at = None;
+        for i in 0..1000 {
+            if noise.is_hopeless(100) {
+                hopeless_at = Some(i);
+                break;
+            }
+            let index = noise.next_index().unwrap();
+            noise.report(index, rng.next_bool());
+        }
+        let hopeless_at = hopeless_at.expect("random votes never looked hopeless");
+        assert!(hopeless_at >= 100);
+        assert!(!noise.is_hopeless(hopeless_at + 1));
+
+        // A clean signal, and one where 30% of the votes are random, are not hopeless.
+        for &flakiness in &[0.0, 0.3] {
+            let mut s = AutoSearcher::new(100);
+            for _ in 0..1000 {
+                let index = s.next_index().unwrap();
+                let heads = if rng.next_f64() < flakiness {
+                    rng.next_bool()
+                } else {
+                    index >= 30
+                };
+                s.report(index, heads);
+                assert!(!s.is_hopeless(100), "flakiness {}", flakiness);
+            }
+        }
+    }
+
+    #[test]
+    fn auto_searcher_vote_summary() {
+        let mut s = AutoSearcher::new(100);
+        assert_eq!(s.vote_summary(), vec![]);
+        s.report(50, true);
+        s.report(20, false);
+        s.report(50, false);
+        s.report(50, true);
+        s.report(70, true);
+        s.report_indeterminate(20);
+        assert_eq!(s.vote_summary(), vec![(20, 2, 1), (50, 1, 2), (70, 0, 1)]);
+    }
+
+    #[test]
+    fn auto_searcher_recommended_target() {
+        let mut s = AutoSearcher::new(100);
+        let initial = s.recommended_target();
+        assert!((initial - (1.0 - 0.1 / (1.0 + 9.0 * s.flakiness()))).abs() < 1e-12);
+        for i in 0..10 {
+            s.report(i * 10, i >= 5);
+        }
+        let clean = s.recommended_target();
+        assert!(clean < initial, "{} >= {}", clean, initial);
+        assert!(clean >= 0.9);
+        for _ in 0..5 {
+            s.report(30, true);
+            s.report(30, false);
+        }
+        let flaky = s.recommended_target();
+        assert!(flaky > clean, "{} <= {}", flaky, clean);
+        assert!(flaky <= 0.99);
+    }
+
+    #[test]
+    fn auto_searcher_report_indeterminate() {
+        let mut s = AutoSearcher::new(100);
+        for i in 0..10 {
+            s.report(i * 10, i >= 5);
+        }
+        let next = s.next_index();
+        let best = s.best_index();
+        let flakiness = s.flakiness();
+        s.report_indeterminate(30);
+        s.report_indeterminate(60);
+        assert_eq!(s.next_index(), next);
+        assert_eq!(s.best_index(), best);
+        assert!(
+            s.flakiness() > flakiness,
+            "{} <= {}",
+            s.flakiness(),
+            flakiness
+        );
+        assert_eq!(s.total_votes(), 10);
+    }
+
+    #[test]
+    fn search_linear_deterministic() {
+        let mut calls = 0;
+        let best = search_linear(1024, 0.99, None, |index| {
+            calls += 1;
+            index >= 637
+        });
+        assert_eq!(best, 637);
+        assert!(calls < 100, "calls = {}", calls);
+    }
+
+    #[test]
+    fn search_linear_max_iterations() {
+        let mut calls = 0;
+        search_linear(1024, 0.99, Some(3), |index| {
+            calls += 1;
+            index >= 637
+        });
+        assert_eq!(calls, 3);
+    }
+
+    #[test]
+    fn next_batch_spread() {
+        let searcher = Searcher::new(100);
+        let batch = searcher.next_batch(3, |_| 1.0);
+        assert_eq!(batch.len(), 3);
+        assert!((20..=30).contains(&batch[0]), "{:?}", batch);
+        assert!((45..=55).contains(&batch[1]), "{:?}", batch);
+        assert!((70..=80).contains(&batch[2]), "{:?}", batch);
+    }
+
+    #[test]
+    fn next_batch_cost() {
+        let searcher = Searcher::new(100);
+        let batch = searcher.next_batch(2, |i| if i < 50 { 10.0 } else { 1.0 });
+        assert_eq!(batch, vec![50, 75]);
+    }
+
+    #[test]
+    fn next_batch_small() {
+        let mut searcher = Searcher::new(3);
+        searcher.add_skip(1);
+        assert_eq!(searcher.next_batch(5, |_| 1.0), vec![0, 2]);
+    }
+
+    #[test]
+    fn constraint_survives_flaky_vote() {
+        let mut searcher = Searcher::new(100);
+        searcher.add_constraint(Constraint::GoodBelow(40)).unwrap();
+        searcher.add_constraint(Constraint::BadAbove(90)).unwrap();
+        // Contradicts GoodBelow(40).
+        searcher.report(20, true, 0.3);
+        // Contradicts BadAbove(90).
+        searcher.report(95, false, 0.3);
+        for i in (0..40).chain(92..=100) {
+            assert_eq!(searcher.likelihood(i), 0.0, "index {}", i);
+        }
+        let total: f64 = (40..92).map(|i| searcher.likelihood(i)).sum();
+        assert!((total - 1.0).abs() < 1e-9, "total = {}", total);
+        assert!((40..92).contains(&searcher.best_index()));
+        assert!((40..92).contains(&searcher.next_index().unwrap()));
+    }
+
+    #[test]
+    fn constraint_errors() {
+        let mut searcher = Searcher::new(100);
+        assert_eq!(
+            searcher.add_constraint(Constraint::GoodBelow(100)),
+            Err(ConstraintError::OutOfRange)
+        );
+        searcher.add_constraint(Constraint::GoodBelow(50)).unwrap();
+        assert_eq!(
+            searcher.add_constraint(Constraint::BadAbove(30)),
+            Err(ConstraintError::Conflict)
+        );
+        // The failed constraint was not applied.
+        assert!(searcher.likelihood(60) > 0.0);
+        // Bad everywhere above 49 with good below 50 pins the transition to 50.
+        searcher.add_constraint(Constraint::BadAbove(49)).unwrap();
+        assert_eq!(searcher.likelihood(50), 1.0);
+    }
+
+    #[test]
+    fn stiffness_for() {
+        let mut searcher = Searcher::new(10);
+        assert_eq!(searcher.stiffness_for(0.1), optimal_stiffness(0.1));
+        assert_eq!(searcher.stiffness_for(0.5), optimal_stiffness(0.5));
+        searcher.set_stiffness_fn(|flakiness| 2.0 * flakiness);
+        assert_eq!(searcher.stiffness_for(0.1), 0.2);
+        assert_eq!(searcher.stiffness_for(0.5), 1.0);
+    }
+
+    #[test]
+    fn rebase_votes_onto_larger_range() {
+        let mut searcher = Searcher::new(10);
+        searcher.enable_trace();
+        searcher.add_skip(7);
+        for _ in 0..5 {
+            searcher.report(3, false, 0.01);
+            searcher.report(4, true, 0.01);
+        }
+        assert_eq!(searcher.best_index(), 4);
+        // Two new indices are inserted before the old range.
+        let rebased = searcher.rebase_votes(12, |i| Some(i + 2));
+        assert_eq!(rebased.best_index(), 6);
+        assert!(rebased.skips.contains(&9));
+        assert!((rebased.likelihood(6) - searcher.likelihood(4)).abs() < 1e-3);
+        // Votes which no longer exist are dropped.
+        let dropped = searcher.rebase_votes(12, |i| if i == 4 { None } else { Some(i) });
+        assert!(dropped.best_index() > 4);
+    }
+
+    #[test]
+    #[should_panic]
+    fn rebase_votes_requires_trace() {
+        Searcher::new(10).rebase_votes(12, Some);
+    }
+
+    #[test]
+    fn graph_confidence_percentile_nearest_singleton() {
+        let mut graph = CompressedDag::default();
+        graph.add_node(CompressedDagSegment::new(1), vec![]);
+        let searcher = CompressedDagSearcher::new(Rc::new(graph));
+        assert_eq!(
+            searcher.confidence_percentile_nearest(0.5),
+            CompressedDagNodeRef {
+                segment: 0,
+                index: 0
+            }
+        );
+    }
+
+    #[test]
+    fn graph_confidence_percentile_nearest_single_segment() {
+        let mut graph = CompressedDag::default();
+        graph.add_node(CompressedDagSegment::new(10), vec![]);
+        let searcher = CompressedDagSearcher::new(Rc::new(graph));
+        assert_eq!(
+            searcher.confidence_percentile_nearest(0.5),
+            CompressedDagNodeRef {
+                segment: 0,
+                index: 4
+            }
+        );
+    }
+
     #[test]
     fn graph_confidence_percentile_nearest_parallel_segments() {
         let mut graph = CompressedDag::default();
@@ -1048,13 +4199,223 @@ mod tests {
             );
             $searcher.report(
                 CompressedDagNodeRef {
-                    segment: $next.0,
-                    index: $next.1,
+                    segment: $next.0,
+                    index: $next.1,
+                },
+                $heads,
+                $flakiness,
+            );
+        };
+    }
+
+    /// Reference implementation of `CompressedDagSearcher::report` which scales segments in a
+    /// separate pass.
+    fn report_separate_passes(
+        s: &mut CompressedDagSearcher,
+        node: CompressedDagNodeRef,
+        heads: bool,
+        flakiness: f64,
+    ) {
+        let stiffness = optimal_stiffness(flakiness);
+        let graph = s.graph.clone();
+        *s.percentile_ranges.get_mut() = None;
+        let ancestor_segments = graph.node(node.segment).ancestors();
+        for segment in 0..graph.nodes().len() {
+            if ancestor_segments.contains(&segment) != heads || segment == node.segment {
+                continue;
+            }
+            for w in s.segment_range_maps[segment].ranges_mut() {
+                *w.value_mut() *= 1.0 + stiffness;
+            }
+        }
+        report_range(
+            &mut s.segment_range_maps[node.segment],
+            node.index,
+            heads,
+            stiffness,
+        );
+        let weight_sum: f64 = s
+            .segment_range_maps
+            .iter()
+            .map(|range_map| {
+                range_map
+                    .ranges()
+                    .map(|w| w.value() * w.len() as f64)
+                    .sum::<f64>()
+            })
+            .sum();
+        for range_map in &mut s.segment_range_maps {
+            for w in range_map.ranges_mut() {
+                *w.value_mut() /= weight_sum;
+            }
+        }
+    }
+
+    #[test]
+    fn graph_report_matches_separate_passes() {
+        //   1-2
+        //  /   \
+        // 0     4-5
+        //  \   /
+        //   3--
+        // 6 (unrelated)
+        let mut graph = CompressedDag::default();
+        graph.add_node(CompressedDagSegment::new(3), vec![]);
+        graph.add_node(CompressedDagSegment::new(5), vec![0]);
+        graph.add_node(CompressedDagSegment::new(2), vec![1]);
+        graph.add_node(CompressedDagSegment::new(7), vec![0]);
+        graph.add_node(CompressedDagSegment::new(1), vec![2, 3]);
+        graph.add_node(CompressedDagSegment::new(4), vec![4]);
+        graph.add_node(CompressedDagSegment::new(6), vec![]);
+        let graph = Rc::new(graph);
+        let target = 3;
+        let mut s = CompressedDagSearcher::new(graph.clone());
+        let mut reference = CompressedDagSearcher::new(graph.clone());
+        for i in 0..40 {
+            let node = s.next_node();
+            assert_eq!(node, reference.next_node());
+            assert_eq!(s.best_node(), reference.best_node());
+            // Every fifth vote is wrong.
+            let heads = (node.segment == target
+                || graph.node(node.segment).ancestors().contains(&target))
+                != (i % 5 == 4);
+            s.report(node, heads, 0.2);
+            report_separate_passes(&mut reference, node, heads, 0.2);
+            for (a, b) in s
+                .segment_range_maps
+                .iter()
+                .zip(&reference.segment_range_maps)
+            {
+                assert_eq!(
+                    a.ranges().collect::<Vec<_>>(),
+                    b.ranges().collect::<Vec<_>>()
+                );
+            }
+        }
+        assert_eq!(s.best_node().segment, target);
+    }
+
+    #[test]
+    fn graph_cached_percentile_ranges() {
+        let mut graph = CompressedDag::default();
+        graph.add_node(CompressedDagSegment::new(10), vec![]);
+        graph.add_node(CompressedDagSegment::new(20), vec![0]);
+        graph.add_node(CompressedDagSegment::new(30), vec![0]);
+        graph.add_node(CompressedDagSegment::new(5), vec![1, 2]);
+        let mut s = CompressedDagSearcher::new(Rc::new(graph));
+        for i in 0..20 {
+            assert_eq!(
+                *s.segment_percentile_ranges(),
+                s.compute_segment_percentile_ranges()
+            );
+            // Reading twice uses the cache.
+            assert_eq!(
+                *s.segment_percentile_ranges(),
+                s.compute_segment_percentile_ranges()
+            );
+            let node = s.next_node();
+            s.report(node, node.segment == 3 || i % 3 == 0, 0.1);
+        }
+
+        // Repeated queries without reports only compute the ranges once.
+        let mut graph = CompressedDag::default();
+        graph.add_node(CompressedDagSegment::new(1), vec![]);
+        for i in 1..10000 {
+            graph.add_node(CompressedDagSegment::new(1), vec![i - 1]);
+        }
+        let s = CompressedDagSearcher::new(Rc::new(graph));
+        let start = std::time::Instant::now();
+        let best = s.best_node();
+        for _ in 0..100 {
+            assert_eq!(s.best_node(), best);
+        }
+        trace!(
+            "100 best_node calls on a 10000 segment chain took {} seconds",
+            start.elapsed().as_secs_f64()
+        );
+    }
+
+    #[test]
+    fn graph_best_n_nodes() {
+        let mut graph = CompressedDag::default();
+        graph.add_node(CompressedDagSegment::new(10), vec![]);
+        graph.add_node(CompressedDagSegment::new(10), vec![0]);
+        graph.add_node(CompressedDagSegment::new(10), vec![0]);
+        graph.add_node(CompressedDagSegment::new(5), vec![1, 2]);
+        let mut s = CompressedDagSearcher::new(Rc::new(graph));
+        let target = CompressedDagNodeRef {
+            segment: 1,
+            index: 5,
+        };
+        for _ in 0..5 {
+            s.report(target, true, 0.01);
+            s.report(
+                CompressedDagNodeRef {
+                    segment: 1,
+                    index: 4,
                 },
-                $heads,
-                $flakiness,
+                false,
+                0.01,
             );
-        };
+        }
+        let best = s.best_n_nodes(7);
+        assert_eq!(best.len(), 7);
+        assert_eq!(best[0].0, target);
+        assert_eq!(best[0].0, s.best_node());
+        for (i, (node, likelihood)) in best.iter().enumerate() {
+            assert_eq!(*likelihood, s.likelihood(*node));
+            if i > 0 {
+                assert!(best[i - 1].1 >= *likelihood);
+            }
+        }
+        assert_eq!(s.best_n_nodes(1000).len(), 35);
+        assert!(s.best_n_nodes(0).is_empty());
+    }
+
+    #[test]
+    fn graph_skips() {
+        let mut graph = CompressedDag::default();
+        graph.add_node(CompressedDagSegment::new(5), vec![]);
+        graph.add_node(CompressedDagSegment::new(5), vec![0]);
+        let mut s = AutoCompressedDagSearcher::new(Rc::new(graph));
+        let node = |segment, index| CompressedDagNodeRef { segment, index };
+        let first = s.next_node();
+        assert_eq!(first, node(0, 4));
+        s.add_skip(first);
+        assert!(s.is_skipped(first));
+        assert_eq!(s.next_node(), node(0, 3));
+        s.add_skip(node(0, 3));
+        assert_eq!(s.next_node(), node(1, 0));
+        // Skips don't affect the weights.
+        assert_eq!(s.likelihood(first), 0.1);
+        assert_eq!(s.best_node(), first);
+        for segment in 0..2 {
+            for index in 0..5 {
+                s.add_skip(node(segment, index));
+            }
+        }
+        assert_eq!(s.next_node(), first);
+    }
+
+    #[test]
+    fn graph_long_chain() {
+        let mut graph = CompressedDag::default();
+        graph.add_node(CompressedDagSegment::new(1), vec![]);
+        for i in 1..10000 {
+            graph.add_node(CompressedDagSegment::new(1), vec![i - 1]);
+        }
+        let mut s = CompressedDagSearcher::new(Rc::new(graph));
+        for _ in 0..40 {
+            let node = s.next_node();
+            s.report(node, node.segment >= 1234, 0.01);
+        }
+        assert_eq!(
+            s.best_node(),
+            CompressedDagNodeRef {
+                segment: 1234,
+                index: 0
+            }
+        );
     }
 
     #[test]
@@ -1076,6 +4437,33 @@ mod tests {
         assert_graph_index!(s, (0, 0), (0, 1), false, DEFAULT_FLAKINESS);
     }
 
+    #[test]
+    fn graph_report_tracked() {
+        let mut graph = CompressedDag::default();
+        graph.add_node(CompressedDagSegment::new(64), vec![]);
+        let mut s = CompressedDagSearcher::new(Rc::new(graph));
+        for _ in 0..20 {
+            let node = s.next_node();
+            s.report_tracked(node, node.index >= 20, DEFAULT_FLAKINESS);
+        }
+        let best = s.best_node();
+        assert_eq!(
+            best,
+            CompressedDagNodeRef {
+                segment: 0,
+                index: 20
+            }
+        );
+        assert!(!s.report_tracked(best, true, DEFAULT_FLAKINESS));
+        let mut contradictions = 0;
+        while !s.report_tracked(best, false, DEFAULT_FLAKINESS) {
+            assert_eq!(s.best_node(), best);
+            contradictions += 1;
+            assert!(contradictions < 10);
+        }
+        assert_ne!(s.best_node(), best);
+    }
+
     #[test]
     fn graph_many_elements_last() {
         let mut graph = CompressedDag::default();
@@ -1197,6 +4585,201 @@ mod tests {
         assert_graph_index!(s, (1, 50), (1, 50), true, DEFAULT_FLAKINESS);
     }
 
+    #[test]
+    fn graph_auto_searcher_reset() {
+        let mut graph = CompressedDag::default();
+        graph.add_node(CompressedDagSegment::new(10), vec![]);
+        graph.add_node(CompressedDagSegment::new(20), vec![0]);
+        graph.add_node(CompressedDagSegment::new(30), vec![0]);
+        graph.add_node(CompressedDagSegment::new(5), vec![1, 2]);
+        let graph = Rc::new(graph);
+        let mut s = AutoCompressedDagSearcher::new(graph.clone());
+        let node = |segment, index| CompressedDagNodeRef { segment, index };
+        s.add_skip(node(1, 3));
+        s.report(node(1, 10), true);
+        s.report(node(2, 5), false);
+        s.report(node(2, 5), true);
+        assert_eq!(s.total_votes(), 3);
+        s.reset();
+        assert_eq!(s.total_votes(), 0);
+        let mut fresh = AutoCompressedDagSearcher::new(graph);
+        assert_eq!(s.flakiness(), fresh.flakiness());
+        assert_eq!(s.likelihood_distribution(), fresh.likelihood_distribution());
+        for heads in &[true, false, false, true, true] {
+            assert_eq!(s.next_node(), fresh.next_node());
+            assert_eq!(s.best_node(), fresh.best_node());
+            let next = s.next_node();
+            s.report(next, *heads);
+            fresh.report(next, *heads);
+        }
+        assert_eq!(s.flakiness(), fresh.flakiness());
+    }
+
+    #[test]
+    fn graph_split_flakiness() {
+        let mut graph = CompressedDag::default();
+        graph.add_node(CompressedDagSegment::new(50), vec![]);
+        graph.add_node(CompressedDagSegment::new(50), vec![0]);
+        graph.add_node(CompressedDagSegment::new(50), vec![0]);
+        graph.add_node(CompressedDagSegment::new(50), vec![1, 2]);
+        let mut s = AutoCompressedDagSearcher::new(Rc::new(graph));
+        let bad = CompressedDagNodeRef {
+            segment: 1,
+            index: 20,
+        };
+        let mut rng = TestRng::new(98765);
+        for _ in 0..200 {
+            let node = s.next_node();
+            let is_bad =
+                (node.segment == bad.segment && node.index >= bad.index) || (node.segment == 3);
+            // The test is reliable before the bad node and often random after it.
+            let noise = if is_bad { 0.6 } else { 0.0 };
+            let heads = if rng.next_f64() < noise {
+                rng.next_bool()
+            } else {
+                is_bad
+            };
+            s.report(node, heads);
+        }
+        assert_eq!(s.best_node(), bad);
+        let (before, after) = s.split_flakiness();
+        assert!(before < 0.1, "before = {}", before);
+        assert!(after > 0.5, "after = {}", after);
+    }
+
+    #[test]
+    fn graph_suggested_repeats() {
+        let mut graph = CompressedDag::default();
+        graph.add_node(CompressedDagSegment::new(100), vec![]);
+        let mut s = AutoCompressedDagSearcher::new(Rc::new(graph));
+        let node = |index| CompressedDagNodeRef { segment: 0, index };
+        for _ in 0..10 {
+            s.report(node(10), false);
+            s.report(node(90), true);
+        }
+        assert_eq!(s.suggested_repeats(), 1);
+        let mut previous = s.suggested_repeats();
+        // Contradictory votes raise the estimated flakiness, and with it the suggestion.
+        for _ in 0..40 {
+            s.report(node(10), true);
+            s.report(node(90), false);
+            let repeats = s.suggested_repeats();
+            assert!(
+                repeats >= previous,
+                "{} < {} at flakiness {}",
+                repeats,
+                previous,
+                s.flakiness()
+            );
+            previous = repeats;
+        }
+        assert!(
+            previous > 2,
+            "repeats = {}, flakiness = {}",
+            previous,
+            s.flakiness()
+        );
+        assert!(previous <= 7);
+    }
+
+    /// Oracle which reports a node as bad if it is `bad` or one of its descendants.
+    struct DescendantOracle {
+        graph: Rc<CompressedDag>,
+        bad: CompressedDagNodeRef,
+        calls: usize,
+    }
+
+    impl Oracle for DescendantOracle {
+        fn test(&mut self, node: CompressedDagNodeRef) -> bool {
+            self.calls += 1;
+            if node.segment == self.bad.segment {
+                node.index >= self.bad.index
+            } else {
+                self.graph
+                    .node(node.segment)
+                    .ancestors()
+                    .contains(&self.bad.segment)
+            }
+        }
+    }
+
+    #[test]
+    fn run_dag_search_parallel() {
+        let mut graph = CompressedDag::default();
+        graph.add_node(CompressedDagSegment::new(100), vec![]);
+        graph.add_node(CompressedDagSegment::new(100), vec![]);
+        let graph = Rc::new(graph);
+        for bad in &[(0, 0), (0, 99), (1, 0), (1, 37), (1, 99)] {
+            let bad = CompressedDagNodeRef {
+                segment: bad.0,
+                index: bad.1,
+            };
+            let mut oracle = DescendantOracle {
+                graph: graph.clone(),
+                bad,
+                calls: 0,
+            };
+            assert_eq!(run_dag_search(graph.clone(), 0.99, None, &mut oracle), bad);
+            assert!(oracle.calls < 100, "calls = {}", oracle.calls);
+        }
+    }
+
+    #[test]
+    fn run_dag_search_fork_join() {
+        let mut graph = CompressedDag::default();
+        graph.add_node(CompressedDagSegment::new(100), vec![]);
+        graph.add_node(CompressedDagSegment::new(100), vec![0]);
+        graph.add_node(CompressedDagSegment::new(100), vec![0]);
+        graph.add_node(CompressedDagSegment::new(100), vec![1, 2]);
+        let graph = Rc::new(graph);
+        let bad = CompressedDagNodeRef {
+            segment: 2,
+            index: 50,
+        };
+        let mut oracle = DescendantOracle {
+            graph: graph.clone(),
+            bad,
+            calls: 0,
+        };
+        assert_eq!(run_dag_search(graph.clone(), 0.99, None, &mut oracle), bad);
+        // Closures work as oracles too.
+        let found = run_dag_search(graph, 0.99, None, &mut |node: CompressedDagNodeRef| {
+            node.segment == 3 || (node.segment == 2 && node.index >= 50)
+        });
+        assert_eq!(found, bad);
+    }
+
+    #[test]
+    fn run_dag_search_max_iterations() {
+        let mut graph = CompressedDag::default();
+        graph.add_node(CompressedDagSegment::new(100), vec![]);
+        graph.add_node(CompressedDagSegment::new(100), vec![0]);
+        let graph = Rc::new(graph);
+        // A completely random oracle never lets the likelihood reach the threshold.
+        let mut rng = TestRng::new(1);
+        let mut calls = 0;
+        let found = run_dag_search(graph.clone(), 0.99, Some(50), &mut |_| {
+            calls += 1;
+            rng.next_bool()
+        });
+        assert_eq!(calls, 50);
+        assert!(found.segment < 2 && found.index < 100);
+        // The cap doesn't matter if the search converges first.
+        let mut oracle = DescendantOracle {
+            graph: graph.clone(),
+            bad: CompressedDagNodeRef {
+                segment: 1,
+                index: 10,
+            },
+            calls: 0,
+        };
+        assert_eq!(
+            run_dag_search(graph, 0.99, Some(1000), &mut oracle),
+            oracle.bad
+        );
+        assert!(oracle.calls < 1000);
+    }
+
     #[test]
     fn graph_fork_join() {
         //      /-1-\
@@ -1220,4 +4803,512 @@ mod tests {
         assert_graph_index!(s, (2, 50), (2, 50), true, DEFAULT_FLAKINESS);
         assert_graph_index!(s, (2, 49), (2, 50), false, DEFAULT_FLAKINESS);
     }
+
+    #[test]
+    fn graph_likelihood_distribution() {
+        // Same graph and votes as graph_fork_join.
+        let mut graph = CompressedDag::default();
+        graph.add_node(CompressedDagSegment::new(100), vec![]);
+        graph.add_node(CompressedDagSegment::new(100), vec![0]);
+        graph.add_node(CompressedDagSegment::new(100), vec![0]);
+        graph.add_node(CompressedDagSegment::new(100), vec![1, 2]);
+        let mut s = CompressedDagSearcher::new(Rc::new(graph));
+        for &(segment, index, heads) in &[
+            (1, 99, false),
+            (2, 99, true),
+            (2, 49, false),
+            (2, 76, true),
+            (2, 62, true),
+            (2, 54, true),
+            (2, 50, true),
+            (2, 31, false),
+            (2, 49, false),
+            (2, 50, true),
+            (2, 49, false),
+        ] {
+            s.report(
+                CompressedDagNodeRef { segment, index },
+                heads,
+                DEFAULT_FLAKINESS,
+            );
+        }
+        let distribution = s.likelihood_distribution();
+        assert_eq!(distribution.len(), 400);
+        let sum = distribution.iter().map(|(_, w)| w).sum::<f64>();
+        assert!((sum - 1.0).abs() < 1e-9, "sum = {}", sum);
+        for (node, w) in &distribution {
+            assert_eq!(*w, s.likelihood(*node));
+        }
+        let (max_node, _) = distribution.iter().fold(distribution[0], |best, entry| {
+            if entry.1 > best.1 {
+                *entry
+            } else {
+                best
+            }
+        });
+        assert_eq!(max_node, s.best_node());
+    }
+
+    #[test]
+    fn graph_report_counts() {
+        let mut graph = CompressedDag::default();
+        graph.add_node(CompressedDagSegment::new(3), vec![]);
+        graph.add_node(CompressedDagSegment::new(4), vec![0]);
+        graph.add_node(CompressedDagSegment::new(5), vec![0]);
+        graph.add_node(CompressedDagSegment::new(2), vec![1, 2]);
+        let graph = Rc::new(graph);
+        let node = CompressedDagNodeRef {
+            segment: 1,
+            index: 2,
+        };
+        for &(heads, tails) in &[(5, 3), (3, 5), (4, 4), (8, 0)] {
+            let mut counted = CompressedDagSearcher::new(graph.clone());
+            let mut repeated = CompressedDagSearcher::new(graph.clone());
+            // Start from a non-uniform distribution.
+            let other = CompressedDagNodeRef {
+                segment: 2,
+                index: 1,
+            };
+            counted.report(other, true, DEFAULT_FLAKINESS);
+            repeated.report(other, true, DEFAULT_FLAKINESS);
+            counted.report_counts(node, heads, tails, 0.2);
+            for _ in 0..heads {
+                repeated.report(node, true, 0.2);
+            }
+            for _ in 0..tails {
+                repeated.report(node, false, 0.2);
+            }
+            for ((n1, l1), (n2, l2)) in counted
+                .likelihood_distribution()
+                .into_iter()
+                .zip(repeated.likelihood_distribution())
+            {
+                assert_eq!(n1, n2);
+                assert!(
+                    (l1 - l2).abs() < 1e-12,
+                    "({}, {}) at {:?}: {} != {}",
+                    heads,
+                    tails,
+                    n1,
+                    l1,
+                    l2
+                );
+            }
+            assert_eq!(counted.best_node(), repeated.best_node());
+        }
+    }
+
+    #[test]
+    fn graph_report_counts_large() {
+        let mut graph = CompressedDag::default();
+        graph.add_node(CompressedDagSegment::new(30), vec![]);
+        graph.add_node(CompressedDagSegment::new(30), vec![0]);
+        graph.add_node(CompressedDagSegment::new(30), vec![0]);
+        let graph = Rc::new(graph);
+        let node = CompressedDagNodeRef {
+            segment: 1,
+            index: 10,
+        };
+        for &(heads, tails) in &[(400, 0), (0, 400), (1000, 3)] {
+            let mut counted = CompressedDagSearcher::new(graph.clone());
+            let mut repeated = CompressedDagSearcher::new(graph.clone());
+            counted.report_counts(node, heads, tails, 0.001);
+            for _ in 0..heads {
+                repeated.report(node, true, 0.001);
+            }
+            for _ in 0..tails {
+                repeated.report(node, false, 0.001);
+            }
+            for ((n, l1), (_, l2)) in counted
+                .likelihood_distribution()
+                .into_iter()
+                .zip(repeated.likelihood_distribution())
+            {
+                assert!(l1.is_finite(), "({}, {}) at {:?}: {}", heads, tails, n, l1);
+                assert!(
+                    (l1 - l2).abs() < 1e-12,
+                    "({}, {}) at {:?}: {} != {}",
+                    heads,
+                    tails,
+                    n,
+                    l1,
+                    l2
+                );
+            }
+            assert_eq!(counted.best_node(), repeated.best_node());
+        }
+    }
+
+    #[test]
+    fn graph_likelihood_linear() {
+        let mut graph = CompressedDag::default();
+        graph.add_node(CompressedDagSegment::new(10), vec![]);
+        graph.add_node(CompressedDagSegment::new(5), vec![0]);
+        graph.add_node(CompressedDagSegment::new(7), vec![0]);
+        graph.add_node(CompressedDagSegment::new(3), vec![1, 2]);
+        let graph = Rc::new(graph);
+        let mut s = CompressedDagSearcher::new(graph.clone());
+        s.report(
+            CompressedDagNodeRef {
+                segment: 2,
+                index: 3,
+            },
+            true,
+            DEFAULT_FLAKINESS,
+        );
+        s.report(
+            CompressedDagNodeRef {
+                segment: 1,
+                index: 2,
+            },
+            false,
+            DEFAULT_FLAKINESS,
+        );
+        s.report(
+            CompressedDagNodeRef {
+                segment: 0,
+                index: 8,
+            },
+            false,
+            DEFAULT_FLAKINESS,
+        );
+        let mut index = 0;
+        for (segment, node) in graph.nodes().iter().enumerate() {
+            for i in 0..node.value().len() {
+                assert_eq!(
+                    s.likelihood_linear(index),
+                    s.likelihood(CompressedDagNodeRef { segment, index: i }),
+                    "index {}",
+                    index
+                );
+                index += 1;
+            }
+        }
+        assert_eq!(index, 25);
+    }
+
+    #[test]
+    #[should_panic(expected = "linear index 25 out of range for CompressedDag with 25 nodes")]
+    fn graph_likelihood_linear_out_of_range() {
+        let mut graph = CompressedDag::default();
+        graph.add_node(CompressedDagSegment::new(10), vec![]);
+        graph.add_node(CompressedDagSegment::new(15), vec![0]);
+        CompressedDagSearcher::new(Rc::new(graph)).likelihood_linear(25);
+    }
+
+    fn assert_ancestor_mass_monotonic(s: &CompressedDagSearcher, graph: &CompressedDag) {
+        let nodes = graph
+            .nodes()
+            .iter()
+            .enumerate()
+            .flat_map(|(segment, node)| {
+                (0..node.value().len()).map(move |index| CompressedDagNodeRef { segment, index })
+            })
+            .collect::<Vec<_>>();
+        for a in &nodes {
+            let mass = s.ancestor_mass(*a);
+            assert!((0.0..=1.0 + 1e-9).contains(&mass), "{:?}: {}", a, mass);
+            for b in &nodes {
+                let is_ancestor = if a.segment == b.segment {
+                    a.index <= b.index
+                } else {
+                    graph.node(b.segment).ancestors().contains(&a.segment)
+                };
+                if is_ancestor {
+                    assert!(
+                        mass <= s.ancestor_mass(*b) + 1e-12,
+                        "{:?} has more mass than its descendant {:?}",
+                        a,
+                        b
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn graph_ancestor_mass_sequential() {
+        let mut graph = CompressedDag::default();
+        graph.add_node(CompressedDagSegment::new(10), vec![]);
+        graph.add_node(CompressedDagSegment::new(10), vec![0]);
+        graph.add_node(CompressedDagSegment::new(10), vec![1]);
+        let graph = Rc::new(graph);
+        let mut s = CompressedDagSearcher::new(graph.clone());
+        let node = |segment, index| CompressedDagNodeRef { segment, index };
+        s.report(node(1, 5), true, DEFAULT_FLAKINESS);
+        s.report(node(0, 3), false, DEFAULT_FLAKINESS);
+        s.report(node(2, 1), true, DEFAULT_FLAKINESS);
+        assert_ancestor_mass_monotonic(&s, &graph);
+        // A chain is the same as a linear range, so this is the cumulative distribution.
+        let mut sum = 0.0;
+        for segment in 0..3 {
+            for index in 0..10 {
+                sum += s.likelihood(node(segment, index));
+                assert!((s.ancestor_mass(node(segment, index)) - sum).abs() < 1e-12);
+            }
+        }
+        assert!((s.ancestor_mass(node(2, 9)) - 1.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn graph_ancestor_mass_fork_join() {
+        //      /-1-\
+        // *-0-*     *-3-*
+        //      \-2-/
+        let mut graph = CompressedDag::default();
+        graph.add_node(CompressedDagSegment::new(10), vec![]);
+        graph.add_node(CompressedDagSegment::new(10), vec![0]);
+        graph.add_node(CompressedDagSegment::new(10), vec![0]);
+        graph.add_node(CompressedDagSegment::new(10), vec![1, 2]);
+        let graph = Rc::new(graph);
+        let mut s = CompressedDagSearcher::new(graph.clone());
+        let node = |segment, index| CompressedDagNodeRef { segment, index };
+        s.report(node(1, 9), false, DEFAULT_FLAKINESS);
+        s.report(node(2, 9), true, DEFAULT_FLAKINESS);
+        s.report(node(2, 4), false, DEFAULT_FLAKINESS);
+        assert_ancestor_mass_monotonic(&s, &graph);
+        let segment_mass = |segment| {
+            (0..10)
+                .map(|index| s.likelihood(node(segment, index)))
+                .sum::<f64>()
+        };
+        // Parallel branches don't count toward each other.
+        assert!((s.ancestor_mass(node(1, 9)) - segment_mass(0) - segment_mass(1)).abs() < 1e-12);
+        assert!((s.ancestor_mass(node(2, 9)) - segment_mass(0) - segment_mass(2)).abs() < 1e-12);
+        assert!((s.ancestor_mass(node(3, 9)) - 1.0).abs() < 1e-9);
+        assert!(s.ancestor_mass(node(2, 9)) > s.ancestor_mass(node(1, 9)));
+    }
+
+    #[test]
+    fn graph_plausible_fraction() {
+        let mut graph = CompressedDag::default();
+        graph.add_node(CompressedDagSegment::new(100), vec![]);
+        graph.add_node(CompressedDagSegment::new(100), vec![0]);
+        graph.add_node(CompressedDagSegment::new(100), vec![0]);
+        graph.add_node(CompressedDagSegment::new(100), vec![1, 2]);
+        let mut s = CompressedDagSearcher::new(Rc::new(graph));
+        let threshold = 1e-4;
+        assert_eq!(s.plausible_fraction(threshold), 1.0);
+        assert_eq!(s.plausible_fraction(1.0), 0.0);
+        let mut fractions = vec![];
+        for _ in 0..20 {
+            // The bad node is (2, 50).
+            let node = s.next_node();
+            let heads = node.segment == 3 || (node.segment == 2 && node.index >= 50);
+            s.report(node, heads, DEFAULT_FLAKINESS);
+            fractions.push(s.plausible_fraction(threshold));
+        }
+        // A single vote can't rule out much, since evidence against a node accumulates slowly, but
+        // by the end only the bad node is left.
+        assert!(fractions[4] <= 0.5, "fractions = {:?}", fractions);
+        assert!(fractions[9] < 0.25, "fractions = {:?}", fractions);
+        assert_eq!(fractions[19], 1.0 / 400.0);
+        assert_eq!(
+            s.best_node(),
+            CompressedDagNodeRef {
+                segment: 2,
+                index: 50
+            }
+        );
+    }
+
+    #[test]
+    fn modes() {
+        // Two peaks at 20 and 70, separated by a valley of low likelihood.
+        let s = Searcher::with_prior(100, |i| match i {
+            15..=25 => 10.0 - (i as f64 - 20.0).abs(),
+            65..=75 => 8.0 - (i as f64 - 70.0).abs(),
+            _ => 0.01,
+        });
+        let modes = s.modes(0.001);
+        assert_eq!(
+            modes.iter().map(|(index, _)| *index).collect::<Vec<_>>(),
+            vec![20, 70]
+        );
+        for (index, likelihood) in &modes {
+            assert_eq!(*likelihood, s.likelihood(*index));
+        }
+        // Only the higher peak clears a threshold between the two.
+        let threshold = (s.likelihood(20) + s.likelihood(70)) / 2.0;
+        assert_eq!(s.modes(threshold), vec![(20, s.likelihood(20))]);
+        assert!(s.modes(1.0).is_empty());
+        // With a threshold below the valley, everything is one region.
+        assert_eq!(s.modes(0.0).len(), 1);
+    }
+
+    #[test]
+    fn modes_converged() {
+        let mut s = Searcher::new(100);
+        for _ in 0..20 {
+            let index = s.next_index().unwrap();
+            s.report(index, index >= 37, DEFAULT_FLAKINESS);
+        }
+        let modes = s.modes(0.01);
+        assert_eq!(modes.len(), 1, "modes = {:?}", modes);
+        assert_eq!(modes[0].0, 37);
+    }
+
+    #[test]
+    fn checkpoint_restore() {
+        let mut s = Searcher::new(100);
+        s.report(60, true, DEFAULT_FLAKINESS);
+        s.report(20, false, DEFAULT_FLAKINESS);
+        s.add_skip(40);
+        let next_index = s.next_index();
+        let likelihoods = s.likelihoods().collect::<Vec<_>>();
+        let checkpoint = s.checkpoint();
+
+        // Simulate both outcomes of the next vote.
+        for heads in &[false, true] {
+            s.report(next_index.unwrap(), *heads, DEFAULT_FLAKINESS);
+            s.add_skip(41);
+            s.add_constraint(Constraint::GoodBelow(30)).unwrap();
+            assert_ne!(s.likelihoods().collect::<Vec<_>>(), likelihoods);
+            s.restore(checkpoint.clone());
+            assert_eq!(s.next_index(), next_index);
+            assert_eq!(s.likelihoods().collect::<Vec<_>>(), likelihoods);
+            assert!(!s.skips.contains(&41));
+            assert_eq!(s.good_below, None);
+        }
+    }
+
+    #[test]
+    #[should_panic(expected = "checkpoint of length 10 restored into Searcher of length 20")]
+    fn restore_wrong_len() {
+        let checkpoint = Searcher::new(10).checkpoint();
+        Searcher::new(20).restore(checkpoint);
+    }
+
+    #[test]
+    fn best_probe_matches_next_index() {
+        let mut s = Searcher::new(99);
+        assert_eq!(s.best_probe(DEFAULT_FLAKINESS), 49);
+        assert_eq!(s.next_index(), Some(49));
+        let mut rng = TestRng::new(1);
+        for _ in 0..5 {
+            let index = rng.below(99);
+            s.report(index, index >= 30, DEFAULT_FLAKINESS);
+            assert_eq!(Some(s.best_probe(DEFAULT_FLAKINESS)), s.next_index());
+        }
+    }
+
+    #[test]
+    fn expected_info_gain() {
+        let s = Searcher::new(99);
+        // A single test can't yield more than one bit, which is what a perfectly reliable test of
+        // the median would yield. Stiffness is bounded, so a reliable test comes close.
+        let gain = s.expected_info_gain(49, 1e-9);
+        assert!(gain > 0.9 && gain <= 1.0, "gain = {}", gain);
+        assert!(s.expected_info_gain(10, 1e-9) < gain);
+        // Flaky results are worth less.
+        assert!(s.expected_info_gain(49, 0.5) < gain);
+        assert!(s.expected_info_gain(49, 1.0) < s.expected_info_gain(49, 0.5));
+        // Out of range flakiness is clamped.
+        assert_eq!(s.expected_info_gain(49, 0.0), gain);
+        assert_eq!(s.expected_info_gain(49, 2.0), s.expected_info_gain(49, 1.0));
+    }
+
+    #[test]
+    #[should_panic(expected = "no testable indices")]
+    fn best_probe_all_skipped() {
+        let mut s = Searcher::new(2);
+        s.add_skip(0);
+        s.add_skip(1);
+        s.best_probe(DEFAULT_FLAKINESS);
+    }
+
+    #[test]
+    fn sparkline() {
+        let mut s = Searcher::new(99);
+        assert_eq!(s.sparkline(10), "█".repeat(10));
+        assert_eq!(s.sparkline(0), "");
+        // Wider than the number of indices, so some columns are empty.
+        assert_eq!(s.sparkline(201).chars().count(), 201);
+        for _ in 0..20 {
+            let index = s.next_index().unwrap();
+            s.report(index, index >= 37, DEFAULT_FLAKINESS);
+        }
+        for width in &[1, 7, 10, 40, 100] {
+            let line = s.sparkline(*width);
+            assert_eq!(line.chars().count(), *width);
+            // The last column whose first index is at most the best index.
+            let peak = ((s.best_index() + 1) * width - 1) / 100;
+            for (column, c) in line.chars().enumerate() {
+                if column == peak {
+                    assert_eq!(c, '█', "sparkline = {:?}", line);
+                } else {
+                    assert_eq!(c, ' ', "sparkline = {:?}", line);
+                }
+            }
+        }
+    }
+
+    /// Returns the shortest interval with at least the given likelihood by trying every interval.
+    fn brute_force_hdi(s: &Searcher, mass: f64) -> (usize, usize) {
+        let likelihoods = s.likelihoods().collect::<Vec<_>>();
+        let mut best: Option<(usize, usize, f64)> = None;
+        for lo in 0..likelihoods.len() {
+            let mut sum = 0.0;
+            for (hi, likelihood) in likelihoods.iter().enumerate().skip(lo) {
+                sum += likelihood;
+                if sum >= mass - 1e-12 {
+                    match best {
+                        Some((best_lo, best_hi, best_sum))
+                            if best_hi - best_lo < hi - lo
+                                || (best_hi - best_lo == hi - lo && best_sum >= sum) => {}
+                        _ => best = Some((lo, hi, sum)),
+                    }
+                    break;
+                }
+            }
+        }
+        let (lo, hi, _) = best.unwrap();
+        (lo, hi)
+    }
+
+    #[test]
+    fn highest_density_interval() {
+        let mut rng = TestRng::new(1);
+        for len in 1..20 {
+            let mut s = Searcher::new(len);
+            for _ in 0..rng.below(6) {
+                let index = rng.below(len);
+                s.report(index, rng.below(2) == 0, 0.3);
+            }
+            for mass in &[0.0, 0.1, 0.5, 0.9, 0.99, 1.0] {
+                let expected = brute_force_hdi(&s, *mass);
+                let actual = s.unclamped_highest_density_interval(*mass);
+                // Intervals of equal length and likelihood can differ by rounding, so only compare
+                // lengths and check the likelihood.
+                assert_eq!(
+                    actual.1 - actual.0,
+                    expected.1 - expected.0,
+                    "len = {}, mass = {}, expected {:?}, got {:?}",
+                    len,
+                    mass,
+                    expected,
+                    actual
+                );
+                assert!(s.probability_mass(actual.0..=actual.1) >= mass - 1e-9);
+            }
+        }
+    }
+
+    #[test]
+    fn highest_density_interval_converged() {
+        let mut s = Searcher::new(1000);
+        for _ in 0..30 {
+            let index = s.next_index().unwrap();
+            s.report(index, index >= 637, DEFAULT_FLAKINESS);
+        }
+        assert_eq!(s.highest_density_interval(0.9), (637, 637));
+        // Index len is included in the search but not in the result.
+        assert_eq!(Searcher::new(10).highest_density_interval(1.0), (0, 9));
+    }
+
+    #[test]
+    #[should_panic(expected = "invalid mass 1.5")]
+    fn highest_density_interval_invalid_mass() {
+        Searcher::new(10).highest_density_interval(1.5);
+    }
 }
@@ -12,8 +12,40 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+//! A run-length encoded map over a fixed range of indices.
+//!
+//! ```
+//! use robust_binary_search::range_map::RangeMap;
+//!
+//! let mut map = RangeMap::new(10, 1.0);
+//! // Double every value from index 4 onwards.
+//! for entry in map.split(4).1 {
+//!     *entry.value_mut() *= 2.0;
+//! }
+//! let entries = map
+//!     .ranges()
+//!     .map(|e| (e.offset(), e.len(), *e.value()))
+//!     .collect::<Vec<_>>();
+//! assert_eq!(entries, vec![(0, 4, 1.0), (4, 6, 2.0)]);
+//! assert_eq!(*map.range_for_index(7).value(), 2.0);
+//! ```
+
+#[cfg(feature = "prefix_sums")]
+use crate::float::{count, float, to_index};
+use alloc::vec;
+use alloc::vec::Vec;
+#[cfg(feature = "prefix_sums")]
+use core::cmp;
+use core::ops::Bound;
+use core::ops::RangeBounds;
+#[cfg(feature = "prefix_sums")]
+use num_traits::Float;
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
 /// A single entry in a RangeMap, which corresponds to a range of individual values.
 #[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct RangeMapEntry<T> {
     /// Beginning index of the range within the conceptual vector of individual values.
     offset: usize,
@@ -24,7 +56,6 @@ pub struct RangeMapEntry<T> {
 }
 
 impl<T> RangeMapEntry<T> {
-    #[allow(dead_code)]
     /// Returns the index of the first individual value in the range.
     pub fn offset(&self) -> usize {
         self.offset
@@ -35,6 +66,12 @@ impl<T> RangeMapEntry<T> {
         self.len
     }
 
+    /// Returns true if the range is empty, which only happens for the single entry of an empty
+    /// RangeMap.
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
     /// Returns offset() + len().
     pub fn end(&self) -> usize {
         self.offset + self.len
@@ -84,15 +121,31 @@ impl<T> RangeMapEntry<T> {
 /// ['a', 'b', 'b', 'c', 'c', 'c', 'd', 'd', 'd', 'd']
 /// ```
 ///
+/// The entries are always kept in order, are contiguous, and together cover the range `[0, len)`.
+/// Every entry has a non-zero length, except that an empty RangeMap has a single empty entry.
 /// Note that neighboring entries may contain the same value.
+///
+/// ```
+/// use robust_binary_search::range_map::RangeMap;
+///
+/// let map = RangeMap::from_fn(10, |i| if i < 3 { 'a' } else { 'b' });
+/// assert_eq!(map.len(), 10);
+/// assert_eq!(map.ranges().count(), 2);
+/// let values = map
+///     .ranges()
+///     .flat_map(|e| core::iter::repeat(*e.value()).take(e.len()))
+///     .collect::<String>();
+/// assert_eq!(values, "aaabbbbbbb");
+/// ```
 #[derive(Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct RangeMap<T> {
     /// Entries within the map. Invariants:
     ///
     /// 1. Must be non-empty.
     /// 2. values[0].offset() == 0
     /// 3. values[i - 1].end() == values[i].offset()
-    /// 4. The length of each entry must be non-zero.
+    /// 4. The length of each entry must be non-zero, unless the map is empty.
     values: Vec<RangeMapEntry<T>>,
 }
 
@@ -109,11 +162,164 @@ impl<T: Clone> RangeMap<T> {
         }
     }
 
+    /// Creates a new RangeMap with the given size, where the value at each index is `f(index)`.
+    /// Neighboring indices with equal values share an entry.
+    pub fn from_fn<F: Fn(usize) -> T>(size: usize, f: F) -> Self
+    where
+        T: PartialEq,
+    {
+        let mut values: Vec<RangeMapEntry<T>> = Vec::new();
+        for index in 0..size {
+            let value = f(index);
+            match values.last_mut() {
+                Some(last) if last.value == value => last.len += 1,
+                _ => values.push(RangeMapEntry {
+                    offset: index,
+                    len: 1,
+                    value,
+                }),
+            }
+        }
+        assert!(!values.is_empty(), "RangeMap must be non-empty");
+        RangeMap { values }
+    }
+
+    /// Sets every individual value to `value`, leaving a single entry spanning the entire range.
+    /// The length is unchanged.
+    pub fn fill(&mut self, value: T) {
+        let len = self.len();
+        self.values.clear();
+        self.values.push(RangeMapEntry {
+            offset: 0,
+            len,
+            value,
+        });
+    }
+
+    /// Extends the range by `additional` indices, which take the value of the last index. The
+    /// last entry is lengthened, so no new entries are created.
+    pub fn grow(&mut self, additional: usize) {
+        self.values.last_mut().unwrap().len += additional;
+    }
+
+    /// Sets every individual value in `range` to `value`. The covered entries are replaced by a
+    /// single entry, and entries which are only partially covered are split. Does nothing if the
+    /// range is empty.
+    ///
+    /// ```
+    /// use robust_binary_search::range_map::RangeMap;
+    ///
+    /// let mut map = RangeMap::new(10, 'a');
+    /// map.set_range(2..5, 'b');
+    /// map.set_range(8.., 'c');
+    /// let entries = map
+    ///     .ranges()
+    ///     .map(|e| (e.offset(), e.len(), *e.value()))
+    ///     .collect::<Vec<_>>();
+    /// assert_eq!(entries, vec![(0, 2, 'a'), (2, 3, 'b'), (5, 3, 'a'), (8, 2, 'c')]);
+    /// ```
+    ///
+    /// # Panics
+    ///
+    /// Panics if the range ends after `len()` or starts after it ends.
+    pub fn set_range<R: RangeBounds<usize>>(&mut self, range: R, value: T) {
+        let start = match range.start_bound() {
+            Bound::Included(start) => *start,
+            Bound::Excluded(start) => start + 1,
+            Bound::Unbounded => 0,
+        };
+        let end = match range.end_bound() {
+            Bound::Included(end) => end + 1,
+            Bound::Excluded(end) => *end,
+            Bound::Unbounded => self.len(),
+        };
+        assert!(
+            start <= end && end <= self.len(),
+            "range {}..{} out of bounds for RangeMap of length {}",
+            start,
+            end,
+            self.len()
+        );
+        if start == end {
+            return;
+        }
+        let first = self._split(start);
+        let last = self._split(end);
+        self.values.splice(
+            first..last,
+            core::iter::once(RangeMapEntry {
+                offset: start,
+                len: end - start,
+                value,
+            }),
+        );
+    }
+
+    /// Returns a RangeMap with the same entries, with `f` applied to each entry's value. `f` is
+    /// called once per entry rather than once per individual value.
+    ///
+    /// ```
+    /// use robust_binary_search::range_map::RangeMap;
+    ///
+    /// let weights = RangeMap::from_fn(10, |i| if i < 5 { 0.05 } else { 0.15 });
+    /// let buckets = weights.map_values(|w| (w * 100.0) as u8);
+    /// assert_eq!(*buckets.range_for_index(7).value(), 15);
+    /// ```
+    pub fn map_values<U, F: FnMut(&T) -> U>(&self, mut f: F) -> RangeMap<U> {
+        RangeMap {
+            values: self
+                .values
+                .iter()
+                .map(|w| RangeMapEntry {
+                    offset: w.offset,
+                    len: w.len,
+                    value: f(&w.value),
+                })
+                .collect(),
+        }
+    }
+
+    /// Merges neighboring entries with equal values. The individual values are unchanged.
+    pub fn compact(&mut self)
+    where
+        T: PartialEq,
+    {
+        self.compact_by(|a, b| a == b);
+    }
+
+    /// Merges neighboring entries for which `same` returns true, which is useful for values which
+    /// are only approximately equal, such as floats. A merged entry keeps the value of its first
+    /// entry, and later entries are compared against that value, so errors don't accumulate across
+    /// a long run of merges.
+    ///
+    /// ```
+    /// use robust_binary_search::range_map::RangeMap;
+    ///
+    /// let mut map = RangeMap::from_fn(4, |i| 1.0 + i as f64 * 1e-15);
+    /// map.compact_by(|a, b| (a - b).abs() <= 1e-12);
+    /// assert_eq!(map.ranges().count(), 1);
+    /// ```
+    pub fn compact_by<F: FnMut(&T, &T) -> bool>(&mut self, mut same: F) {
+        self.values.dedup_by(|next, prev| {
+            if same(&prev.value, &next.value) {
+                prev.len += next.len;
+                true
+            } else {
+                false
+            }
+        });
+    }
+
     /// Returns the length of the entire range.
     pub fn len(&self) -> usize {
         self.values[self.values.len() - 1].end()
     }
 
+    /// Returns true if the range is empty.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
     /// Takes an individual element index and returns the RangeMapEntry index.
     fn range_index(&self, index: usize) -> usize {
         for (i, w) in self.values.iter().enumerate() {
@@ -124,17 +330,30 @@ impl<T: Clone> RangeMap<T> {
         self.values.len()
     }
 
-    /// Returns an iterator over entries.
+    /// Returns an iterator over entries, in order of increasing offset.
     pub fn ranges(&self) -> impl DoubleEndedIterator<Item = &RangeMapEntry<T>> {
         self.values.iter()
     }
 
-    /// Returns an iterator over mutable entries.
+    /// Returns an iterator over mutable entries, in order of increasing offset. Only the values can
+    /// be modified.
     pub fn ranges_mut(&mut self) -> impl DoubleEndedIterator<Item = &mut RangeMapEntry<T>> {
         self.values.iter_mut()
     }
 
+    /// Returns an iterator over the individual values, i.e. the value for index 0, then index 1,
+    /// and so on up to `len()`. Each entry's value is repeated once for each index it covers.
+    pub fn iter_expanded(&self) -> impl DoubleEndedIterator<Item = &T> {
+        self.values
+            .iter()
+            .flat_map(|w| core::iter::repeat_n(&w.value, w.len))
+    }
+
     /// Returns the entry containing the given index.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `index >= len()`.
     pub fn range_for_index(&self, index: usize) -> &RangeMapEntry<T> {
         let range_index = self.range_index(index);
         &self.values[range_index]
@@ -166,7 +385,18 @@ impl<T: Clone> RangeMap<T> {
     }
 
     /// Ensures that `index-1` and `index` are in different RangeMapEntrys.
-    /// Returns iterators for the left and right side of the split.
+    /// Returns iterators for the left and right side of the split, i.e. the entries covering
+    /// `[0, index)` and `[index, len)`. The individual values are unchanged.
+    ///
+    /// ```
+    /// use robust_binary_search::range_map::RangeMap;
+    ///
+    /// let mut map = RangeMap::new(10, 0);
+    /// let (left, right) = map.split(3);
+    /// assert_eq!(left.map(|e| e.len()).sum::<usize>(), 3);
+    /// assert_eq!(right.map(|e| e.len()).sum::<usize>(), 7);
+    /// assert_eq!(map.ranges().count(), 2);
+    /// ```
     pub fn split(
         &mut self,
         index: usize,
@@ -180,6 +410,110 @@ impl<T: Clone> RangeMap<T> {
     }
 }
 
+/// A snapshot of a `RangeMap` of non-negative floats (`f64` by default), augmented with the cumulative sum of
+/// the values before each entry, so that percentile queries take `O(log n)` time in the number of
+/// entries rather than `O(n)`. Building it takes `O(n)` time, so it pays off when several queries
+/// are made against the same weights.
+///
+/// ```
+/// use robust_binary_search::range_map::{PrefixSumRangeMap, RangeMap};
+///
+/// let map = RangeMap::from_fn(4, |i| if i < 2 { 0.1 } else { 0.4 });
+/// let sums = PrefixSumRangeMap::new(&map);
+/// assert_eq!(sums.index_at_cumulative(0.5).0, 2);
+/// ```
+#[cfg(feature = "prefix_sums")]
+#[derive(Clone, Debug)]
+pub struct PrefixSumRangeMap<F: Float = f64> {
+    entries: Vec<RangeMapEntry<F>>,
+    /// `starts[i]` is the sum of the individual values before `entries[i]`, and the last element
+    /// is the sum of all values.
+    starts: Vec<F>,
+}
+
+#[cfg(feature = "prefix_sums")]
+impl<F: Float> PrefixSumRangeMap<F> {
+    /// Creates a PrefixSumRangeMap from the current contents of a RangeMap.
+    pub fn new(map: &RangeMap<F>) -> Self {
+        let mut starts = Vec::with_capacity(map.values.len() + 1);
+        let mut sum = F::zero();
+        starts.push(sum);
+        for w in &map.values {
+            sum = sum + count::<F>(w.len()) * *w.value();
+            starts.push(sum);
+        }
+        PrefixSumRangeMap {
+            entries: map.values.clone(),
+            starts,
+        }
+    }
+
+    /// Returns the sum of all individual values.
+    pub fn total(&self) -> F {
+        self.starts[self.starts.len() - 1]
+    }
+
+    /// Returns the index of the first entry whose cumulative sum (including the entry itself) is
+    /// greater than or equal to `target`, or the number of entries if there is none.
+    fn entry_at_cumulative(&self, target: F) -> usize {
+        self.starts[1..].partition_point(|sum| *sum < target)
+    }
+
+    /// Finds the smallest index such that the sum of values at indices [0, i] (inclusive) is
+    /// greater than or equal to `target`. Returns the index and the sum. If no sum is greater than
+    /// or equal to `target`, returns the last index and the sum over all values.
+    pub fn index_at_cumulative(&self, target: F) -> (usize, F) {
+        let i = self.entry_at_cumulative(target);
+        if i == self.entries.len() {
+            return (self.entries[i - 1].end() - 1, self.total());
+        }
+        let w = &self.entries[i];
+        let sum = self.starts[i];
+        let ix = w.offset() + to_index((target - sum) / *w.value() - float(1e-9));
+        (ix, sum + count::<F>(ix - w.offset() + 1) * *w.value())
+    }
+
+    /// Returns the index within entry `i` whose cumulative sum is nearest `target`, and that sum.
+    fn nearest_in_entry(&self, i: usize, target: F) -> (usize, F) {
+        let w = &self.entries[i];
+        let sum = self.starts[i];
+        let ix = w.offset()
+            + cmp::min(
+                w.len() - 1,
+                to_index(((target - sum) / *w.value() - float(0.5)).max(F::zero())),
+            );
+        (ix, sum + count::<F>(ix - w.offset() + 1) * *w.value())
+    }
+
+    /// Finds the index such that the sum of values at indices [0, i] (inclusive) is as close as
+    /// possible to `target`, preferring the lowest such index. Returns the index and the sum.
+    pub fn nearest_index_at_cumulative(&self, target: F) -> (usize, F) {
+        // The cumulative sums are non-decreasing, so the nearest one is next to the entry where
+        // they cross the target, or in a run of equal sums just before that.
+        let crossing = self.entry_at_cumulative(target);
+        let first = crossing.saturating_sub(1);
+        let last = cmp::min(crossing + 1, self.entries.len() - 1);
+        let mut best_entry = first;
+        let mut best = self.nearest_in_entry(first, target);
+        for i in first + 1..=last {
+            let candidate = self.nearest_in_entry(i, target);
+            if (candidate.1 - target).abs() < (best.1 - target).abs() {
+                best_entry = i;
+                best = candidate;
+            }
+        }
+        while best_entry > 0 {
+            let candidate = self.nearest_in_entry(best_entry - 1, target);
+            if (candidate.1 - target).abs() > (best.1 - target).abs() {
+                break;
+            }
+            best_entry -= 1;
+            best = candidate;
+        }
+        best
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -205,6 +539,18 @@ mod tests {
         );
     }
 
+    #[test]
+    fn grow() {
+        let mut m = RangeMap::from_fn(5, |i| if i < 2 { 1 } else { 2 });
+        m.grow(3);
+        assert_eq!(m.len(), 8);
+        assert_eq!(m.ranges().count(), 2);
+        assert_eq!(
+            m.iter_expanded().cloned().collect::<Vec<_>>(),
+            vec![1, 1, 2, 2, 2, 2, 2, 2]
+        );
+    }
+
     #[test]
     fn split() {
         let mut m = RangeMap::new(10, 0.0);
@@ -266,4 +612,198 @@ mod tests {
             }
         );
     }
+
+    #[test]
+    fn fill() {
+        let mut m = RangeMap::new(10, 0.0);
+        let _ = m.split(5);
+        m.fill(1.0);
+        assert_eq!(m.len(), 10);
+        assert_eq!(
+            m.ranges().collect::<Vec<_>>(),
+            vec![&RangeMapEntry {
+                offset: 0,
+                len: 10,
+                value: 1.0
+            }]
+        );
+    }
+
+    #[test]
+    fn from_fn() {
+        let m = RangeMap::from_fn(10, |i| if i < 3 { 'a' } else { 'b' });
+        assert_eq!(
+            m.ranges().collect::<Vec<_>>(),
+            vec![
+                &RangeMapEntry {
+                    offset: 0,
+                    len: 3,
+                    value: 'a'
+                },
+                &RangeMapEntry {
+                    offset: 3,
+                    len: 7,
+                    value: 'b'
+                }
+            ]
+        );
+    }
+
+    #[test]
+    fn compact() {
+        let mut m = RangeMap::new(20, 1.0);
+        for i in 1..20 {
+            let _ = m.split(i);
+        }
+        for w in m.split(15).1 {
+            *w.value_mut() = 2.0;
+        }
+        assert_eq!(m.ranges().count(), 20);
+        let before = (0..20)
+            .map(|i| *m.range_for_index(i).value())
+            .collect::<Vec<_>>();
+        m.compact();
+        assert_eq!(
+            m.ranges().cloned().collect::<Vec<_>>(),
+            vec![
+                RangeMapEntry {
+                    offset: 0,
+                    len: 15,
+                    value: 1.0
+                },
+                RangeMapEntry {
+                    offset: 15,
+                    len: 5,
+                    value: 2.0
+                }
+            ]
+        );
+        let after = (0..20)
+            .map(|i| *m.range_for_index(i).value())
+            .collect::<Vec<_>>();
+        assert_eq!(before, after);
+    }
+
+    #[test]
+    fn compact_by_epsilon() {
+        let mut m = RangeMap::new(10, 1.0f64);
+        for i in 1..10 {
+            let (_, right) = m.split(i);
+            for w in right {
+                *w.value_mut() += 1e-14;
+            }
+        }
+        assert_eq!(m.ranges().count(), 10);
+        m.compact();
+        assert_eq!(m.ranges().count(), 10);
+        m.compact_by(|a, b| (a - b).abs() < 1e-12);
+        assert_eq!(
+            m.ranges().cloned().collect::<Vec<_>>(),
+            vec![RangeMapEntry {
+                offset: 0,
+                len: 10,
+                value: 1.0
+            }]
+        );
+    }
+
+    #[test]
+    fn map_values() {
+        let mut m = RangeMap::new(10, 0.5);
+        for w in m.split(3).1 {
+            *w.value_mut() = 0.25;
+        }
+        let _ = m.split(7);
+        let mut calls = 0;
+        let mapped = m.map_values(|w| {
+            calls += 1;
+            format!("{:.2}", w)
+        });
+        assert_eq!(calls, 3);
+        assert_eq!(
+            mapped.ranges().cloned().collect::<Vec<_>>(),
+            vec![
+                RangeMapEntry {
+                    offset: 0,
+                    len: 3,
+                    value: "0.50".to_string()
+                },
+                RangeMapEntry {
+                    offset: 3,
+                    len: 4,
+                    value: "0.25".to_string()
+                },
+                RangeMapEntry {
+                    offset: 7,
+                    len: 3,
+                    value: "0.25".to_string()
+                }
+            ]
+        );
+    }
+
+    fn entries<T: Clone>(m: &RangeMap<T>) -> Vec<(usize, usize, T)> {
+        m.ranges()
+            .map(|e| (e.offset(), e.len(), e.value().clone()))
+            .collect()
+    }
+
+    #[test]
+    fn set_range_inside_one_entry() {
+        let mut m = RangeMap::new(10, 0);
+        m.set_range(3..6, 1);
+        assert_eq!(entries(&m), vec![(0, 3, 0), (3, 3, 1), (6, 4, 0)]);
+        for i in 0..10 {
+            let expected = if (3..6).contains(&i) { 1 } else { 0 };
+            assert_eq!(*m.range_for_index(i).value(), expected, "index {}", i);
+        }
+    }
+
+    #[test]
+    fn set_range_across_entries() {
+        let mut m = RangeMap::new(10, 0);
+        m.set_range(2..4, 1);
+        m.set_range(6..8, 2);
+        m.set_range(3..=6, 3);
+        assert_eq!(
+            entries(&m),
+            vec![(0, 2, 0), (2, 1, 1), (3, 4, 3), (7, 1, 2), (8, 2, 0)]
+        );
+    }
+
+    #[test]
+    fn set_range_edges() {
+        let mut m = RangeMap::new(10, 0);
+        m.set_range(..3, 1);
+        assert_eq!(entries(&m), vec![(0, 3, 1), (3, 7, 0)]);
+        m.set_range(7.., 2);
+        assert_eq!(entries(&m), vec![(0, 3, 1), (3, 4, 0), (7, 3, 2)]);
+        m.set_range(5..5, 9);
+        m.set_range(10.., 9);
+        assert_eq!(entries(&m), vec![(0, 3, 1), (3, 4, 0), (7, 3, 2)]);
+        m.set_range(.., 4);
+        assert_eq!(entries(&m), vec![(0, 10, 4)]);
+    }
+
+    #[test]
+    #[should_panic(expected = "range 5..11 out of bounds for RangeMap of length 10")]
+    fn set_range_out_of_bounds() {
+        let mut m = RangeMap::new(10, 0);
+        m.set_range(5..11, 1);
+    }
+
+    #[test]
+    fn iter_expanded() {
+        // The example from the RangeMap docs.
+        let mut m = RangeMap::new(10, 'a');
+        m.set_range(1..3, 'b');
+        m.set_range(3..6, 'c');
+        m.set_range(6..10, 'd');
+        assert_eq!(
+            m.iter_expanded().cloned().collect::<Vec<_>>(),
+            vec!['a', 'b', 'b', 'c', 'c', 'c', 'd', 'd', 'd', 'd']
+        );
+        assert_eq!(m.iter_expanded().next_back(), Some(&'d'));
+        assert_eq!(RangeMap::new(0, 'a').iter_expanded().count(), 0);
+    }
 }
@@ -0,0 +1,106 @@
+// Copyright 2020 Google LLC
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Simulated searches against a noisy oracle, for benchmarking stiffness settings.
+
+use crate::Searcher;
+use crate::StiffnessCalculator;
+use rand::Rng;
+
+/// Simulates a search over `len` indices for a transition at `target`, and returns the number of
+/// indices tested before `next_index` chose `target`, including that last one, or `max_steps` if
+/// it never did. This is the measure used by tuner.rs.
+///
+/// Each test of an index `i` is random with probability `flakiness`, and otherwise heads if and
+/// only if `i >= target`. Votes are reported with the stiffness from `calc`. The result depends
+/// only on the state of `rng`, so a seeded generator gives reproducible results.
+///
+/// # Panics
+///
+/// Panics if `target >= len`.
+pub fn simulate_steps<R: Rng>(
+    rng: &mut R,
+    len: usize,
+    target: usize,
+    flakiness: f64,
+    calc: &dyn StiffnessCalculator,
+    max_steps: usize,
+) -> usize {
+    assert!(
+        target < len,
+        "target {} out of range for length {}",
+        target,
+        len
+    );
+    let stiffness = calc.stiffness(flakiness);
+    let mut searcher = Searcher::new(len);
+    for step in 1..max_steps {
+        let index = searcher.next_index().unwrap();
+        if index == target {
+            return step;
+        }
+        let heads = if rng.gen::<f64>() < flakiness {
+            rng.gen::<f32>() < 0.5
+        } else {
+            index >= target
+        };
+        searcher.report_with_stiffness(index, heads, stiffness);
+    }
+    max_steps
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::InterpolatingStiffnessCalculator;
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+
+    #[test]
+    fn low_flakiness_takes_log_steps() {
+        let mut rng = StdRng::seed_from_u64(1);
+        let calc = InterpolatingStiffnessCalculator::default();
+        let len = 1 << 10;
+        let runs = 50;
+        let mut total = 0;
+        for _ in 0..runs {
+            let target = rng.gen_range(0, len);
+            let steps = simulate_steps(&mut rng, len, target, 0.001, &calc, 1000);
+            assert!(steps < 1000);
+            total += steps;
+        }
+        let mean = total as f64 / runs as f64;
+        assert!((8.0..=14.0).contains(&mean), "mean = {}", mean);
+    }
+
+    #[test]
+    fn same_seed_same_steps() {
+        let calc = InterpolatingStiffnessCalculator::default();
+        let mut rng1 = StdRng::seed_from_u64(7);
+        let mut rng2 = StdRng::seed_from_u64(7);
+        for target in &[0, 100, 999] {
+            assert_eq!(
+                simulate_steps(&mut rng1, 1000, *target, 0.2, &calc, 1000),
+                simulate_steps(&mut rng2, 1000, *target, 0.2, &calc, 1000)
+            );
+        }
+    }
+
+    #[test]
+    fn max_steps() {
+        let mut rng = StdRng::seed_from_u64(3);
+        let calc = InterpolatingStiffnessCalculator::default();
+        assert_eq!(simulate_steps(&mut rng, 1000, 3, 0.001, &calc, 2), 2);
+    }
+}
@@ -0,0 +1,219 @@
+// Copyright 2020 Google LLC
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use crate::clamp_flakiness;
+use crate::optimal_stiffness;
+use crate::MAX_STIFFNESS;
+use crate::MIN_FLAKINESS;
+use alloc::vec;
+use alloc::vec::Vec;
+// Provides ln and exp for f64 without std. Unused if std is linked in by another crate.
+#[cfg(not(feature = "std"))]
+#[allow(unused_imports)]
+use num_traits::Float;
+
+/// Converts a flakiness into the stiffness used when reporting a vote. See
+/// `Searcher::report_with_calculator`.
+pub trait StiffnessCalculator {
+    /// Returns the stiffness for the given flakiness, which should be in the range (0.0, 1.0].
+    fn stiffness(&self, flakiness: f64) -> f64;
+}
+
+/// Maps flakiness in [MIN_FLAKINESS, 1.0] to [-1.0, 1.0] on a log scale. Flakiness outside that
+/// range is clamped into it.
+fn log_flakiness_to_unit(flakiness: f64) -> f64 {
+    let flakiness = clamp_flakiness(flakiness);
+    1.0 - 2.0 * flakiness.ln() / MIN_FLAKINESS.ln()
+}
+
+/// Computes stiffness from a Chebyshev series approximating the log of the stiffness.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ChebyshevStiffnessCalculator {
+    coefficients: Vec<f64>,
+}
+
+impl ChebyshevStiffnessCalculator {
+    /// Creates a calculator from Chebyshev coefficients. `coefficients[k]` is the coefficient of
+    /// the Chebyshev polynomial of the first kind `T_k(x)`, where `x` is the log of the flakiness
+    /// scaled so that a flakiness of 1e-6 maps to -1.0 and a flakiness of 1.0 maps to 1.0. The
+    /// stiffness is the exponential of the sum of the series, limited to at most 128.0.
+    pub fn new(coefficients: Vec<f64>) -> Self {
+        ChebyshevStiffnessCalculator { coefficients }
+    }
+
+    /// Returns the Chebyshev coefficients.
+    pub fn coefficients(&self) -> &[f64] {
+        &self.coefficients
+    }
+}
+
+impl Default for ChebyshevStiffnessCalculator {
+    /// Returns a calculator which approximates `optimal_stiffness` to within about 20%.
+    fn default() -> Self {
+        // Fit to the log of optimal_stiffness at the Chebyshev nodes.
+        ChebyshevStiffnessCalculator::new(vec![
+            2.8641, -2.8231, -0.9426, -0.2636, -0.313, -0.1618, -0.0097,
+        ])
+    }
+}
+
+impl StiffnessCalculator for ChebyshevStiffnessCalculator {
+    fn stiffness(&self, flakiness: f64) -> f64 {
+        let x = log_flakiness_to_unit(flakiness);
+        // Clenshaw's algorithm
+        let mut b1 = 0.0;
+        let mut b2 = 0.0;
+        for c in self.coefficients.iter().skip(1).rev() {
+            let b = c + 2.0 * x * b1 - b2;
+            b2 = b1;
+            b1 = b;
+        }
+        let c0 = self.coefficients.first().cloned().unwrap_or(0.0);
+        (c0 + x * b1 - b2).exp().min(MAX_STIFFNESS)
+    }
+}
+
+/// Computes stiffness by interpolating between known `(flakiness, stiffness)` points. The
+/// interpolation is linear in log-log space, and flakiness outside the range of the points uses
+/// the stiffness of the nearest point.
+#[derive(Clone, Debug, PartialEq)]
+pub struct InterpolatingStiffnessCalculator {
+    points: Vec<(f64, f64)>,
+}
+
+impl InterpolatingStiffnessCalculator {
+    /// Creates a calculator from `(flakiness, stiffness)` points, which must be sorted by
+    /// flakiness.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `points` is empty, is not sorted by strictly increasing flakiness, or contains a
+    /// flakiness or stiffness which is not finite and positive.
+    pub fn new(points: Vec<(f64, f64)>) -> Self {
+        assert!(!points.is_empty(), "at least one point is required");
+        for (flakiness, stiffness) in &points {
+            assert!(
+                flakiness.is_finite() && *flakiness > 0.0,
+                "invalid flakiness {}",
+                flakiness
+            );
+            assert!(
+                stiffness.is_finite() && *stiffness > 0.0,
+                "invalid stiffness {}",
+                stiffness
+            );
+        }
+        for w in points.windows(2) {
+            assert!(w[0].0 < w[1].0, "points must be sorted by flakiness");
+        }
+        InterpolatingStiffnessCalculator { points }
+    }
+
+    /// Returns the `(flakiness, stiffness)` points.
+    pub fn points(&self) -> &[(f64, f64)] {
+        &self.points
+    }
+}
+
+impl Default for InterpolatingStiffnessCalculator {
+    /// Returns a calculator which interpolates `optimal_stiffness` at one point per decade of
+    /// flakiness.
+    fn default() -> Self {
+        InterpolatingStiffnessCalculator::new(
+            [1e-6, 1e-5, 1e-4, 1e-3, 1e-2, 1e-1, 1.0]
+                .iter()
+                .map(|f| (*f, optimal_stiffness(*f)))
+                .collect(),
+        )
+    }
+}
+
+impl StiffnessCalculator for InterpolatingStiffnessCalculator {
+    fn stiffness(&self, flakiness: f64) -> f64 {
+        let first = self.points[0];
+        let last = self.points[self.points.len() - 1];
+        if flakiness.is_nan() || flakiness >= last.0 {
+            return last.1;
+        }
+        if flakiness <= first.0 {
+            return first.1;
+        }
+        let i = self.points.partition_point(|p| p.0 <= flakiness);
+        let (f0, s0) = self.points[i - 1];
+        let (f1, s1) = self.points[i];
+        let t = (flakiness.ln() - f0.ln()) / (f1.ln() - f0.ln());
+        (s0.ln() + t * (s1.ln() - s0.ln())).exp()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn check_positive_finite(calculator: &dyn StiffnessCalculator) {
+        for i in 0..=1000 {
+            let flakiness = i as f64 / 1000.0;
+            let stiffness = calculator.stiffness(flakiness);
+            assert!(
+                stiffness.is_finite() && stiffness > 0.0,
+                "flakiness {}, stiffness {}",
+                flakiness,
+                stiffness
+            );
+        }
+    }
+
+    #[test]
+    fn chebyshev_positive_finite() {
+        check_positive_finite(&ChebyshevStiffnessCalculator::default());
+        check_positive_finite(&ChebyshevStiffnessCalculator::new(vec![]));
+        check_positive_finite(&ChebyshevStiffnessCalculator::new(vec![1.0, -10.0, 3.0]));
+    }
+
+    #[test]
+    fn chebyshev_approximates_optimal() {
+        let calculator = ChebyshevStiffnessCalculator::default();
+        for flakiness in &[1e-6, 1e-4, 0.01, 0.1, 0.5, 1.0] {
+            let ratio = calculator.stiffness(*flakiness) / optimal_stiffness(*flakiness);
+            assert!(
+                (ratio - 1.0).abs() < 0.2,
+                "flakiness {}, ratio {}",
+                flakiness,
+                ratio
+            );
+        }
+    }
+
+    #[test]
+    fn chebyshev_constant() {
+        let calculator = ChebyshevStiffnessCalculator::new(vec![2.0f64.ln()]);
+        assert!((calculator.stiffness(0.3) - 2.0).abs() < 1e-12);
+    }
+
+    #[test]
+    fn interpolating() {
+        let calculator = InterpolatingStiffnessCalculator::new(vec![(0.01, 100.0), (1.0, 1.0)]);
+        assert_eq!(calculator.stiffness(0.001), 100.0);
+        assert_eq!(calculator.stiffness(2.0), 1.0);
+        assert!((calculator.stiffness(0.1) - 10.0).abs() < 1e-9);
+        check_positive_finite(&calculator);
+        check_positive_finite(&InterpolatingStiffnessCalculator::default());
+    }
+
+    #[test]
+    #[should_panic(expected = "sorted")]
+    fn interpolating_unsorted() {
+        InterpolatingStiffnessCalculator::new(vec![(1.0, 1.0), (0.01, 100.0)]);
+    }
+}
@@ -0,0 +1,88 @@
+// Copyright 2020 Google LLC
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use crate::Searcher;
+
+/// Decides when a search has converged. See `Searcher::is_converged`.
+pub trait StoppingCriterion {
+    /// Returns true if the search should stop.
+    fn should_stop(&self, searcher: &Searcher) -> bool;
+}
+
+/// Stops once the likelihood of the best index is greater than the given value.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct MinLikelihood(pub f64);
+
+impl StoppingCriterion for MinLikelihood {
+    fn should_stop(&self, searcher: &Searcher) -> bool {
+        searcher.likelihood(searcher.best_index()) > self.0
+    }
+}
+
+/// Stops once the `MaxIntervalWidth::CONFIDENCE` credible interval (see
+/// `Searcher::confidence_interval`) contains at most the given number of indices.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct MaxIntervalWidth(pub usize);
+
+impl MaxIntervalWidth {
+    /// Probability mass of the credible interval whose width is checked.
+    pub const CONFIDENCE: f64 = 0.9;
+}
+
+impl StoppingCriterion for MaxIntervalWidth {
+    fn should_stop(&self, searcher: &Searcher) -> bool {
+        let (low, high) = searcher.confidence_interval(Self::CONFIDENCE);
+        high - low < self.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn min_likelihood() {
+        let criterion = MinLikelihood(0.9);
+        let mut searcher = Searcher::new(1024);
+        assert!(!searcher.is_converged(&criterion));
+        let mut steps = 0;
+        while !searcher.is_converged(&criterion) {
+            let index = searcher.next_index().unwrap();
+            searcher.report(index, index >= 100, 0.01);
+            steps += 1;
+            assert!(steps < 100);
+        }
+        assert_eq!(searcher.best_index(), 100);
+        assert!(searcher.likelihood(100) > 0.9);
+    }
+
+    #[test]
+    fn max_interval_width() {
+        let criterion = MaxIntervalWidth(10);
+        let mut searcher = Searcher::new(1024);
+        assert!(!searcher.is_converged(&criterion));
+        let mut steps = 0;
+        while !searcher.is_converged(&criterion) {
+            let index = searcher.next_index().unwrap();
+            searcher.report(index, index >= 100, 0.01);
+            steps += 1;
+            assert!(steps < 100);
+        }
+        let (low, high) = searcher.confidence_interval(MaxIntervalWidth::CONFIDENCE);
+        assert!(low <= 100 && 100 <= high);
+        assert!(high - low < 10);
+        // A wider limit is reached no later than a narrower one.
+        assert!(searcher.is_converged(&MaxIntervalWidth(100)));
+    }
+}
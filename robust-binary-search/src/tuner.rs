@@ -12,7 +12,9 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+use rand::rngs::StdRng;
 use rand::Rng;
+use rand::SeedableRng;
 use robust_binary_search::*;
 use std::cmp;
 use std::env;
@@ -21,35 +23,48 @@ use std::fs::File;
 use std::io::Write;
 use std::process;
 
+/// Always returns the same stiffness, regardless of flakiness.
+struct ConstantStiffness(f64);
+
+impl StiffnessCalculator for ConstantStiffness {
+    fn stiffness(&self, _flakiness: f64) -> f64 {
+        self.0
+    }
+}
+
 fn steps_required<R: Rng>(rng: &mut R, flakiness: f64, stiffness: f64) -> f64 {
     let size = 1 << 20;
     let mut max = 0;
     let count = 100;
     for _ in 0..count {
-        let mut searcher = Searcher::new(size);
-        let mut i = 0;
         let index = (rng.gen::<f64>() * size as f64) as usize;
-        let max_steps = 1000;
-        max = cmp::max(
-            max,
-            loop {
-                i += 1;
-                let test_index = searcher.next_index().unwrap();
-                if test_index == index || i == max_steps {
-                    break i;
-                }
-                let heads = if rng.gen::<f64>() < flakiness {
-                    rng.gen::<f32>() < 0.5
-                } else {
-                    test_index >= index
-                };
-                searcher.report_with_stiffness(test_index, heads, stiffness);
-            },
+        let steps = simulate_steps(
+            rng,
+            size,
+            index,
+            flakiness,
+            &ConstantStiffness(stiffness),
+            1000,
         );
+        max = cmp::max(max, steps);
     }
     max as f64
 }
 
+/// Removes `--seed <n>` from the arguments, if present, and returns a random number generator
+/// seeded with it, so that runs can be reproduced. Without `--seed`, the generator is seeded from
+/// the operating system.
+fn rng_from_args(args: &mut Vec<String>) -> Result<StdRng, Box<dyn Error>> {
+    match args.iter().position(|arg| arg == "--seed") {
+        None => Ok(StdRng::from_entropy()),
+        Some(i) => {
+            let seed = args.get(i + 1).ok_or("--seed requires a value")?.parse()?;
+            args.drain(i..i + 2);
+            Ok(StdRng::seed_from_u64(seed))
+        }
+    }
+}
+
 fn log_interpolate(index: usize, buckets: usize, min: f64, max: f64) -> f64 {
     (min.ln() + index as f64 / buckets as f64 * (max / min).ln()).exp()
 }
@@ -58,9 +73,10 @@ fn main() -> Result<(), Box<dyn Error>> {
     // optimal stiffness is approximately
     // min(2.6/x**0.37, 0.58/x**0.97, 0.19/x**2.4)
     // where x is the flakiness (0 is deterministic, 1 is fully random)
-    let args: Vec<String> = env::args().collect();
+    let mut args: Vec<String> = env::args().collect();
+    let mut rng = rng_from_args(&mut args)?;
     if args.len() != 2 {
-        println!("Usage: main <output_file>");
+        println!("Usage: main [--seed <seed>] <output_file>");
         process::exit(1);
     }
     let mut f = File::create(&args[1])?;
@@ -77,7 +93,6 @@ fn main() -> Result<(), Box<dyn Error>> {
             min_flakiness,
             max_flakiness,
         );
-        let mut rng = rand::thread_rng();
         let mut searcher = Searcher::new(stiffness_buckets);
         let to_stiffness = |i| log_interpolate(i, stiffness_buckets, min_stiffness, max_stiffness);
         let window = 1.5;
@@ -116,3 +131,33 @@ fn main() -> Result<(), Box<dyn Error>> {
     }
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn same_seed_same_steps() {
+        let mut rng1 = StdRng::seed_from_u64(7);
+        let mut rng2 = StdRng::seed_from_u64(7);
+        for flakiness in &[0.01, 0.1] {
+            assert_eq!(
+                steps_required(&mut rng1, *flakiness, 1.0),
+                steps_required(&mut rng2, *flakiness, 1.0)
+            );
+        }
+    }
+
+    #[test]
+    fn rng_from_args_seed() {
+        let mut args = vec!["tuner", "--seed", "42", "out.dat"]
+            .into_iter()
+            .map(String::from)
+            .collect::<Vec<_>>();
+        let mut rng = rng_from_args(&mut args).unwrap();
+        assert_eq!(args, vec!["tuner", "out.dat"]);
+        assert_eq!(rng.gen::<u64>(), StdRng::seed_from_u64(42).gen::<u64>());
+        let mut args = vec!["tuner".to_string(), "--seed".to_string()];
+        assert!(rng_from_args(&mut args).is_err());
+    }
+}
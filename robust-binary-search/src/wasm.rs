@@ -0,0 +1,103 @@
+// Copyright 2020 Google LLC
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! A thin wrapper around `AutoSearcher` for use from JavaScript through wasm-bindgen. Only plain
+//! numbers and booleans cross the boundary, so the API is usable from JS without any glue beyond
+//! what wasm-bindgen generates.
+
+use crate::AutoSearcher;
+use wasm_bindgen::prelude::*;
+
+/// An `AutoSearcher` exported to JavaScript. Indices are JS numbers, and `nextIndex` returns
+/// `undefined` when there is nothing left to test.
+#[wasm_bindgen]
+#[derive(Clone, Debug)]
+pub struct WasmSearcher {
+    searcher: AutoSearcher,
+}
+
+#[wasm_bindgen]
+impl WasmSearcher {
+    /// Creates a new WasmSearcher over a range with the given number of testable indices.
+    #[wasm_bindgen(constructor)]
+    pub fn new(len: usize) -> WasmSearcher {
+        WasmSearcher {
+            searcher: AutoSearcher::new(len),
+        }
+    }
+
+    /// Adds a vote. `bad` is true if the index is at or after the transition. See
+    /// `AutoSearcher::report`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `index >= len`.
+    pub fn report(&mut self, index: usize, bad: bool) {
+        self.searcher.report(index, bad);
+    }
+
+    /// Returns the next index that should be tested. See `AutoSearcher::next_index`.
+    #[wasm_bindgen(js_name = nextIndex)]
+    pub fn next_index(&self) -> Option<usize> {
+        self.searcher.next_index()
+    }
+
+    /// Returns the current estimate of the best index. See `AutoSearcher::best_index`.
+    #[wasm_bindgen(js_name = bestIndex)]
+    pub fn best_index(&self) -> usize {
+        self.searcher.best_index()
+    }
+
+    /// Returns the likelihood of the given index.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `index > len`.
+    pub fn likelihood(&self, index: usize) -> f64 {
+        self.searcher.likelihood(index)
+    }
+
+    /// Returns the estimated flakiness.
+    pub fn flakiness(&self) -> f64 {
+        self.searcher.flakiness()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_rng::TestRng;
+
+    #[test]
+    fn matches_auto_searcher() {
+        let len = 100;
+        let transition = 37;
+        let mut wasm = WasmSearcher::new(len);
+        let mut native = AutoSearcher::new(len);
+        let mut rng = TestRng::new(1);
+        for _ in 0..50 {
+            let index = native.next_index().unwrap();
+            assert_eq!(wasm.next_index(), Some(index));
+            // Flip about 10% of the votes.
+            let flip = rng.below(10) == 0;
+            let bad = (index >= transition) != flip;
+            wasm.report(index, bad);
+            native.report(index, bad);
+            assert_eq!(wasm.best_index(), native.best_index());
+            assert_eq!(wasm.flakiness(), native.flakiness());
+            assert_eq!(wasm.likelihood(index), native.likelihood(index));
+        }
+        assert_eq!(wasm.best_index(), transition);
+    }
+}
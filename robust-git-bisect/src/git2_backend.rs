@@ -0,0 +1,304 @@
+// Copyright 2020 Google LLC
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Implementations of `CommitGraphSource` and `CommitTester` which use libgit2 rather than running
+//! the git command.
+
+use crate::run_test_cmd;
+use crate::CommitEdges;
+use crate::CommitGraphSource;
+use crate::CommitTester;
+use crate::ExitCodes;
+use crate::Verdict;
+use git2::build::CheckoutBuilder;
+use git2::Repository;
+use std::error::Error;
+
+/// Reads the commit graph with libgit2.
+pub struct Git2GraphSource {
+    repo: Repository,
+    first_parent: bool,
+}
+
+impl Git2GraphSource {
+    /// Opens the repository containing `dir`.
+    pub fn open(dir: &str) -> Result<Self, git2::Error> {
+        Ok(Git2GraphSource {
+            repo: Repository::open(dir)?,
+            first_parent: false,
+        })
+    }
+
+    /// Sets whether only the first parent of each merge commit is followed.
+    pub fn with_first_parent(mut self, first_parent: bool) -> Self {
+        self.first_parent = first_parent;
+        self
+    }
+}
+
+impl CommitGraphSource for Git2GraphSource {
+    fn commit_edges(
+        &self,
+        start_commit: &str,
+        end_commit: &str,
+    ) -> Result<CommitEdges, Box<dyn Error>> {
+        let mut walk = self.repo.revwalk()?;
+        walk.push(
+            self.repo
+                .revparse_single(end_commit)?
+                .peel_to_commit()?
+                .id(),
+        )?;
+        walk.hide(
+            self.repo
+                .revparse_single(start_commit)?
+                .peel_to_commit()?
+                .id(),
+        )?;
+        if self.first_parent {
+            walk.simplify_first_parent()?;
+        }
+        let parents = if self.first_parent { 1 } else { usize::MAX };
+        let mut edges = Vec::new();
+        for oid in walk {
+            let commit = self.repo.find_commit(oid?)?;
+            edges.push((
+                commit.id().to_string(),
+                commit
+                    .parent_ids()
+                    .take(parents)
+                    .map(|id| id.to_string())
+                    .collect(),
+            ));
+        }
+        Ok(edges)
+    }
+}
+
+/// Checks out commits with libgit2 and tests them with a shell command. Checkouts detach HEAD and,
+/// like `git checkout`, fail rather than overwrite uncommitted changes.
+pub struct Git2CommitTester<'a> {
+    repo: Repository,
+    dir: &'a str,
+    test_cmd: &'a str,
+    exit_codes: ExitCodes,
+}
+
+impl<'a> Git2CommitTester<'a> {
+    /// Opens the repository or worktree in `dir`.
+    pub fn open(
+        dir: &'a str,
+        test_cmd: &'a str,
+        exit_codes: ExitCodes,
+    ) -> Result<Self, git2::Error> {
+        Ok(Git2CommitTester {
+            repo: Repository::open(dir)?,
+            dir,
+            test_cmd,
+            exit_codes,
+        })
+    }
+}
+
+impl<'a> CommitTester for Git2CommitTester<'a> {
+    fn checkout(&mut self, commit: &str) -> Result<(), Box<dyn Error>> {
+        let object = self.repo.revparse_single(commit)?;
+        self.repo
+            .checkout_tree(&object, Some(CheckoutBuilder::new().safe()))?;
+        self.repo.set_head_detached(object.id())?;
+        Ok(())
+    }
+
+    fn test(&mut self) -> Verdict {
+        run_test_cmd(self.dir, self.test_cmd, &self.exit_codes)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::run;
+    use crate::run_bisect;
+    use crate::BisectOptions;
+    use crate::GitCommandSource;
+    use crate::Metrics;
+    use git2::Oid;
+    use git2::Signature;
+    use std::fs;
+    use std::io;
+    use std::path::Path;
+    use std::path::PathBuf;
+
+    /// Creates a fixture repository in `dir` with 20 commits, each of which sets the file `n` to its
+    /// number. Commits 6-8 and 9-10 are parallel branches off of commit 5, which are merged by
+    /// commit 11. The history is otherwise linear. `main` is checked out at commit 19.
+    fn fixture_repo(dir: &Path) -> (Repository, Vec<Oid>) {
+        let repo = Repository::init(dir).unwrap();
+        let signature = Signature::now("Test", "test@example.com").unwrap();
+        let mut hashes = Vec::<Oid>::new();
+        for i in 0..20 {
+            let parents = match i {
+                0 => vec![],
+                9 => vec![hashes[5]],
+                11 => vec![hashes[8], hashes[10]],
+                _ => vec![hashes[i - 1]],
+            };
+            let parents = parents
+                .iter()
+                .map(|oid| repo.find_commit(*oid).unwrap())
+                .collect::<Vec<_>>();
+            let blob = repo.blob(format!("{}\n", i).as_bytes()).unwrap();
+            let mut tree_builder = repo.treebuilder(None).unwrap();
+            tree_builder.insert("n", blob, 0o100644).unwrap();
+            let tree = repo.find_tree(tree_builder.write().unwrap()).unwrap();
+            let oid = repo
+                .commit(
+                    None,
+                    &signature,
+                    &signature,
+                    &format!("commit {}", i),
+                    &tree,
+                    &parents.iter().collect::<Vec<_>>(),
+                )
+                .unwrap();
+            hashes.push(oid);
+        }
+        repo.reference("refs/heads/main", hashes[19], true, "fixture")
+            .unwrap();
+        repo.set_head("refs/heads/main").unwrap();
+        repo.checkout_head(Some(CheckoutBuilder::new().force()))
+            .unwrap();
+        (repo, hashes)
+    }
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir =
+            std::env::temp_dir().join(format!("robust-git-bisect-{}-{}", name, std::process::id()));
+        if dir.exists() {
+            fs::remove_dir_all(&dir).unwrap();
+        }
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn compare_edges(name: &str, first_parent: bool) -> usize {
+        let dir = temp_dir(name);
+        let (_repo, hashes) = fixture_repo(&dir);
+        let dir_str = dir.to_str().unwrap();
+        let start = hashes[0].to_string();
+        let end = hashes[19].to_string();
+        let mut expected = GitCommandSource {
+            dir: dir_str,
+            path: None,
+            first_parent,
+        }
+        .commit_edges(&start, &end)
+        .unwrap();
+        let mut actual = Git2GraphSource::open(dir_str)
+            .unwrap()
+            .with_first_parent(first_parent)
+            .commit_edges(&start, &end)
+            .unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+        expected.sort();
+        actual.sort();
+        assert_eq!(actual, expected);
+        actual.len()
+    }
+
+    #[test]
+    fn edges_match_git_command() {
+        assert_eq!(compare_edges("git2-edges", false), 19);
+    }
+
+    #[test]
+    fn first_parent_edges_match_git_command() {
+        // Commits 9 and 10 are on the second parent's branch.
+        assert_eq!(compare_edges("git2-first-parent-edges", true), 17);
+    }
+
+    #[test]
+    fn checkout_keeps_uncommitted_changes() {
+        let dir = temp_dir("git2-dirty");
+        let (repo, hashes) = fixture_repo(&dir);
+        fs::write(dir.join("n"), "local change\n").unwrap();
+        let mut tester =
+            Git2CommitTester::open(dir.to_str().unwrap(), "true", ExitCodes::default()).unwrap();
+        let result = tester.checkout(&hashes[3].to_string());
+        let head = repo.head().unwrap();
+        let n = fs::read_to_string(dir.join("n")).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+        assert!(result.is_err());
+        assert_eq!(head.name(), Some("refs/heads/main"));
+        assert_eq!(n, "local change\n");
+    }
+
+    #[test]
+    fn checkout_unknown_commit() {
+        let dir = temp_dir("git2-unknown");
+        fixture_repo(&dir);
+        let mut tester =
+            Git2CommitTester::open(dir.to_str().unwrap(), "true", ExitCodes::default()).unwrap();
+        let result = tester.checkout("no-such-commit");
+        fs::remove_dir_all(&dir).unwrap();
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn bisect_in_worktree() {
+        let dir = temp_dir("git2-worktree");
+        let repo_dir = dir.join("repo");
+        let worktree_dir = dir.join("worktree");
+        let (repo, hashes) = fixture_repo(&repo_dir);
+        let repo_str = repo_dir.to_str().unwrap();
+        let worktree_str = worktree_dir.to_str().unwrap();
+        run("git", |cmd| {
+            cmd.current_dir(repo_str)
+                .args(["worktree", "add", "--detach", worktree_str])
+        })
+        .unwrap();
+        let mut metrics = Metrics::default();
+        let (graph, segments) = Git2GraphSource::open(repo_str)
+            .unwrap()
+            .load_history(
+                &hashes[0].to_string(),
+                &hashes[19].to_string(),
+                &mut metrics,
+            )
+            .unwrap();
+        let mut tester =
+            Git2CommitTester::open(worktree_str, "[ $(cat n) -lt 14 ]", ExitCodes::default())
+                .unwrap();
+        let report = run_bisect(
+            graph,
+            &segments,
+            &mut tester,
+            &BisectOptions {
+                min_likelihood: 0.9,
+                ..BisectOptions::default()
+            },
+            &mut metrics,
+            &mut io::sink(),
+        )
+        .unwrap();
+        let head = repo.head().unwrap();
+        let n = fs::read_to_string(repo_dir.join("n")).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(report.best_commit, hashes[14].to_string());
+        // The main checkout is untouched.
+        assert_eq!(head.name(), Some("refs/heads/main"));
+        assert_eq!(head.target(), Some(hashes[19]));
+        assert_eq!(n, "19\n");
+    }
+}
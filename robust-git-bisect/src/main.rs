@@ -13,51 +13,108 @@
 // limitations under the License.
 
 use clap::App;
+use clap::AppSettings;
 use clap::Arg;
+use clap::ArgMatches;
+use clap::SubCommand;
 use log::info;
 use log::trace;
-use robust_binary_search::AutoCompressedDAGSearcher;
-use robust_binary_search::CompressedDAG;
-use robust_binary_search::CompressedDAGSegment;
+use robust_binary_search::AutoCompressedDagSearcher;
+use robust_binary_search::CompressedDag;
+use robust_binary_search::CompressedDagNodeRef;
+use serde::Deserialize;
+use serde::Serialize;
 use simplelog::Config;
 use simplelog::LevelFilter;
 use simplelog::TermLogger;
 use simplelog::TerminalMode;
+use std::collections::BTreeMap;
 use std::collections::HashMap;
-use std::collections::HashSet;
 use std::error::Error;
+use std::fmt;
+use std::fs::File;
+use std::io;
+use std::io::Write;
 use std::path::Path;
 use std::process::Command;
 use std::rc::Rc;
 use std::time::Duration;
 use std::time::Instant;
-use union_find::QuickFindUf;
-use union_find::Union;
-use union_find::UnionFind;
-use union_find::UnionResult;
 
-#[derive(Clone, Debug)]
-struct StringUnion(String);
-
-impl Union for StringUnion {
-    fn union(lval: Self, _rval: Self) -> UnionResult<Self> {
-        UnionResult::Left(lval)
-    }
-}
+#[cfg(feature = "git2")]
+mod git2_backend;
 
 #[derive(Debug, Default)]
-struct GitSegmentUf {
-    parents: Vec<usize>,
+struct GitSegment {
     commits: Vec<String>,
 }
 
+/// Cumulative time spent in each phase of a bisection.
 #[derive(Debug, Default)]
-struct GitSegment {
-    parents: Vec<usize>,
-    commits: Vec<String>,
+struct Metrics {
+    durations: BTreeMap<String, Duration>,
+    iterations: usize,
+}
+
+impl Metrics {
+    /// Adds `duration` to the time spent in `phase`.
+    fn record(&mut self, phase: &str, duration: Duration) {
+        *self.durations.entry(phase.to_string()).or_default() += duration;
+    }
+
+    /// Runs `f` and adds the time it took to `phase`.
+    fn time<R, F: FnOnce() -> R>(&mut self, phase: &str, f: F) -> R {
+        let start = Instant::now();
+        let result = f();
+        self.record(phase, start.elapsed());
+        result
+    }
+
+    /// Returns one line per phase with its total time and its average time per iteration, followed
+    /// by the number of iterations.
+    fn summary(&self) -> Vec<String> {
+        let mut lines = self
+            .durations
+            .iter()
+            .map(|(phase, duration)| {
+                let seconds = duration.as_secs_f64();
+                if self.iterations > 0 {
+                    format!(
+                        "{:>12}: {:.3} seconds ({:.3} per iteration)",
+                        phase,
+                        seconds,
+                        seconds / self.iterations as f64
+                    )
+                } else {
+                    format!("{:>12}: {:.3} seconds", phase, seconds)
+                }
+            })
+            .collect::<Vec<_>>();
+        lines.push(format!("{:>12}: {}", "iterations", self.iterations));
+        lines
+    }
 }
 
-fn run<F>(name: &str, mut configure: F) -> Result<String, String>
+/// A command which exited unsuccessfully.
+#[derive(Debug)]
+struct CommandError {
+    description: String,
+    /// The exit code, or None if the command was terminated by a signal.
+    code: Option<i32>,
+}
+
+impl fmt::Display for CommandError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.code {
+            Some(code) => write!(f, "{} exited with code {}", self.description, code),
+            None => write!(f, "{} was terminated by a signal", self.description),
+        }
+    }
+}
+
+impl Error for CommandError {}
+
+fn run<F>(name: &str, mut configure: F) -> Result<String, CommandError>
 where
     F: FnMut(&mut Command) -> &mut Command,
 {
@@ -66,105 +123,771 @@ where
     info!("Executing {:?}", configured);
     let out = configured.output().unwrap();
     if !out.status.success() {
-        let msg = format!("failed to execute {:?}", configured);
-        info!("{}", msg);
-        return Err(msg);
+        let err = CommandError {
+            description: format!("{:?}", configured),
+            code: out.status.code(),
+        };
+        info!("{}", err);
+        return Err(err);
     }
     info!("Command {:?} finished successfully", configured);
     Ok(String::from_utf8(out.stdout).unwrap())
 }
 
-fn sort_segments(segments: &HashMap<usize, GitSegmentUf>) -> Vec<usize> {
-    let mut parents = HashMap::<usize, HashSet<usize>>::new();
-    let mut children = HashMap::<usize, HashSet<usize>>::new();
-    let mut initial_segments = Vec::new();
-    for (id, segment) in segments {
-        parents.insert(*id, segment.parents.iter().copied().collect());
-        for parent in &segment.parents {
-            children
-                .entry(*parent)
-                .or_insert_with(HashSet::new)
-                .insert(*id);
-        }
-        if segment.parents.is_empty() {
-            initial_segments.push(*id);
-        }
-    }
-    let mut sorted = Vec::new();
-    while let Some(id) = initial_segments.pop() {
-        sorted.push(id);
-        if let Some(children_to_update) = children.get(&id) {
-            for child in children_to_update {
-                let p = parents.get_mut(child).unwrap();
-                p.remove(&id);
-                if p.is_empty() {
-                    parents.remove(child);
-                    initial_segments.push(*child);
-                }
+/// Commits along with their parents.
+type CommitEdges = Vec<(String, Vec<String>)>;
+
+/// Reads the commit graph of a repository.
+trait CommitGraphSource {
+    /// Returns every commit in `start_commit..end_commit` along with its parents, in any order.
+    fn commit_edges(
+        &self,
+        start_commit: &str,
+        end_commit: &str,
+    ) -> Result<CommitEdges, Box<dyn Error>>;
+
+    /// Reads the commits in `start_commit..end_commit` and builds the graph of their segments.
+    fn load_history(
+        &self,
+        start_commit: &str,
+        end_commit: &str,
+        metrics: &mut Metrics,
+    ) -> Result<(CompressedDag, Vec<GitSegment>), Box<dyn Error>> {
+        let edges = self.commit_edges(start_commit, end_commit)?;
+        let graph_start = Instant::now();
+        let (graph, refs) = CompressedDag::from_edges(&edges);
+        metrics.record("graph-built", graph_start.elapsed());
+        trace!(
+            "CompressedDag built in {} seconds",
+            graph_start.elapsed().as_secs_f64()
+        );
+        let mut git_segments = graph
+            .nodes()
+            .iter()
+            .map(|node| GitSegment {
+                commits: vec![String::new(); node.value().len()],
+            })
+            .collect::<Vec<_>>();
+        for (commit, node) in refs {
+            git_segments[node.segment].commits[node.index] = commit;
+        }
+        Ok((graph, git_segments))
+    }
+}
+
+/// Reads the commit graph by running `git log`.
+struct GitCommandSource<'a> {
+    dir: &'a str,
+    /// If set, only commits which touch this pathspec are read. Their parents are rewritten to
+    /// skip over commits which don't.
+    path: Option<&'a str>,
+    /// If true, only the first parent of each merge commit is followed, like
+    /// `git bisect --first-parent`.
+    first_parent: bool,
+}
+
+impl<'a> CommitGraphSource for GitCommandSource<'a> {
+    fn commit_edges(
+        &self,
+        start_commit: &str,
+        end_commit: &str,
+    ) -> Result<CommitEdges, Box<dyn Error>> {
+        let commit_log = run("git", |command| {
+            // TODO: Do we need --ancestry-path?
+            command
+                .current_dir(self.dir)
+                .arg("log")
+                .arg(format!("{}..{}", start_commit, end_commit))
+                .arg("--format=format:%H %P");
+            if self.first_parent {
+                command.arg("--first-parent");
             }
+            if let Some(path) = self.path {
+                // Without --parents, %P lists the original parents even if they don't touch the
+                // path, which would disconnect the graph.
+                command.arg("--parents").arg("--").arg(path);
+            }
+            command
+        })?;
+        Ok(commit_log
+            .lines()
+            .map(|line| {
+                let mut hashes = line.split(' ').map(|s| s.to_string()).collect::<Vec<_>>();
+                let commit = hashes.remove(0);
+                // %P still lists every parent with --first-parent.
+                if self.first_parent {
+                    hashes.truncate(1);
+                }
+                (commit, hashes)
+            })
+            .collect())
+    }
+}
+
+/// Maps exit codes of the test command to verdicts.
+#[derive(Clone, Debug)]
+pub struct ExitCodes {
+    good: Vec<i32>,
+    /// Codes which mean the commit is bad. If None, every code which is neither good nor skip is
+    /// bad, including termination by a signal.
+    bad: Option<Vec<i32>>,
+    skip: Vec<i32>,
+}
+
+impl Default for ExitCodes {
+    /// 0 is good, 125 is skip (as with `git bisect run`), and everything else is bad.
+    fn default() -> Self {
+        ExitCodes {
+            good: vec![0],
+            bad: None,
+            skip: vec![125],
         }
-        children.remove(&id);
     }
-    sorted
 }
 
-fn run_bisect<P: AsRef<Path>>(
-    dir: P,
-    segments: &[GitSegment],
-    test_cmd: &str,
+impl ExitCodes {
+    /// Returns the verdict for an exit code, or None if the command was terminated by a signal.
+    /// If bad codes are listed, codes which aren't listed at all are treated as skip.
+    fn classify(&self, code: Option<i32>) -> Verdict {
+        match code {
+            Some(code) if self.good.contains(&code) => Verdict::Good,
+            Some(code) if self.skip.contains(&code) => Verdict::Skip,
+            _ => match &self.bad {
+                None => Verdict::Bad,
+                Some(bad) if code.is_some_and(|code| bad.contains(&code)) => Verdict::Bad,
+                Some(_) => Verdict::Skip,
+            },
+        }
+    }
+}
+
+/// Reads `--good-exit`, `--bad-exit`, and `--skip-exit`.
+fn parse_exit_codes(matches: &ArgMatches) -> Result<ExitCodes, Box<dyn Error>> {
+    let codes = |name: &str| -> Result<Option<Vec<i32>>, Box<dyn Error>> {
+        match matches.values_of(name) {
+            None => Ok(None),
+            Some(values) => Ok(Some(
+                values
+                    .map(|value| {
+                        value
+                            .parse::<i32>()
+                            .map_err(|_| format!("invalid exit code {:?} for --{}", value, name))
+                    })
+                    .collect::<Result<_, _>>()?,
+            )),
+        }
+    };
+    let defaults = ExitCodes::default();
+    let exit_codes = ExitCodes {
+        good: codes("good-exit")?.unwrap_or(defaults.good),
+        bad: codes("bad-exit")?,
+        skip: codes("skip-exit")?.unwrap_or(defaults.skip),
+    };
+    let bad = exit_codes.bad.as_deref().unwrap_or(&[]);
+    for code in &exit_codes.good {
+        if exit_codes.skip.contains(code) || bad.contains(code) {
+            return Err(format!("exit code {} is mapped to more than one verdict", code).into());
+        }
+    }
+    if let Some(code) = exit_codes.skip.iter().find(|code| bad.contains(code)) {
+        return Err(format!("exit code {} is mapped to more than one verdict", code).into());
+    }
+    Ok(exit_codes)
+}
+
+/// Runs `test_cmd` with `sh` in `dir`, and classifies its exit code.
+fn run_test_cmd(dir: &str, test_cmd: &str, exit_codes: &ExitCodes) -> Verdict {
+    let code = match run("sh", |cmd| cmd.current_dir(dir).arg("-c").arg(test_cmd)) {
+        Ok(_) => Some(0),
+        Err(CommandError { code, .. }) => code,
+    };
+    exit_codes.classify(code)
+}
+
+/// Checks out and tests commits.
+trait CommitTester {
+    /// Checks out the given commit. Fails rather than discarding uncommitted changes.
+    fn checkout(&mut self, commit: &str) -> Result<(), Box<dyn Error>>;
+
+    /// Tests the currently checked out commit.
+    fn test(&mut self) -> Verdict;
+}
+
+/// Checks out commits with git and tests them with a shell command.
+struct ShellCommitTester<'a> {
+    dir: &'a str,
+    test_cmd: &'a str,
+    exit_codes: ExitCodes,
+}
+
+impl<'a> CommitTester for ShellCommitTester<'a> {
+    fn checkout(&mut self, commit: &str) -> Result<(), Box<dyn Error>> {
+        run("git", |cmd| {
+            cmd.current_dir(self.dir).arg("checkout").arg(commit)
+        })?;
+        Ok(())
+    }
+
+    fn test(&mut self) -> Verdict {
+        run_test_cmd(self.dir, self.test_cmd, &self.exit_codes)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+enum Verdict {
+    Good,
+    Bad,
+    /// The commit cannot be tested, e.g. because it doesn't build.
+    Skip,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+struct TestedCommit {
+    commit: String,
+    verdict: Verdict,
+}
+
+/// Parses a test result given as `<commit>=<good|bad|skip>`.
+fn parse_result(result: &str) -> Result<TestedCommit, String> {
+    let mut parts = result.splitn(2, '=');
+    let commit = parts.next().unwrap();
+    let verdict = match parts.next() {
+        Some("good") => Verdict::Good,
+        Some("bad") => Verdict::Bad,
+        Some("skip") => Verdict::Skip,
+        _ => {
+            return Err(format!(
+                "invalid result {:?}, expected <commit>=<good|bad|skip>",
+                result
+            ))
+        }
+    };
+    Ok(TestedCommit {
+        commit: commit.to_string(),
+        verdict,
+    })
+}
+
+/// Everything the `next` subcommand needs to remember between runs, persisted with
+/// `--state-file`. The searcher is rebuilt by replaying the results, so the state stays valid even
+/// if the commit graph is loaded in a different order.
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+struct BisectState {
+    start_commit: String,
+    end_commit: String,
+    /// Every result reported so far, in order.
+    tested_commits: Vec<TestedCommit>,
+}
+
+/// Reads the state file, or returns an empty state if it doesn't exist yet.
+fn load_state(path: &Path) -> Result<BisectState, Box<dyn Error>> {
+    if !path.exists() {
+        return Ok(BisectState::default());
+    }
+    Ok(serde_json::from_reader(io::BufReader::new(File::open(
+        path,
+    )?))?)
+}
+
+/// Writes the state file.
+fn save_state(path: &Path, state: &BisectState) -> Result<(), Box<dyn Error>> {
+    serde_json::to_writer_pretty(File::create(path)?, state)?;
+    Ok(())
+}
+
+#[derive(Clone, Debug, Serialize)]
+struct Suspect {
+    commit: String,
+    #[serde(skip)]
+    node: CompressedDagNodeRef,
+    likelihood: f64,
+}
+
+/// Summary of a bisection, written out by `--report`.
+#[derive(Clone, Debug, Default, Serialize)]
+struct BisectReport {
+    best_commit: String,
+    likelihood: f64,
+    flakiness: f64,
+    /// Estimated flakiness of the commits before the best commit.
+    flakiness_before: f64,
+    /// Estimated flakiness of the best commit and its descendants.
+    flakiness_after: f64,
+    iterations: usize,
+    /// True if the search stopped because skipped commits made it impossible to narrow the
+    /// credible set down to a single commit.
+    ambiguous: bool,
+    /// Every commit tested, in order, with the test's verdict.
+    tested_commits: Vec<TestedCommit>,
+    /// The most likely commits whose likelihoods add up to at least the minimum likelihood, most
+    /// likely first.
+    credible_set: Vec<Suspect>,
+}
+
+/// How progress and results are printed.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum OutputFormat {
+    /// Human-readable text.
+    Text,
+    /// One JSON object per line: an `iteration` object after each test, followed by a `summary`
+    /// object containing the `BisectReport`.
+    Json,
+}
+
+/// Settings for `run_bisect`.
+#[derive(Clone, Debug)]
+struct BisectOptions {
+    /// Minimum likelihood required to stop iterating.
     min_likelihood: f64,
-) -> HashMap<String, Duration> {
-    let start = Instant::now();
-    let mut graph = CompressedDAG::new();
+    /// Number of times to run the test at each chosen commit. Every run is reported as a separate
+    /// vote, so disagreements between runs at the same commit count as inversions and raise the
+    /// estimated flakiness. Runs are assumed to be independent; if a commit's failures are
+    /// correlated (e.g. a test that's broken until the machine is rebooted), repeats will overstate
+    /// the confidence.
+    repeats: usize,
+    format: OutputFormat,
+    /// If true, progress after each test is only logged, and only the final result is printed.
+    quiet: bool,
+}
+
+impl Default for BisectOptions {
+    fn default() -> Self {
+        BisectOptions {
+            min_likelihood: 0.99,
+            repeats: 1,
+            format: OutputFormat::Text,
+            quiet: false,
+        }
+    }
+}
+
+/// Progress after a single test, printed with `--format=json`.
+#[derive(Debug, Serialize)]
+struct IterationOutput<'a> {
+    commit: &'a str,
+    verdict: Verdict,
+    best_commit: &'a str,
+    likelihood: f64,
+    iterations: usize,
+    flakiness: f64,
+    flakiness_before: f64,
+    flakiness_after: f64,
+}
+
+/// Result of the `next` subcommand, printed as JSON with `--format=json`.
+#[derive(Debug, Serialize)]
+struct NextOutput {
+    /// Commit to test next, or None if the search is complete or every likely commit is skipped.
+    next_commit: Option<String>,
+    best_commit: String,
+    likelihood: f64,
+    flakiness: f64,
+    /// Number of results reported so far.
+    iterations: usize,
+}
+
+/// A line of output printed with `--format=json`.
+#[derive(Debug, Serialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+enum JsonOutput<'a> {
+    Iteration(IterationOutput<'a>),
+    Summary(&'a BisectReport),
+    Next(&'a NextOutput),
+}
+
+/// Returns the most likely commits whose likelihoods add up to at least `mass`, most likely first.
+fn credible_set(
+    searcher: &AutoCompressedDagSearcher,
+    segments: &[GitSegment],
+    mass: f64,
+) -> Vec<Suspect> {
+    let mut suspects = Vec::new();
     for (i, segment) in segments.iter().enumerate() {
-        if i % 100 == 0 {
-            trace!("Processing segment {} of {}", i, segments.len());
+        for (j, commit) in segment.commits.iter().enumerate() {
+            let node = CompressedDagNodeRef {
+                segment: i,
+                index: j,
+            };
+            suspects.push(Suspect {
+                commit: commit.clone(),
+                node,
+                likelihood: searcher.likelihood(node),
+            });
         }
-        graph.add_node(
-            CompressedDAGSegment::new(segment.commits.len()),
-            segment.parents.clone(),
-        );
     }
-    let mut metrics = HashMap::new();
-    metrics.insert("graph-built".to_string(), start.elapsed());
-    trace!(
-        "CompressedDAG built in {} seconds",
-        start.elapsed().as_secs_f64()
-    );
-    let mut searcher = AutoCompressedDAGSearcher::new(Rc::new(graph));
+    suspects.sort_by(|a, b| b.likelihood.partial_cmp(&a.likelihood).unwrap());
+    let mut sum = 0.0;
+    let count = suspects
+        .iter()
+        .take_while(|suspect| {
+            let needed = sum < mass;
+            sum += suspect.likelihood;
+            needed
+        })
+        .count();
+    suspects.truncate(count);
+    suspects
+}
+
+/// Logs a progress message at info level, and also prints it unless the output format is JSON or
+/// `quiet` is set.
+fn progress(out: &mut dyn Write, options: &BisectOptions, message: &str) -> io::Result<()> {
+    info!("{}", message);
+    if options.format == OutputFormat::Text && !options.quiet {
+        writeln!(out, "{}", message)?;
+    }
+    Ok(())
+}
+
+/// Describes the most likely commit and the estimated flakiness.
+fn best_commit_message(
+    searcher: &AutoCompressedDagSearcher,
+    segments: &[GitSegment],
+    iterations: usize,
+) -> String {
+    let best = searcher.best_node();
+    let (flakiness_before, flakiness_after) = searcher.split_flakiness();
+    format!(
+        "Most likely commit is {} with likelihood {} after {} iterations.  Estimated flakiness is {} ({} before and {} after the most likely commit).",
+        segments[best.segment].commits[best.index],
+        searcher.likelihood(best),
+        iterations,
+        searcher.flakiness(),
+        flakiness_before,
+        flakiness_after
+    )
+}
+
+fn run_bisect<T: CommitTester + ?Sized>(
+    graph: CompressedDag,
+    segments: &[GitSegment],
+    tester: &mut T,
+    options: &BisectOptions,
+    metrics: &mut Metrics,
+    out: &mut dyn Write,
+) -> Result<BisectReport, Box<dyn Error>> {
+    let min_likelihood = options.min_likelihood;
+    let format = options.format;
+    let mut searcher = AutoCompressedDagSearcher::new(Rc::new(graph));
+    let mut report = BisectReport::default();
     let mut iterations = 0;
+    let mut any_skipped = false;
     loop {
-        iterations += 1;
         let node = searcher.next_node();
+        if searcher.is_skipped(node) {
+            // Every commit has been skipped.
+            report.ambiguous = true;
+            break;
+        }
+        iterations += 1;
+        metrics.iterations += 1;
         let commit = &segments[node.segment].commits[node.index];
-        run("git", |cmd| {
-            cmd.current_dir(&dir).arg("checkout").arg(commit)
-        })
-        .unwrap();
-        let heads = run("sh", |cmd| cmd.current_dir(&dir).arg("-c").arg(test_cmd)).is_err();
-        println!(
-            "Reporting {} as {}",
-            commit,
-            if heads { "bad" } else { "good" }
-        );
-        searcher.report(node, heads);
+        metrics.time("checkout", || tester.checkout(commit))?;
+        for _ in 0..options.repeats {
+            let verdict = metrics.time("test", || tester.test());
+            report.tested_commits.push(TestedCommit {
+                commit: commit.clone(),
+                verdict,
+            });
+            match verdict {
+                Verdict::Good | Verdict::Bad => {
+                    let heads = verdict == Verdict::Bad;
+                    let message = format!(
+                        "Reporting {} as {}",
+                        commit,
+                        if heads { "bad" } else { "good" }
+                    );
+                    progress(out, options, &message)?;
+                    metrics.time("report", || searcher.report(node, heads));
+                }
+                Verdict::Skip => {
+                    let message = format!("Skipping {}, which cannot be tested", commit);
+                    progress(out, options, &message)?;
+                    searcher.add_skip(node);
+                    any_skipped = true;
+                }
+            }
+            if format == OutputFormat::Json && !options.quiet {
+                let best = searcher.best_node();
+                let (flakiness_before, flakiness_after) = searcher.split_flakiness();
+                let line = JsonOutput::Iteration(IterationOutput {
+                    commit,
+                    verdict,
+                    best_commit: &segments[best.segment].commits[best.index],
+                    likelihood: searcher.likelihood(best),
+                    iterations,
+                    flakiness: searcher.flakiness(),
+                    flakiness_before,
+                    flakiness_after,
+                });
+                serde_json::to_writer(&mut *out, &line)?;
+                writeln!(out)?;
+            }
+            if verdict == Verdict::Skip {
+                break;
+            }
+        }
         let best = searcher.best_node();
-        let best_commit = segments[best.segment].commits[best.index].clone();
-        println!("Most likely commit is {} with likelihood {} after {} iterations.  Estimated flakiness is {}.",
-                 best_commit, searcher.likelihood(best), iterations, searcher.flakiness());
+        progress(
+            out,
+            options,
+            &best_commit_message(&searcher, segments, iterations),
+        )?;
         if searcher.likelihood(best) > min_likelihood {
             break;
         }
+        // If skipped commits account for all but one of the likely commits, further tests can't
+        // tell them apart.
+        if any_skipped
+            && credible_set(&searcher, segments, min_likelihood)
+                .iter()
+                .filter(|suspect| !searcher.is_skipped(suspect.node))
+                .count()
+                <= 1
+        {
+            report.ambiguous = true;
+            break;
+        }
     }
-    metrics
+    let best = searcher.best_node();
+    report.best_commit = segments[best.segment].commits[best.index].clone();
+    report.likelihood = searcher.likelihood(best);
+    report.flakiness = searcher.flakiness();
+    let (flakiness_before, flakiness_after) = searcher.split_flakiness();
+    report.flakiness_before = flakiness_before;
+    report.flakiness_after = flakiness_after;
+    report.iterations = iterations;
+    report.credible_set = credible_set(&searcher, segments, min_likelihood);
+    match format {
+        OutputFormat::Text => {
+            if options.quiet && iterations > 0 {
+                writeln!(
+                    out,
+                    "{}",
+                    best_commit_message(&searcher, segments, iterations)
+                )?;
+            }
+            if report.ambiguous {
+                writeln!(
+                    out,
+                    "Skipped commits prevent narrowing down further.  The first bad commit could be any of:"
+                )?;
+                for suspect in &report.credible_set {
+                    writeln!(
+                        out,
+                        "{} with likelihood {}",
+                        suspect.commit, suspect.likelihood
+                    )?;
+                }
+            }
+        }
+        OutputFormat::Json => {
+            serde_json::to_writer(&mut *out, &JsonOutput::Summary(&report))?;
+            writeln!(out)?;
+        }
+    }
+    Ok(report)
 }
 
-fn main() -> Result<(), Box<dyn Error>> {
-    let start = Instant::now();
-    let matches = App::new("git-bisect")
+/// Adds `results` to `state`, replays every result in `state` against a new searcher, and prints
+/// the next commit to test along with the most likely commit so far. Instead of testing commits
+/// itself, this lets an external tool drive the bisection one result at a time.
+fn run_next(
+    graph: CompressedDag,
+    segments: &[GitSegment],
+    state: &mut BisectState,
+    results: &[TestedCommit],
+    options: &BisectOptions,
+    out: &mut dyn Write,
+) -> Result<NextOutput, Box<dyn Error>> {
+    let nodes = segments
+        .iter()
+        .enumerate()
+        .flat_map(|(segment, git_segment)| {
+            git_segment
+                .commits
+                .iter()
+                .enumerate()
+                .map(move |(index, commit)| {
+                    (commit.as_str(), CompressedDagNodeRef { segment, index })
+                })
+        })
+        .collect::<HashMap<_, _>>();
+    let node = |commit: &str| {
+        nodes
+            .get(commit)
+            .copied()
+            .ok_or_else(|| format!("commit {} is not in the searched range", commit))
+    };
+    // Check the new results before recording any of them.
+    for result in results {
+        node(&result.commit)?;
+    }
+    state.tested_commits.extend_from_slice(results);
+
+    let mut searcher = AutoCompressedDagSearcher::new(Rc::new(graph));
+    for tested in &state.tested_commits {
+        let node = node(&tested.commit)?;
+        match tested.verdict {
+            Verdict::Good | Verdict::Bad => searcher.report(node, tested.verdict == Verdict::Bad),
+            Verdict::Skip => searcher.add_skip(node),
+        }
+    }
+    let iterations = state.tested_commits.len();
+    let best = searcher.best_node();
+    let next = searcher.next_node();
+    let done = searcher.likelihood(best) > options.min_likelihood || searcher.is_skipped(next);
+    let output = NextOutput {
+        next_commit: if done {
+            None
+        } else {
+            Some(segments[next.segment].commits[next.index].clone())
+        },
+        best_commit: segments[best.segment].commits[best.index].clone(),
+        likelihood: searcher.likelihood(best),
+        flakiness: searcher.flakiness(),
+        iterations,
+    };
+    match options.format {
+        OutputFormat::Text => {
+            writeln!(
+                out,
+                "{}",
+                best_commit_message(&searcher, segments, iterations)
+            )?;
+            match &output.next_commit {
+                Some(commit) => writeln!(out, "Next commit to test is {}", commit)?,
+                None => writeln!(out, "No more commits need to be tested.")?,
+            }
+        }
+        OutputFormat::Json => {
+            serde_json::to_writer(&mut *out, &JsonOutput::Next(&output))?;
+            writeln!(out)?;
+        }
+    }
+    Ok(output)
+}
+
+/// Runs the `next` subcommand.
+fn next_command(
+    matches: &ArgMatches,
+    next_matches: &ArgMatches,
+    options: &BisectOptions,
+) -> Result<(), Box<dyn Error>> {
+    let state_path = Path::new(next_matches.value_of("state-file").unwrap());
+    let mut state = load_state(state_path)?;
+    match (
+        next_matches.value_of("start-commit"),
+        next_matches.value_of("end-commit"),
+    ) {
+        (Some(start_commit), Some(end_commit)) => {
+            if state.start_commit.is_empty() {
+                state.start_commit = start_commit.to_string();
+                state.end_commit = end_commit.to_string();
+            } else if state.start_commit != start_commit || state.end_commit != end_commit {
+                return Err(format!(
+                    "{}..{} doesn't match the range {}..{} in the state file",
+                    start_commit, end_commit, state.start_commit, state.end_commit
+                )
+                .into());
+            }
+        }
+        _ if state.start_commit.is_empty() => {
+            return Err("the start and end commits are required to create a state file".into());
+        }
+        _ => (),
+    }
+    let results = next_matches
+        .values_of("result")
+        .into_iter()
+        .flatten()
+        .map(parse_result)
+        .collect::<Result<Vec<_>, _>>()?;
+    let dir = matches.value_of("dir").unwrap();
+    let source = open_source(matches, dir)?;
+    let (graph, git_segments) = source.load_history(
+        &state.start_commit,
+        &state.end_commit,
+        &mut Metrics::default(),
+    )?;
+    run_next(
+        graph,
+        &git_segments,
+        &mut state,
+        &results,
+        options,
+        &mut io::stdout(),
+    )?;
+    save_state(state_path, &state)
+}
+
+/// A commit graph source and the commit tester to use with it.
+type Backend<'a> = (Box<dyn CommitGraphSource + 'a>, Box<dyn CommitTester + 'a>);
+
+/// Creates the commit graph source and commit tester selected by the command line arguments. Tests
+/// are run in `work_dir`, which may be a separate worktree of the repository in `dir`.
+fn open_backend<'a>(
+    matches: &'a ArgMatches,
+    dir: &'a str,
+    work_dir: &'a str,
+    test_cmd: &'a str,
+    exit_codes: ExitCodes,
+) -> Result<Backend<'a>, Box<dyn Error>> {
+    let source = open_source(matches, dir)?;
+    #[cfg(feature = "git2")]
+    {
+        if matches.value_of("backend") == Some("git2") {
+            return Ok((
+                source,
+                Box::new(git2_backend::Git2CommitTester::open(
+                    work_dir, test_cmd, exit_codes,
+                )?),
+            ));
+        }
+    }
+    Ok((
+        source,
+        Box::new(ShellCommitTester {
+            dir: work_dir,
+            test_cmd,
+            exit_codes,
+        }),
+    ))
+}
+
+/// Creates the commit graph source selected by the command line arguments.
+fn open_source<'a>(
+    matches: &'a ArgMatches,
+    dir: &'a str,
+) -> Result<Box<dyn CommitGraphSource + 'a>, Box<dyn Error>> {
+    let path = matches.value_of("path");
+    let first_parent = matches.is_present("first-parent");
+    #[cfg(feature = "git2")]
+    {
+        if matches.value_of("backend") == Some("git2") {
+            if path.is_some() {
+                return Err("--path is not supported with --backend git2".into());
+            }
+            return Ok(Box::new(
+                git2_backend::Git2GraphSource::open(dir)?.with_first_parent(first_parent),
+            ));
+        }
+    }
+    Ok(Box::new(GitCommandSource {
+        dir,
+        path,
+        first_parent,
+    }))
+}
+
+/// Returns the command line parser.
+fn app<'a, 'b>() -> App<'a, 'b> {
+    let app = App::new("git-bisect")
         .version("1.0")
         .author("Adam Crume <acrume@google.com>")
         .about("Robust git bisect which works in the face of noise.")
+        .setting(AppSettings::SubcommandsNegateReqs)
         .arg(
             Arg::with_name("dir")
                 .long("dir")
@@ -177,6 +900,75 @@ fn main() -> Result<(), Box<dyn Error>> {
                 .help("Minimum likelihood required to stop iterating.")
                 .default_value("0.99"),
         )
+        .arg(
+            Arg::with_name("format")
+                .long("format")
+                .help("Output format. json prints one JSON object per line")
+                .possible_values(&["text", "json"])
+                .default_value("text"),
+        )
+        .arg(
+            Arg::with_name("repeats")
+                .long("repeats")
+                .help(
+                    "Number of times to run the test command at each chosen commit. Every run is \
+                     a separate vote, so disagreeing runs raise the estimated flakiness",
+                )
+                .default_value("1"),
+        )
+        .arg(
+            Arg::with_name("path")
+                .long("path")
+                .help(
+                    "Only search commits which touch this path or glob (a git pathspec), e.g. a \
+                     subdirectory of a monorepo. Commits which don't touch it are never tested",
+                )
+                .takes_value(true),
+        )
+        .arg(Arg::with_name("first-parent").long("first-parent").help(
+            "Only follow the first parent of merge commits, so the search stays on the \
+                     mainline and each merge is tested as a whole",
+        ))
+        .arg(
+            Arg::with_name("report")
+                .long("report")
+                .help("Path to write a JSON summary of the bisection to")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("good-exit")
+                .long("good-exit")
+                .help(
+                    "Comma-separated exit codes of the test command which mean the commit is good",
+                )
+                .default_value("0")
+                .require_delimiter(true)
+                .multiple(true),
+        )
+        .arg(
+            Arg::with_name("bad-exit")
+                .long("bad-exit")
+                .help(
+                    "Comma-separated exit codes of the test command which mean the commit is bad. \
+                     If given, unlisted exit codes mean the commit cannot be tested. By default, \
+                     every exit code which isn't good or skip is bad",
+                )
+                .takes_value(true)
+                .require_delimiter(true)
+                .multiple(true),
+        )
+        .arg(
+            Arg::with_name("skip-exit")
+                .long("skip-exit")
+                .alias("skip-exit-code")
+                .help(
+                    "Comma-separated exit codes of the test command which mean the commit cannot \
+                     be tested",
+                )
+                .default_value("125")
+                .require_delimiter(true)
+                .multiple(true),
+        )
         .arg(
             Arg::with_name("verbose")
                 .short("v")
@@ -184,6 +976,10 @@ fn main() -> Result<(), Box<dyn Error>> {
                 .help("More verbose output")
                 .multiple(true),
         )
+        .arg(Arg::with_name("quiet").short("q").long("quiet").help(
+            "Only print the final result. Progress is still logged at info level, so it \
+                     is shown with --verbose",
+        ))
         .arg(
             Arg::with_name("start-commit")
                 .help("Good/start commit")
@@ -199,145 +995,888 @@ fn main() -> Result<(), Box<dyn Error>> {
                 .help("Command to run which succeeds for good commits and fails for bad commits")
                 .required(true),
         )
-        .get_matches();
+        .arg(
+            Arg::with_name("worktree")
+                .long("worktree")
+                .help(
+                    "Directory of a separate worktree (e.g. created with `git worktree add \
+                     --detach`) to check out and test commits in, leaving --dir untouched",
+                )
+                .takes_value(true),
+        )
+        .subcommand(
+            SubCommand::with_name("next")
+                .about(
+                    "Records test results from an external tool, prints the next commit to test, \
+                     and exits without testing anything",
+                )
+                .arg(
+                    Arg::with_name("state-file")
+                        .long("state-file")
+                        .help("JSON file holding the results so far. Created if it doesn't exist")
+                        .takes_value(true)
+                        .required(true),
+                )
+                .arg(
+                    Arg::with_name("result")
+                        .long("result")
+                        .help("Test result to record, as <commit>=<good|bad|skip>")
+                        .takes_value(true)
+                        .number_of_values(1)
+                        .multiple(true),
+                )
+                .arg(
+                    Arg::with_name("start-commit")
+                        .help("Good/start commit. Required if the state file doesn't exist"),
+                )
+                .arg(
+                    Arg::with_name("end-commit")
+                        .help("Bad/end commit. Required if the state file doesn't exist")
+                        .requires("start-commit"),
+                ),
+        );
+    #[cfg(feature = "git2")]
+    let app = app.arg(
+        Arg::with_name("backend")
+            .long("backend")
+            .help(
+                "Whether to run the git command or use libgit2 to read commits and check them out",
+            )
+            .possible_values(&["git", "git2"])
+            .default_value("git"),
+    );
+    app
+}
+
+fn main() -> Result<(), Box<dyn Error>> {
+    let start = Instant::now();
+    let matches = app().get_matches();
     let level_filter = match matches.occurrences_of("verbose") {
         0 => LevelFilter::Warn,
         1 => LevelFilter::Info,
         2 => LevelFilter::Debug,
         _ => LevelFilter::Trace,
     };
-    TermLogger::init(level_filter, Config::default(), TerminalMode::Mixed).unwrap();
+    // Failing to set up logging (e.g. because a logger is already installed when embedded) isn't
+    // worth aborting the bisection over.
+    if let Err(e) = TermLogger::init(level_filter, Config::default(), TerminalMode::Mixed) {
+        eprintln!("Unable to initialize logging: {}", e);
+    }
     let dir = matches.value_of("dir").unwrap();
     let min_likelihood = matches
         .value_of("min-likelihood")
         .unwrap()
         .parse::<f64>()
         .unwrap();
+    let exit_codes = parse_exit_codes(&matches)?;
+    let repeats = matches
+        .value_of("repeats")
+        .unwrap()
+        .parse::<usize>()
+        .unwrap();
+    if repeats == 0 {
+        return Err("--repeats must be at least 1".into());
+    }
+    let options = BisectOptions {
+        min_likelihood,
+        repeats,
+        format: match matches.value_of("format").unwrap() {
+            "json" => OutputFormat::Json,
+            _ => OutputFormat::Text,
+        },
+        quiet: matches.is_present("quiet"),
+    };
+    if let Some(next_matches) = matches.subcommand_matches("next") {
+        return next_command(&matches, next_matches, &options);
+    }
     let start_commit = matches.value_of("start-commit").unwrap();
     let end_commit = matches.value_of("end-commit").unwrap();
     let test_cmd = matches.value_of("test-cmd").unwrap();
-    let commit_log = run("git", |command| {
-        // TODO: Do we need --ancestry-path?
-        command
-            .current_dir(dir)
-            .arg("log")
-            .arg(format!("{}..{}", start_commit, end_commit))
-            .arg("--format=format:%H %P")
-    })
-    .unwrap();
-    let mut parents = HashMap::<String, Vec<String>>::new();
-    let mut children = HashMap::<String, Vec<String>>::new();
-    for line in commit_log.lines() {
-        let mut hashes = line.split(' ').map(|s| s.to_string()).collect::<Vec<_>>();
-        let commit = hashes.swap_remove(0);
-        for parent in hashes.into_iter() {
-            children
-                .entry(parent.clone())
-                .or_insert_with(Vec::new)
-                .push(commit.clone());
-            parents
-                .entry(commit.clone())
-                .or_insert_with(Vec::new)
-                .push(parent);
-        }
-    }
-
-    let mut unify = [].iter().cloned().collect::<QuickFindUf<StringUnion>>();
-    let mut uf_keys = HashMap::<String, usize>::new();
-    for (key, value) in &parents {
-        let uf_key1: usize = *uf_keys
-            .entry(key.clone())
-            .or_insert_with(|| unify.insert(StringUnion(key.clone())));
-        if value.len() == 1 {
-            if let Some(child_hashes) = children.get(&value[0]) {
-                if child_hashes.len() == 1 {
-                    let uf_key2: usize = *uf_keys
-                        .entry(value[0].clone())
-                        .or_insert_with(|| unify.insert(StringUnion(value[0].clone())));
-                    unify.union(uf_key1, uf_key2);
-                }
+    let work_dir = matches.value_of("worktree").unwrap_or(dir);
+    let (source, mut tester) = open_backend(&matches, dir, work_dir, test_cmd, exit_codes)?;
+    let mut metrics = Metrics::default();
+    let (graph, git_segments) = source.load_history(start_commit, end_commit, &mut metrics)?;
+
+    info!("Running bisection");
+    let report = run_bisect(
+        graph,
+        &git_segments,
+        tester.as_mut(),
+        &options,
+        &mut metrics,
+        &mut io::stdout(),
+    )?;
+    if let Some(path) = matches.value_of("report") {
+        serde_json::to_writer_pretty(File::create(path)?, &report)?;
+    }
+    info!("Metrics:");
+    for line in metrics.summary() {
+        info!("{}", line);
+    }
+    info!("Elapsed time: {} seconds", start.elapsed().as_secs_f64());
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use robust_binary_search::CompressedDagSegment;
+
+    /// Simulates a linear history where every commit from `first_bad` on is bad, except that the
+    /// commits in `skipped` cannot be tested.
+    struct FakeCommitTester {
+        commits: Vec<String>,
+        first_bad: usize,
+        skipped: Vec<usize>,
+        checked_out: usize,
+        /// Number of times `test` has been called.
+        tests_run: usize,
+    }
+
+    impl CommitTester for FakeCommitTester {
+        fn checkout(&mut self, commit: &str) -> Result<(), Box<dyn Error>> {
+            self.checked_out = self.commits.iter().position(|c| c == commit).unwrap();
+            Ok(())
+        }
+
+        fn test(&mut self) -> Verdict {
+            self.tests_run += 1;
+            if self.skipped.contains(&self.checked_out) {
+                Verdict::Skip
+            } else if self.checked_out >= self.first_bad {
+                Verdict::Bad
+            } else {
+                Verdict::Good
             }
         }
     }
 
-    let mut segments = HashMap::<usize, GitSegmentUf>::new();
-    for (key, value) in &parents {
-        let uf_key: usize = *uf_keys.get(key).unwrap();
-        let segment: usize = unify.find(uf_key);
-        let git_segment = segments
-            .entry(segment)
-            .or_insert_with(GitSegmentUf::default);
-        git_segment.commits.push(key.clone());
-        for parent in value {
-            if let Some(parent_uf_key) = uf_keys.get(parent) {
-                let parent_segment: usize = unify.find(*parent_uf_key);
-                if parent_segment != segment {
-                    git_segment.parents.push(parent_segment);
-                }
-            }
+    fn linear_history(len: usize) -> (CompressedDag, Vec<GitSegment>, Vec<String>) {
+        let commits = (0..len).map(|i| format!("c{}", i)).collect::<Vec<_>>();
+        let mut graph = CompressedDag::new();
+        graph.add_node(CompressedDagSegment::new(len), vec![]);
+        let segments = vec![GitSegment {
+            commits: commits.clone(),
+        }];
+        (graph, segments, commits)
+    }
+
+    #[test]
+    fn report_contents() {
+        let (graph, segments, commits) = linear_history(20);
+        let mut tester = FakeCommitTester {
+            commits,
+            first_bad: 13,
+            skipped: vec![],
+            checked_out: 0,
+            tests_run: 0,
+        };
+        let mut metrics = Metrics::default();
+        let report = run_bisect(
+            graph,
+            &segments,
+            &mut tester,
+            &BisectOptions {
+                min_likelihood: 0.9,
+                ..BisectOptions::default()
+            },
+            &mut metrics,
+            &mut io::sink(),
+        )
+        .unwrap();
+        let json = serde_json::to_value(&report).unwrap();
+        let object = json.as_object().unwrap();
+        for key in &[
+            "best_commit",
+            "likelihood",
+            "flakiness",
+            "flakiness_before",
+            "flakiness_after",
+            "iterations",
+            "ambiguous",
+            "tested_commits",
+            "credible_set",
+        ] {
+            assert!(object.contains_key(*key), "missing key {}", key);
+        }
+        assert_eq!(json["best_commit"], "c13");
+        assert_eq!(
+            json["tested_commits"].as_array().unwrap().len() as u64,
+            json["iterations"].as_u64().unwrap()
+        );
+        assert_eq!(json["credible_set"][0]["commit"], "c13");
+        assert_eq!(metrics.iterations, report.iterations);
+        for phase in &["checkout", "test", "report"] {
+            assert!(metrics.durations.contains_key(*phase), "missing {}", phase);
         }
     }
 
-    for value in segments.values_mut() {
-        let commit_set = value.commits.iter().cloned().collect::<HashSet<String>>();
-        let first_commits = value
-            .commits
+    #[test]
+    fn skipped_commits() {
+        let (graph, segments, commits) = linear_history(20);
+        let mut tester = FakeCommitTester {
+            commits,
+            first_bad: 13,
+            skipped: vec![9, 10, 11],
+            checked_out: 0,
+            tests_run: 0,
+        };
+        let report = run_bisect(
+            graph,
+            &segments,
+            &mut tester,
+            &BisectOptions {
+                min_likelihood: 0.9,
+                ..BisectOptions::default()
+            },
+            &mut Metrics::default(),
+            &mut io::sink(),
+        )
+        .unwrap();
+        assert_eq!(report.best_commit, "c13");
+        assert!(!report.ambiguous);
+        assert!(report
+            .tested_commits
             .iter()
-            .filter(|commit: &&String| {
-                let commit_parents = parents.get(*commit).unwrap();
-                commit_parents.len() != 1 || !commit_set.contains(&commit_parents[0])
-            })
-            .cloned()
-            .collect::<Vec<String>>();
-        assert_eq!(first_commits.len(), 1);
-        let mut commit = first_commits[0].clone();
-        let mut sorted_commits = vec![commit.clone()];
-        while let Some(child_commits) = children.get(&commit) {
-            if child_commits.len() != 1 {
-                break;
-            }
-            let child_commit = child_commits[0].clone();
-            if !commit_set.contains(&child_commit) {
-                break;
-            }
-            sorted_commits.push(child_commit);
-            commit = child_commits[0].clone();
+            .any(|tested| tested.verdict == Verdict::Skip));
+        // Skipped commits are tested at most once.
+        for skipped in &["c9", "c10", "c11"] {
+            assert!(
+                report
+                    .tested_commits
+                    .iter()
+                    .filter(|tested| tested.commit == *skipped)
+                    .count()
+                    <= 1
+            );
         }
+    }
+
+    #[test]
+    fn skipped_commits_ambiguous() {
+        let (graph, segments, commits) = linear_history(20);
+        let mut tester = FakeCommitTester {
+            commits,
+            first_bad: 13,
+            skipped: vec![12],
+            checked_out: 0,
+            tests_run: 0,
+        };
+        let report = run_bisect(
+            graph,
+            &segments,
+            &mut tester,
+            &BisectOptions {
+                min_likelihood: 0.9,
+                ..BisectOptions::default()
+            },
+            &mut Metrics::default(),
+            &mut io::sink(),
+        )
+        .unwrap();
+        assert!(report.ambiguous);
+        let mut suspects = report
+            .credible_set
+            .iter()
+            .map(|suspect| suspect.commit.as_str())
+            .collect::<Vec<_>>();
+        suspects.sort_unstable();
+        assert_eq!(suspects, vec!["c12", "c13"]);
+    }
+
+    #[test]
+    fn all_commits_skipped() {
+        let (graph, segments, commits) = linear_history(5);
+        let mut tester = FakeCommitTester {
+            commits,
+            first_bad: 3,
+            skipped: (0..5).collect(),
+            checked_out: 0,
+            tests_run: 0,
+        };
+        let report = run_bisect(
+            graph,
+            &segments,
+            &mut tester,
+            &BisectOptions {
+                min_likelihood: 0.9,
+                ..BisectOptions::default()
+            },
+            &mut Metrics::default(),
+            &mut io::sink(),
+        )
+        .unwrap();
+        assert!(report.ambiguous);
+        assert!(report.iterations <= 5);
+        assert!(report
+            .tested_commits
+            .iter()
+            .all(|tested| tested.verdict == Verdict::Skip));
+    }
+
+    /// Runs git with a fixed identity in `dir`.
+    fn git(dir: &str, args: &[&str]) -> String {
+        run("git", |cmd| {
+            cmd.current_dir(dir)
+                .args(["-c", "user.name=Test", "-c", "user.email=test@example.com"])
+                .args(["-c", "commit.gpgsign=false"])
+                .args(args)
+        })
+        .unwrap()
+    }
+
+    #[test]
+    fn classify_exit_codes() {
+        let defaults = ExitCodes::default();
+        assert_eq!(defaults.classify(Some(0)), Verdict::Good);
+        assert_eq!(defaults.classify(Some(1)), Verdict::Bad);
+        assert_eq!(defaults.classify(Some(125)), Verdict::Skip);
+        assert_eq!(defaults.classify(None), Verdict::Bad);
+
+        let inverted = ExitCodes {
+            good: vec![1],
+            bad: Some(vec![0]),
+            skip: vec![],
+        };
+        assert_eq!(inverted.classify(Some(0)), Verdict::Bad);
+        assert_eq!(inverted.classify(Some(1)), Verdict::Good);
+        assert_eq!(inverted.classify(Some(2)), Verdict::Skip);
+        assert_eq!(inverted.classify(None), Verdict::Skip);
+    }
+
+    #[test]
+    fn exit_code_args() {
+        let parse = |args: &[&str]| {
+            let mut all_args = vec!["robust-git-bisect"];
+            all_args.extend_from_slice(args);
+            all_args.extend_from_slice(&["a", "b", "true"]);
+            parse_exit_codes(&app().get_matches_from(all_args))
+        };
+        let exit_codes = parse(&[]).unwrap();
+        assert_eq!(exit_codes.good, vec![0]);
+        assert_eq!(exit_codes.bad, None);
+        assert_eq!(exit_codes.skip, vec![125]);
+
+        let exit_codes =
+            parse(&["--bad-exit", "1", "--skip-exit", "2,3", "--skip-exit=4"]).unwrap();
+        assert_eq!(exit_codes.good, vec![0]);
+        assert_eq!(exit_codes.bad, Some(vec![1]));
+        assert_eq!(exit_codes.skip, vec![2, 3, 4]);
+
+        // The old name still works.
+        let exit_codes = parse(&["--skip-exit-code", "77"]).unwrap();
+        assert_eq!(exit_codes.skip, vec![77]);
+
         assert_eq!(
-            sorted_commits.iter().cloned().collect::<HashSet<_>>(),
-            commit_set
+            parse(&["--bad-exit", "x"]).unwrap_err().to_string(),
+            "invalid exit code \"x\" for --bad-exit"
+        );
+        assert_eq!(
+            parse(&["--good-exit", "0,1", "--bad-exit", "1"])
+                .unwrap_err()
+                .to_string(),
+            "exit code 1 is mapped to more than one verdict"
+        );
+        assert_eq!(
+            parse(&["--skip-exit", "3", "--bad-exit", "3"])
+                .unwrap_err()
+                .to_string(),
+            "exit code 3 is mapped to more than one verdict"
         );
-        value.commits = sorted_commits;
     }
 
-    let sorted_segments = sort_segments(&segments);
-    let segment_index_by_id = sorted_segments
-        .iter()
-        .enumerate()
-        .map(|(k, v)| (*v, k))
-        .collect::<HashMap<usize, usize>>();
-    let git_segments = sorted_segments
-        .iter()
-        .map(|segment_id| {
-            let segment = segments.get(segment_id).unwrap();
-            let parents = segment
-                .parents
-                .iter()
-                .map(|id| segment_index_by_id.get(id).unwrap())
-                .copied()
-                .collect::<Vec<usize>>();
-            GitSegment {
-                parents,
-                commits: segment.commits.clone(),
+    #[test]
+    fn run_test_cmd_exit_codes() {
+        let dir = std::env::temp_dir();
+        let dir_str = dir.to_str().unwrap();
+        let exit_codes = ExitCodes {
+            good: vec![0, 10],
+            bad: Some(vec![1, 11]),
+            skip: vec![2],
+        };
+        let verdict = |test_cmd: &str| run_test_cmd(dir_str, test_cmd, &exit_codes);
+        assert_eq!(verdict("exit 0"), Verdict::Good);
+        assert_eq!(verdict("exit 10"), Verdict::Good);
+        assert_eq!(verdict("exit 1"), Verdict::Bad);
+        assert_eq!(verdict("exit 11"), Verdict::Bad);
+        assert_eq!(verdict("exit 2"), Verdict::Skip);
+        // Unmapped.
+        assert_eq!(verdict("exit 3"), Verdict::Skip);
+        assert_eq!(verdict("kill -9 $$"), Verdict::Skip);
+        assert_eq!(
+            run_test_cmd(dir_str, "exit 3", &ExitCodes::default()),
+            Verdict::Bad
+        );
+    }
+
+    #[test]
+    fn shell_tester_skip_exit_code() {
+        let dir = std::env::temp_dir().join(format!(
+            "robust-git-bisect-skip-test-{}",
+            std::process::id()
+        ));
+        std::fs::create_dir_all(&dir).unwrap();
+        let dir_str = dir.to_str().unwrap();
+        git(dir_str, &["init", "-q"]);
+        let mut hashes = Vec::new();
+        for i in 0..20 {
+            std::fs::write(dir.join("n"), format!("{}\n", i)).unwrap();
+            git(dir_str, &["add", "n"]);
+            git(dir_str, &["commit", "-q", "-m", &format!("commit {}", i)]);
+            hashes.push(git(dir_str, &["rev-parse", "HEAD"]).trim().to_string());
+        }
+        let mut metrics = Metrics::default();
+        let (graph, segments) = GitCommandSource {
+            dir: dir_str,
+            path: None,
+            first_parent: false,
+        }
+        .load_history(&hashes[0], &hashes[19], &mut metrics)
+        .unwrap();
+        let mut tester = ShellCommitTester {
+            dir: dir_str,
+            test_cmd:
+                "n=$(cat n); if [ $n -eq 10 ] || [ $n -eq 12 ]; then exit 125; fi; [ $n -lt 14 ]",
+            exit_codes: ExitCodes::default(),
+        };
+        let report = run_bisect(
+            graph,
+            &segments,
+            &mut tester,
+            &BisectOptions {
+                min_likelihood: 0.9,
+                ..BisectOptions::default()
+            },
+            &mut metrics,
+            &mut io::sink(),
+        )
+        .unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(report.best_commit, hashes[14]);
+        assert!(!report.ambiguous);
+        for tested in &report.tested_commits {
+            let n = hashes.iter().position(|h| *h == tested.commit).unwrap();
+            let expected = match n {
+                10 | 12 => Verdict::Skip,
+                n if n < 14 => Verdict::Good,
+                _ => Verdict::Bad,
+            };
+            assert_eq!(tested.verdict, expected, "commit {}", n);
+        }
+    }
+
+    #[test]
+    fn path_filter() {
+        let dir = std::env::temp_dir().join(format!(
+            "robust-git-bisect-path-test-{}",
+            std::process::id()
+        ));
+        std::fs::create_dir_all(dir.join("sub")).unwrap();
+        let dir_str = dir.to_str().unwrap();
+        git(dir_str, &["init", "-q"]);
+        // Even commits touch sub/, and odd commits touch other.
+        let mut hashes = Vec::new();
+        for i in 0..30 {
+            let file = if i % 2 == 0 { "sub/n" } else { "other" };
+            std::fs::write(dir.join(file), format!("{}\n", i)).unwrap();
+            git(dir_str, &["add", file]);
+            git(dir_str, &["commit", "-q", "-m", &format!("commit {}", i)]);
+            hashes.push(git(dir_str, &["rev-parse", "HEAD"]).trim().to_string());
+        }
+        let mut metrics = Metrics::default();
+        let (graph, segments) = GitCommandSource {
+            dir: dir_str,
+            path: Some("sub"),
+            first_parent: false,
+        }
+        .load_history(&hashes[0], &hashes[29], &mut metrics)
+        .unwrap();
+        // The rewritten parents keep the remaining commits in a single chain.
+        assert_eq!(graph.nodes().len(), 1);
+        let expected = (2..30)
+            .step_by(2)
+            .map(|i| hashes[i].clone())
+            .collect::<Vec<_>>();
+        assert_eq!(segments[0].commits, expected);
+        let mut tester = ShellCommitTester {
+            dir: dir_str,
+            test_cmd: "[ $(cat sub/n) -lt 15 ]",
+            exit_codes: ExitCodes::default(),
+        };
+        let report = run_bisect(
+            graph,
+            &segments,
+            &mut tester,
+            &BisectOptions {
+                min_likelihood: 0.9,
+                ..BisectOptions::default()
+            },
+            &mut metrics,
+            &mut io::sink(),
+        )
+        .unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(report.best_commit, hashes[16]);
+        for tested in &report.tested_commits {
+            let n = hashes.iter().position(|h| *h == tested.commit).unwrap();
+            assert_eq!(n % 2, 0, "commit {} doesn't touch the path", n);
+        }
+    }
+
+    #[test]
+    fn first_parent() {
+        let dir = std::env::temp_dir().join(format!(
+            "robust-git-bisect-first-parent-test-{}",
+            std::process::id()
+        ));
+        std::fs::create_dir_all(&dir).unwrap();
+        let dir_str = dir.to_str().unwrap();
+        git(dir_str, &["init", "-q"]);
+        let commit = |name: &str| {
+            std::fs::write(dir.join(name), "").unwrap();
+            git(dir_str, &["add", name]);
+            git(dir_str, &["commit", "-q", "-m", name]);
+            git(dir_str, &["rev-parse", "HEAD"]).trim().to_string()
+        };
+        let start = commit("start");
+        let mainline = git(dir_str, &["rev-parse", "--abbrev-ref", "HEAD"])
+            .trim()
+            .to_string();
+        let mut expected = vec![commit("a"), commit("b")];
+        git(dir_str, &["checkout", "-q", "-b", "side"]);
+        commit("side1");
+        commit("side2");
+        git(dir_str, &["checkout", "-q", &mainline]);
+        expected.push(commit("c"));
+        git(dir_str, &["merge", "-q", "--no-ff", "-m", "merge", "side"]);
+        expected.push(git(dir_str, &["rev-parse", "HEAD"]).trim().to_string());
+        expected.push(commit("d"));
+        let end = expected.last().unwrap().clone();
+
+        let load = |first_parent| {
+            GitCommandSource {
+                dir: dir_str,
+                path: None,
+                first_parent,
             }
-        })
-        .collect::<Vec<_>>();
+            .load_history(&start, &end, &mut Metrics::default())
+            .unwrap()
+        };
+        let (graph, _) = load(false);
+        let all_commits = graph.total_len();
+        let (graph, segments) = load(true);
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(all_commits, 7);
+        assert_eq!(graph.segment_count(), 1);
+        assert_eq!(segments[0].commits, expected);
+    }
 
-    info!("Running bisection");
-    let metrics = run_bisect(dir, &git_segments, test_cmd, min_likelihood);
-    for (k, v) in metrics {
-        info!("{}: {}", k, v.as_secs_f64());
+    #[test]
+    fn parse_results() {
+        let result = parse_result("abc=bad").unwrap();
+        assert_eq!(result.commit, "abc");
+        assert_eq!(result.verdict, Verdict::Bad);
+        assert_eq!(parse_result("abc=good").unwrap().verdict, Verdict::Good);
+        assert_eq!(parse_result("abc=skip").unwrap().verdict, Verdict::Skip);
+        assert!(parse_result("abc").is_err());
+        assert!(parse_result("abc=flaky").is_err());
+    }
+
+    #[test]
+    fn next_args() {
+        let matches = app().get_matches_from(
+            [
+                "robust-git-bisect",
+                "next",
+                "--state-file",
+                "state.json",
+                "--result",
+                "a=good",
+                "--result",
+                "b=bad",
+            ]
+            .iter(),
+        );
+        let next_matches = matches.subcommand_matches("next").unwrap();
+        assert_eq!(next_matches.value_of("state-file"), Some("state.json"));
+        assert_eq!(
+            next_matches
+                .values_of("result")
+                .unwrap()
+                .collect::<Vec<_>>(),
+            vec!["a=good", "b=bad"]
+        );
+        assert_eq!(next_matches.value_of("start-commit"), None);
+    }
+
+    #[test]
+    fn next_cycles() {
+        let dir = std::env::temp_dir().join(format!(
+            "robust-git-bisect-next-test-{}",
+            std::process::id()
+        ));
+        std::fs::create_dir_all(&dir).unwrap();
+        let dir_str = dir.to_str().unwrap();
+        git(dir_str, &["init", "-q"]);
+        let mut hashes = Vec::new();
+        for i in 0..20 {
+            std::fs::write(dir.join("n"), format!("{}\n", i)).unwrap();
+            git(dir_str, &["add", "n"]);
+            git(dir_str, &["commit", "-q", "-m", &format!("commit {}", i)]);
+            hashes.push(git(dir_str, &["rev-parse", "HEAD"]).trim().to_string());
+        }
+        let state_path = dir.join("state.json");
+        let options = BisectOptions {
+            min_likelihood: 0.9,
+            ..BisectOptions::default()
+        };
+        let source = GitCommandSource {
+            dir: dir_str,
+            path: None,
+            first_parent: false,
+        };
+        // Each cycle is a separate run: load the state, record the previous result, and save.
+        let mut results = vec![];
+        let mut output = None;
+        for _ in 0..20 {
+            let mut state = load_state(&state_path).unwrap();
+            if state.start_commit.is_empty() {
+                state.start_commit = hashes[0].clone();
+                state.end_commit = hashes[19].clone();
+            }
+            let (graph, segments) = source
+                .load_history(
+                    &state.start_commit,
+                    &state.end_commit,
+                    &mut Metrics::default(),
+                )
+                .unwrap();
+            let mut out = Vec::new();
+            let next =
+                run_next(graph, &segments, &mut state, &results, &options, &mut out).unwrap();
+            save_state(&state_path, &state).unwrap();
+            let out = String::from_utf8(out).unwrap();
+            assert!(out.contains(&next.best_commit), "output: {}", out);
+            let commit = match &next.next_commit {
+                Some(commit) => commit.clone(),
+                None => {
+                    output = Some(next);
+                    break;
+                }
+            };
+            assert!(out.contains(&format!("Next commit to test is {}", commit)));
+            let n = hashes.iter().position(|h| *h == commit).unwrap();
+            let verdict = if n >= 14 { "bad" } else { "good" };
+            results = vec![parse_result(&format!("{}={}", commit, verdict)).unwrap()];
+        }
+        let state = load_state(&state_path).unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+        let output = output.expect("search didn't finish");
+        assert_eq!(output.best_commit, hashes[14]);
+        assert!(output.likelihood > 0.9);
+        assert_eq!(output.iterations, state.tested_commits.len());
+        assert!(state.tested_commits.len() > 1);
+    }
+
+    #[test]
+    fn next_unknown_commit() {
+        let (graph, segments, _) = linear_history(5);
+        let mut state = BisectState::default();
+        let results = vec![
+            parse_result("c2=bad").unwrap(),
+            parse_result("x=bad").unwrap(),
+        ];
+        let err = run_next(
+            graph,
+            &segments,
+            &mut state,
+            &results,
+            &BisectOptions::default(),
+            &mut io::sink(),
+        )
+        .unwrap_err();
+        assert_eq!(err.to_string(), "commit x is not in the searched range");
+        assert!(state.tested_commits.is_empty());
+    }
+
+    #[test]
+    fn repeats() {
+        let (graph, segments, commits) = linear_history(20);
+        let mut tester = FakeCommitTester {
+            commits,
+            first_bad: 13,
+            skipped: vec![],
+            checked_out: 0,
+            tests_run: 0,
+        };
+        let report = run_bisect(
+            graph,
+            &segments,
+            &mut tester,
+            &BisectOptions {
+                min_likelihood: 0.9,
+                repeats: 3,
+                ..BisectOptions::default()
+            },
+            &mut Metrics::default(),
+            &mut io::sink(),
+        )
+        .unwrap();
+        assert_eq!(report.best_commit, "c13");
+        assert_eq!(tester.tests_run, 3 * report.iterations);
+        assert_eq!(report.tested_commits.len(), 3 * report.iterations);
+        for chunk in report.tested_commits.chunks(3) {
+            assert!(chunk.iter().all(|tested| tested.commit == chunk[0].commit));
+        }
+    }
+
+    #[test]
+    fn repeats_stop_at_skip() {
+        let (graph, segments, commits) = linear_history(20);
+        let mut tester = FakeCommitTester {
+            commits,
+            first_bad: 13,
+            skipped: vec![9],
+            checked_out: 0,
+            tests_run: 0,
+        };
+        let report = run_bisect(
+            graph,
+            &segments,
+            &mut tester,
+            &BisectOptions {
+                min_likelihood: 0.9,
+                repeats: 3,
+                ..BisectOptions::default()
+            },
+            &mut Metrics::default(),
+            &mut io::sink(),
+        )
+        .unwrap();
+        assert_eq!(report.best_commit, "c13");
+        let skips = report
+            .tested_commits
+            .iter()
+            .filter(|tested| tested.verdict == Verdict::Skip)
+            .count();
+        assert_eq!(skips, 1);
+        assert_eq!(tester.tests_run, 3 * report.iterations - 2);
+    }
+
+    #[test]
+    fn json_output() {
+        let (graph, segments, commits) = linear_history(20);
+        let mut tester = FakeCommitTester {
+            commits,
+            first_bad: 13,
+            skipped: vec![],
+            checked_out: 0,
+            tests_run: 0,
+        };
+        let mut out = Vec::new();
+        let report = run_bisect(
+            graph,
+            &segments,
+            &mut tester,
+            &BisectOptions {
+                min_likelihood: 0.9,
+                format: OutputFormat::Json,
+                ..BisectOptions::default()
+            },
+            &mut Metrics::default(),
+            &mut out,
+        )
+        .unwrap();
+        let lines = String::from_utf8(out)
+            .unwrap()
+            .lines()
+            .map(|line| serde_json::from_str::<serde_json::Value>(line).unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(lines.len(), report.iterations + 1);
+        for (i, line) in lines[..report.iterations].iter().enumerate() {
+            assert_eq!(line["type"], "iteration");
+            for key in &[
+                "commit",
+                "verdict",
+                "best_commit",
+                "likelihood",
+                "flakiness",
+                "flakiness_before",
+                "flakiness_after",
+            ] {
+                assert!(line.get(*key).is_some(), "missing key {}", key);
+            }
+            assert_eq!(line["iterations"], i + 1);
+            assert_eq!(line["commit"], report.tested_commits[i].commit.as_str());
+        }
+        let summary = &lines[report.iterations];
+        assert_eq!(summary["type"], "summary");
+        assert_eq!(summary["best_commit"], "c13");
+        assert_eq!(summary["iterations"], report.iterations);
+        assert_eq!(lines[report.iterations - 1]["best_commit"], "c13");
+    }
+
+    #[test]
+    fn quiet_flag() {
+        let args = ["robust-git-bisect", "start", "end", "true"];
+        assert!(!app().get_matches_from(args.iter()).is_present("quiet"));
+        for flag in &["--quiet", "-q"] {
+            let matches =
+                app().get_matches_from(["robust-git-bisect", flag, "start", "end", "true"].iter());
+            assert!(matches.is_present("quiet"));
+        }
+    }
+
+    #[test]
+    fn quiet_output() {
+        for format in &[OutputFormat::Text, OutputFormat::Json] {
+            let (graph, segments, commits) = linear_history(20);
+            let mut tester = FakeCommitTester {
+                commits,
+                first_bad: 13,
+                skipped: vec![],
+                checked_out: 0,
+                tests_run: 0,
+            };
+            let mut out = Vec::new();
+            let report = run_bisect(
+                graph,
+                &segments,
+                &mut tester,
+                &BisectOptions {
+                    min_likelihood: 0.9,
+                    format: *format,
+                    quiet: true,
+                    ..BisectOptions::default()
+                },
+                &mut Metrics::default(),
+                &mut out,
+            )
+            .unwrap();
+            assert!(report.iterations > 1);
+            let out = String::from_utf8(out).unwrap();
+            assert_eq!(out.lines().count(), 1, "output: {}", out);
+            assert!(out.contains("c13"), "output: {}", out);
+        }
+    }
+
+    #[test]
+    fn metrics_accumulate() {
+        let mut metrics = Metrics::default();
+        metrics.record("test", Duration::from_millis(1500));
+        metrics.record("checkout", Duration::from_millis(250));
+        metrics.record("test", Duration::from_millis(500));
+        assert_eq!(metrics.time("report", || 7), 7);
+        metrics.iterations = 2;
+        assert_eq!(metrics.durations["test"], Duration::from_secs(2));
+        assert_eq!(metrics.durations["checkout"], Duration::from_millis(250));
+        assert!(metrics.durations.contains_key("report"));
+        let summary = metrics.summary();
+        assert_eq!(summary.len(), 4);
+        assert_eq!(
+            summary[2],
+            "        test: 2.000 seconds (1.000 per iteration)"
+        );
+        assert_eq!(summary[3], "  iterations: 2");
     }
-    info!("Elapsed time: {} seconds", start.elapsed().as_secs_f64());
-    Ok(())
 }